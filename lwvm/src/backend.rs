@@ -1,4 +1,10 @@
-use crate::{ir::reg::RegisterMap, unit::TranslationUnit};
+use crate::{
+    host::HostFunctions,
+    ir::{reg::RegisterMap, types::BlockLabel},
+    unit::TranslationUnit,
+};
+use std::any::Any;
+use std::fmt::Debug;
 use std::rc::Rc;
 
 pub trait Compiler {
@@ -6,10 +12,220 @@ pub trait Compiler {
         &mut self,
         unit: &TranslationUnit,
     ) -> Result<Rc<dyn Executable<State> + 'a>, String>;
+
+    /// Compiles a unit whose function calls are resolved against `functions`.
+    /// Backends without host function support compile the unit as is.
+    fn compile_unit_with_functions<'a, State: RegisterMap + 'a>(
+        &mut self,
+        unit: &TranslationUnit,
+        _functions: &HostFunctions,
+    ) -> Result<Rc<dyn Executable<State> + 'a>, String> {
+        self.compile_unit(unit)
+    }
+}
+
+/// How execution of a translation unit ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// An Exit operation was executed with the given code
+    Exit(u8),
+    /// An indirect branch left the unit for the given guest address,
+    /// which had no entry in its jump table
+    Jump(u64),
+    /// The fuel given to the run was used up. Execution can be continued
+    /// by passing the resume point to Executable::resume.
+    OutOfFuel(ResumePoint),
+    /// The guest performed an operation that cannot complete
+    Trap(Trap),
+}
+
+/// A fault raised while executing a unit. The operation that raised it has
+/// no effect and is not counted as retired.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trap {
+    /// Division or remainder by zero
+    DivideByZero,
+    /// Signed division or remainder of the most negative value by -1
+    IntegerOverflow,
+    /// A branch targeted a label with no block in the unit
+    UnknownLabel(BlockLabel),
+    /// An operation referenced a register the state does not have
+    InvalidRegister(u8),
+    /// A scratch value was read before being written
+    UninitializedScratch(u8),
+    /// A guest memory access at the given address was out of bounds
+    GuestMemoryFault(u64),
+    /// A guest memory access used a size other than 8, 16, 32, 64 or 128 bits,
+    /// or an atomic access was larger than 64 bits
+    InvalidAccessSize(u8),
+    /// An atomic guest memory access at the given address was not aligned
+    /// to its size
+    MisalignedAtomic(u64),
+    /// A host memory access assumed to be aligned was made to the given
+    /// host address, which is not a multiple of its size
+    MisalignedHostAccess(u64),
+    /// An operand held a value of a different kind than the operation
+    /// expects, such as a vector where an integer was expected, or two
+    /// vectors of different widths
+    TypeMismatch,
+    /// A vector lane index was out of range for the vector
+    InvalidLane(u8),
+    /// A struct field index was out of range for the struct
+    InvalidField(u8),
+    /// A floating point operation was given a value of the given width,
+    /// which is not 32 or 64 bits
+    InvalidFloatWidth(u8),
+    /// A condition was read before any flags were set in the run
+    UndefinedFlags,
+    /// A function call named a function that is not registered
+    UnknownFunction(String),
+    /// A run was resumed from a point produced by an executable that was
+    /// compiled from a different unit, or by a different backend or
+    /// guest memory. Nothing is executed.
+    ForeignResumePoint,
+}
+
+/// Limits how much work a single run may do before returning control.
+/// Operation and block fuel is only checked between blocks, so such a run
+/// always retires at least one block and may overshoot an operation budget
+/// by up to one block. Instruction fuel is checked at instruction markers,
+/// and a run always retires at least one guest instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fuel {
+    Unlimited,
+    /// Stop at the first block boundary after this many operations have retired
+    Ops(u64),
+    /// Stop after this many blocks have retired
+    Blocks(u64),
+    /// Stop at the first instruction marker after this many guest
+    /// instructions have retired. Units without markers run unlimited.
+    Instructions(u64),
+}
+
+impl Fuel {
+    pub(crate) fn exhausted(&self, ops_retired: u64, blocks_retired: u64) -> bool {
+        match *self {
+            Self::Unlimited => false,
+            Self::Ops(ops) => ops_retired >= ops,
+            Self::Blocks(blocks) => blocks_retired >= blocks,
+            Self::Instructions(_) => false,
+        }
+    }
+
+    /// Whether a run that has reached an instruction marker, having
+    /// retired `instructions_retired` guest instructions, should stop there
+    pub(crate) fn exhausted_at_instruction(&self, instructions_retired: u64) -> bool {
+        match *self {
+            Self::Instructions(instructions) => instructions_retired >= instructions.max(1),
+            _ => false,
+        }
+    }
+}
+
+/// The point at which a run stopped, along with whatever state the backend
+/// needs to continue it. Its contents are private to the backend that
+/// produced it, and it must only be resumed on the executable that
+/// produced it.
+#[derive(Debug)]
+pub struct ResumePoint(Box<dyn ResumeState>);
+
+/// Backend specific state held by a ResumePoint. Implemented for every
+/// type that can be cloned, compared and printed.
+pub trait ResumeState: Any + Debug {
+    fn clone_state(&self) -> Box<dyn ResumeState>;
+    fn eq_state(&self, other: &dyn ResumeState) -> bool;
+    fn as_any(&self) -> &dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Any + Debug + Clone + Eq> ResumeState for T {
+    fn clone_state(&self) -> Box<dyn ResumeState> {
+        Box::new(self.clone())
+    }
+
+    fn eq_state(&self, other: &dyn ResumeState) -> bool {
+        other.as_any().downcast_ref::<T>() == Some(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl ResumePoint {
+    /// Wraps a backend's own state for continuing a run
+    pub fn new(state: impl ResumeState) -> Self {
+        Self(Box::new(state))
+    }
+
+    /// Returns the backend state, or the point itself if it holds
+    /// state of another type
+    pub fn downcast<T: ResumeState>(self) -> Result<T, Self> {
+        if !self.0.as_any().is::<T>() {
+            return Err(self);
+        }
+        Ok(*self.0.into_any().downcast::<T>().unwrap())
+    }
+}
+
+impl Clone for ResumePoint {
+    fn clone(&self) -> Self {
+        Self(self.0.clone_state())
+    }
+}
+
+impl PartialEq for ResumePoint {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.eq_state(&*other.0)
+    }
+}
+
+impl Eq for ResumePoint {}
+
+/// Why execution of a translation unit stopped, and how much work it did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitReason {
+    pub status: ExitStatus,
+    /// Label of the last block executed
+    pub block: BlockLabel,
+    /// Number of operations executed during this run, including block terminators
+    pub ops_retired: u64,
+    /// Number of blocks executed during this run up to and including their terminator
+    pub blocks_retired: u64,
+    /// Guest PC of the instruction executing when the run stopped: the
+    /// faulting instruction for a trap, the exiting instruction for an exit,
+    /// and the instruction execution continues in when out of fuel.
+    /// None if no instruction marker was reached.
+    pub guest_pc: Option<u64>,
+    /// Number of guest instructions completed during this run. An instruction
+    /// completes when the next instruction marker is reached or the unit exits.
+    pub instructions_retired: u64,
 }
 
 pub trait Executable<State: RegisterMap> {
-    unsafe fn execute(&self, state: &mut State);
+    /// # Safety
+    /// Executing a translation unit performs any host memory accesses and
+    /// guest memory accesses it contains. The caller must ensure these are
+    /// valid for the given state.
+    unsafe fn execute(&self, state: &mut State) -> ExitReason {
+        self.execute_with_fuel(state, Fuel::Unlimited)
+    }
+
+    /// Runs the unit from its entrypoint until it exits or uses up `fuel`
+    ///
+    /// # Safety
+    /// See [`Executable::execute`]
+    unsafe fn execute_with_fuel(&self, state: &mut State, fuel: Fuel) -> ExitReason;
+
+    /// Continues a run that ran out of fuel from the point it stopped
+    ///
+    /// # Safety
+    /// See [`Executable::execute`]
+    unsafe fn resume(&self, state: &mut State, from: ResumePoint, fuel: Fuel) -> ExitReason;
 }
 
 #[derive(Default)]
@@ -18,7 +234,7 @@ pub struct PlatformDefaultBackend {}
 impl Compiler for PlatformDefaultBackend {
     fn compile_unit<'a, State: RegisterMap + 'a>(
         &mut self,
-        unit: &TranslationUnit,
+        _unit: &TranslationUnit,
     ) -> Result<Rc<dyn Executable<State> + 'a>, String> {
         Err(String::from("No platform backend available"))
     }
@@ -1,20 +1,113 @@
 use crate::ir::ops::Operation;
-use crate::ir::types::{BlockLabel, Comparator, IntImmed, IntType, LValue, RValue};
+use crate::ir::types::{
+    AtomicOp, BlockLabel, Comparator, Condition, Endianness, FlagOp, FloatComparator, FloatImmed,
+    FloatType, IntImmed, IntType, LValue, LaneType, MemoryOrdering, MultSignedness, RValue,
+    StructType, StructTypeImmed, Type, TypeImmed, VectorImmed, VectorType,
+};
+use std::collections::BTreeMap;
 
 pub(crate) trait InstructionStream {
     fn to_vec(&self) -> &Vec<Operation>;
 
     fn validate(&self) -> bool {
         if let Some(op) = self.to_vec().last() {
-            match op {
-                Operation::Branch(_, _, _) => true,
-                Operation::Exit(_) => true,
-                _ => false,
-            }
+            matches!(
+                op,
+                Operation::Branch(_, _, _)
+                    | Operation::BranchCondition(_, _, _)
+                    | Operation::CompareBranch(_, _, _, _, _)
+                    | Operation::IndirectBranch(_, _)
+                    | Operation::Exit(_)
+            )
         } else {
             false
         }
     }
+
+    /// Checks struct operations whose struct types are known when the block
+    /// is built. A scratch value only has a known type if a struct operation
+    /// earlier in the block wrote it. Anything else is checked at run time.
+    fn check_struct_types(&self) -> Result<(), String> {
+        let mut known: BTreeMap<u8, StructType> = BTreeMap::new();
+
+        for op in self.to_vec() {
+            if let Operation::GuestReadStruct(LValue::Register(_), ..)
+            | Operation::InsertField(LValue::Register(_), ..) = op
+            {
+                return Err(String::from(
+                    "Structs can only be written to scratch values",
+                ));
+            }
+
+            let struct_type = |value: &RValue<StructTypeImmed>| match value {
+                RValue::Immediate(s) => Some(s.get_type().clone()),
+                RValue::LValue(LValue::Scratch(s)) => known.get(s).cloned(),
+                RValue::LValue(LValue::Register(_)) => None,
+            };
+            let field_type = |ty: &StructType, idx: u8| {
+                ty.fields()
+                    .get(idx as usize)
+                    .cloned()
+                    .ok_or(format!("Struct has no field {}", idx))
+            };
+
+            let written = match op {
+                Operation::GuestReadStruct(_, _, ty) => Some(ty.clone()),
+                Operation::ExtractField(dest, value, idx) => match struct_type(value) {
+                    Some(ty) => match (field_type(&ty, *idx)?, dest) {
+                        (Type::Struct(_), LValue::Register(_)) => {
+                            return Err(String::from(
+                                "Struct fields can only be extracted to scratch values",
+                            ))
+                        }
+                        (Type::Struct(s), LValue::Scratch(_)) => Some(s),
+                        (Type::Integer(_), _) => None,
+                    },
+                    None => None,
+                },
+                Operation::InsertField(_, value, field, idx) => {
+                    let ty = struct_type(value);
+                    if let Some(ty) = &ty {
+                        let compatible = match (field_type(ty, *idx)?, field) {
+                            (Type::Integer(_), RValue::Immediate(v)) => {
+                                matches!(v, TypeImmed::Integer(_))
+                            }
+                            (Type::Struct(s), RValue::Immediate(v)) => {
+                                v.get_type() == Type::Struct(s)
+                            }
+                            (Type::Integer(_), RValue::LValue(LValue::Scratch(f))) => {
+                                !known.contains_key(f)
+                            }
+                            (Type::Struct(s), RValue::LValue(LValue::Scratch(f))) => {
+                                !matches!(known.get(f), Some(f) if *f != s)
+                            }
+                            (Type::Integer(_), RValue::LValue(LValue::Register(_))) => true,
+                            (Type::Struct(_), RValue::LValue(LValue::Register(_))) => false,
+                        };
+                        if !compatible {
+                            return Err(format!(
+                                "Value does not match the type of struct field {}",
+                                idx
+                            ));
+                        }
+                    }
+                    ty
+                }
+                _ => None,
+            };
+
+            for dest in op.dests() {
+                if let LValue::Scratch(s) = dest {
+                    match &written {
+                        Some(ty) => known.insert(s, ty.clone()),
+                        None => known.remove(&s),
+                    };
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -45,8 +138,8 @@ macro_rules! op_lv1_rv1 {
     ($name:ident, $op:ident) => {
         pub fn $name(
             &mut self,
-            dest: (impl Into<LValue> + Clone),
-            arg1: (impl Into<RValue<IntImmed>> + Clone),
+            dest: impl Into<LValue> + Clone,
+            arg1: impl Into<RValue<IntImmed>> + Clone,
         ) {
             self.ops.push(Operation::$op(
                 Into::<LValue>::into(dest),
@@ -56,121 +149,405 @@ macro_rules! op_lv1_rv1 {
     };
 }
 
-macro_rules! op_lv1_rv1_u8 {
+macro_rules! op_lv1_rv1_ty {
     ($name:ident, $op:ident) => {
         pub fn $name(
             &mut self,
-            dest: (impl Into<LValue> + Clone),
-            arg1: (impl Into<RValue<IntImmed>> + Clone),
-            arg2: u8,
+            dest: impl Into<LValue> + Clone,
+            arg1: impl Into<RValue<IntImmed>> + Clone,
+            ty: IntType,
         ) {
             self.ops.push(Operation::$op(
                 Into::<LValue>::into(dest),
                 Into::<RValue<IntImmed>>::into(arg1),
-                arg2,
+                ty,
             ));
         }
     };
 }
 
-macro_rules! op_lv1_rv1_ty {
+macro_rules! op_lv1_rv2 {
     ($name:ident, $op:ident) => {
         pub fn $name(
             &mut self,
-            dest: (impl Into<LValue> + Clone),
-            arg1: (impl Into<RValue<IntImmed>> + Clone),
-            ty: IntType,
+            dest: impl Into<LValue> + Clone,
+            arg1: impl Into<RValue<IntImmed>> + Clone,
+            arg2: impl Into<RValue<IntImmed>> + Clone,
         ) {
             self.ops.push(Operation::$op(
                 Into::<LValue>::into(dest),
                 Into::<RValue<IntImmed>>::into(arg1),
-                ty,
+                Into::<RValue<IntImmed>>::into(arg2),
             ));
         }
     };
 }
 
-macro_rules! op_lv1_rv2 {
+macro_rules! op_lv1_rv2_signed {
     ($name:ident, $op:ident) => {
         pub fn $name(
             &mut self,
-            dest: (impl Into<LValue> + Clone),
-            arg1: (impl Into<RValue<IntImmed>> + Clone),
-            arg2: (impl Into<RValue<IntImmed>> + Clone),
+            dest: impl Into<LValue> + Clone,
+            arg1: impl Into<RValue<IntImmed>> + Clone,
+            arg2: impl Into<RValue<IntImmed>> + Clone,
+            signed: bool,
         ) {
             self.ops.push(Operation::$op(
                 Into::<LValue>::into(dest),
                 Into::<RValue<IntImmed>>::into(arg1),
                 Into::<RValue<IntImmed>>::into(arg2),
+                signed,
             ));
         }
     };
 }
 
-macro_rules! op_lv0_rv2_u8 {
+/// Writes the result to `dest` and whether it overflowed to `overflow`
+/// as a Bool. Unsigned operations report the carry or borrow out, and
+/// signed operations report signed overflow.
+macro_rules! op_lv2_rv2_signed {
     ($name:ident, $op:ident) => {
         pub fn $name(
             &mut self,
-            arg1: (impl Into<RValue<IntImmed>> + Clone),
-            arg2: (impl Into<RValue<IntImmed>> + Clone),
-            arg3: u8,
+            dest: impl Into<LValue> + Clone,
+            overflow: impl Into<LValue> + Clone,
+            arg1: impl Into<RValue<IntImmed>> + Clone,
+            arg2: impl Into<RValue<IntImmed>> + Clone,
+            signed: bool,
         ) {
             self.ops.push(Operation::$op(
+                Into::<LValue>::into(dest),
+                Into::<LValue>::into(overflow),
                 Into::<RValue<IntImmed>>::into(arg1),
                 Into::<RValue<IntImmed>>::into(arg2),
-                arg3,
+                signed,
             ));
         }
     };
 }
 
-macro_rules! op_lv1_rv2_signed {
+/// Same as op_lv2_rv2_signed, with a carry or borrow in which is 1 if
+/// it is non-zero
+macro_rules! op_lv2_rv3_signed {
     ($name:ident, $op:ident) => {
         pub fn $name(
             &mut self,
-            dest: (impl Into<LValue> + Clone),
-            arg1: (impl Into<RValue<IntImmed>> + Clone),
-            arg2: (impl Into<RValue<IntImmed>> + Clone),
+            dest: impl Into<LValue> + Clone,
+            overflow: impl Into<LValue> + Clone,
+            arg1: impl Into<RValue<IntImmed>> + Clone,
+            arg2: impl Into<RValue<IntImmed>> + Clone,
+            carry: impl Into<RValue<IntImmed>> + Clone,
             signed: bool,
         ) {
             self.ops.push(Operation::$op(
                 Into::<LValue>::into(dest),
+                Into::<LValue>::into(overflow),
                 Into::<RValue<IntImmed>>::into(arg1),
                 Into::<RValue<IntImmed>>::into(arg2),
+                Into::<RValue<IntImmed>>::into(carry),
                 signed,
             ));
         }
     };
 }
 
+/// Rotates through a carry, which is 1 if it is non-zero, writing the
+/// carry out to `carry_out` as a Bool
+macro_rules! op_lv2_rv3 {
+    ($name:ident, $op:ident) => {
+        pub fn $name(
+            &mut self,
+            dest: impl Into<LValue> + Clone,
+            carry_out: impl Into<LValue> + Clone,
+            arg1: impl Into<RValue<IntImmed>> + Clone,
+            arg2: impl Into<RValue<IntImmed>> + Clone,
+            carry: impl Into<RValue<IntImmed>> + Clone,
+        ) {
+            self.ops.push(Operation::$op(
+                Into::<LValue>::into(dest),
+                Into::<LValue>::into(carry_out),
+                Into::<RValue<IntImmed>>::into(arg1),
+                Into::<RValue<IntImmed>>::into(arg2),
+                Into::<RValue<IntImmed>>::into(carry),
+            ));
+        }
+    };
+}
+
+macro_rules! op_lv1_frv1 {
+    ($name:ident, $op:ident) => {
+        pub fn $name(
+            &mut self,
+            dest: impl Into<LValue> + Clone,
+            arg1: impl Into<RValue<FloatImmed>> + Clone,
+        ) {
+            self.ops.push(Operation::$op(
+                Into::<LValue>::into(dest),
+                Into::<RValue<FloatImmed>>::into(arg1),
+            ));
+        }
+    };
+}
+
+macro_rules! op_lv1_frv2 {
+    ($name:ident, $op:ident) => {
+        pub fn $name(
+            &mut self,
+            dest: impl Into<LValue> + Clone,
+            arg1: impl Into<RValue<FloatImmed>> + Clone,
+            arg2: impl Into<RValue<FloatImmed>> + Clone,
+        ) {
+            self.ops.push(Operation::$op(
+                Into::<LValue>::into(dest),
+                Into::<RValue<FloatImmed>>::into(arg1),
+                Into::<RValue<FloatImmed>>::into(arg2),
+            ));
+        }
+    };
+}
+
+macro_rules! op_lv1_vrv2_lane {
+    ($name:ident, $op:ident) => {
+        pub fn $name(
+            &mut self,
+            dest: impl Into<LValue> + Clone,
+            arg1: impl Into<RValue<VectorImmed>> + Clone,
+            arg2: impl Into<RValue<VectorImmed>> + Clone,
+            lane: LaneType,
+        ) {
+            self.ops.push(Operation::$op(
+                Into::<LValue>::into(dest),
+                Into::<RValue<VectorImmed>>::into(arg1),
+                Into::<RValue<VectorImmed>>::into(arg2),
+                lane,
+            ));
+        }
+    };
+}
+
+macro_rules! op_lv1_vrv2_lane_signed {
+    ($name:ident, $op:ident) => {
+        pub fn $name(
+            &mut self,
+            dest: impl Into<LValue> + Clone,
+            arg1: impl Into<RValue<VectorImmed>> + Clone,
+            arg2: impl Into<RValue<VectorImmed>> + Clone,
+            lane: LaneType,
+            signed: bool,
+        ) {
+            self.ops.push(Operation::$op(
+                Into::<LValue>::into(dest),
+                Into::<RValue<VectorImmed>>::into(arg1),
+                Into::<RValue<VectorImmed>>::into(arg2),
+                lane,
+                signed,
+            ));
+        }
+    };
+}
+
+/// Operations that record their flags if `$flags` is set. The flags come
+/// from the same operand values as the result, even if `dest` is one of
+/// the operands.
+macro_rules! op_lv1_rv2_flags {
+    ($name:ident, $op:ident, $flags:literal) => {
+        pub fn $name(
+            &mut self,
+            dest: impl Into<LValue> + Clone,
+            arg1: impl Into<RValue<IntImmed>> + Clone,
+            arg2: impl Into<RValue<IntImmed>> + Clone,
+        ) {
+            self.ops.push(Operation::$op(
+                Into::<LValue>::into(dest),
+                Into::<RValue<IntImmed>>::into(arg1),
+                Into::<RValue<IntImmed>>::into(arg2),
+                $flags,
+            ));
+        }
+    };
+}
+
+macro_rules! op_lv1_rv2_signed_flags {
+    ($name:ident, $op:ident, $flags:literal) => {
+        pub fn $name(
+            &mut self,
+            dest: impl Into<LValue> + Clone,
+            arg1: impl Into<RValue<IntImmed>> + Clone,
+            arg2: impl Into<RValue<IntImmed>> + Clone,
+            signed: bool,
+        ) {
+            self.ops.push(Operation::$op(
+                Into::<LValue>::into(dest),
+                Into::<RValue<IntImmed>>::into(arg1),
+                Into::<RValue<IntImmed>>::into(arg2),
+                signed,
+                $flags,
+            ));
+        }
+    };
+}
+
 impl BasicBlockBuilder {
-    op_lv1_rv2_signed!(add, Add);
-    op_lv1_rv2_signed!(sub, Sub);
+    op_lv1_rv2_signed_flags!(add, Add, false);
+    op_lv1_rv2_signed_flags!(sub, Sub, false);
+    op_lv1_rv2_signed_flags!(add_with_flags, Add, true);
+    op_lv1_rv2_signed_flags!(sub_with_flags, Sub, true);
 
     op_lv1_rv2_signed!(mult, Mult);
     op_lv1_rv2_signed!(div, Div);
     op_lv1_rv2_signed!(rem, Rem);
 
+    op_lv2_rv3_signed!(add_carry, AddCarry);
+    op_lv2_rv3_signed!(sub_borrow, SubBorrow);
+    op_lv2_rv2_signed!(add_overflow, AddOverflow);
+    op_lv2_rv2_signed!(sub_overflow, SubOverflow);
+    op_lv2_rv2_signed!(mult_overflow, MultOverflow);
+
+    /// Writes the low and high halves of the full product to `lo` and `hi`,
+    /// each at the width of the wider operand. The high half is written
+    /// last, so it is kept if both destinations are the same.
+    pub fn mult_wide(
+        &mut self,
+        lo: impl Into<LValue> + Clone,
+        hi: impl Into<LValue> + Clone,
+        arg1: impl Into<RValue<IntImmed>> + Clone,
+        arg2: impl Into<RValue<IntImmed>> + Clone,
+        signedness: MultSignedness,
+    ) {
+        self.ops.push(Operation::MultWide(
+            Into::<LValue>::into(lo),
+            Into::<LValue>::into(hi),
+            Into::<RValue<IntImmed>>::into(arg1),
+            Into::<RValue<IntImmed>>::into(arg2),
+            signedness,
+        ));
+    }
+
+    /// Writes the high half of the full product, at the width of the wider
+    /// operand
+    pub fn mult_high(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        arg1: impl Into<RValue<IntImmed>> + Clone,
+        arg2: impl Into<RValue<IntImmed>> + Clone,
+        signedness: MultSignedness,
+    ) {
+        self.ops.push(Operation::MultHigh(
+            Into::<LValue>::into(dest),
+            Into::<RValue<IntImmed>>::into(arg1),
+            Into::<RValue<IntImmed>>::into(arg2),
+            signedness,
+        ));
+    }
+
     op_lv1_rv2!(shift_left, LShift);
     op_lv1_rv2_signed!(shift_right, RShift);
 
     op_lv1_rv1_ty!(sign_extend, SignExtend);
     op_lv1_rv1_ty!(zero_extend, ZeroExtend);
 
-    op_lv1_rv2!(and, And);
-    op_lv1_rv2!(or, Or);
-    op_lv1_rv2!(xor, Xor);
+    op_lv1_rv2!(rotate_left, RotateLeft);
+    op_lv1_rv2!(rotate_right, RotateRight);
+    op_lv2_rv3!(rotate_left_carry, RotateLeftCarry);
+    op_lv2_rv3!(rotate_right_carry, RotateRightCarry);
+    op_lv1_rv1!(count_leading_zeros, CountLeadingZeros);
+    op_lv1_rv1!(count_trailing_zeros, CountTrailingZeros);
+    op_lv1_rv1!(pop_count, PopCount);
+    op_lv1_rv1!(byte_swap, ByteSwap);
+    op_lv1_rv1!(bit_reverse, BitReverse);
+
+    op_lv1_rv2_flags!(and, And, false);
+    op_lv1_rv2_flags!(or, Or, false);
+    op_lv1_rv2_flags!(xor, Xor, false);
     op_lv1_rv1!(not, Not);
+    op_lv1_rv2_flags!(and_with_flags, And, true);
+    op_lv1_rv2_flags!(or_with_flags, Or, true);
+    op_lv1_rv2_flags!(xor_with_flags, Xor, true);
+
+    op_lv1_frv2!(float_add, FAdd);
+    op_lv1_frv2!(float_sub, FSub);
+    op_lv1_frv2!(float_mult, FMult);
+    op_lv1_frv2!(float_div, FDiv);
+    op_lv1_frv1!(float_sqrt, FSqrt);
+
+    op_lv1_frv1!(float_to_bits, FloatToBits);
+    op_lv1_rv1!(bits_to_float, BitsToFloat);
+
+    op_lv1_vrv2_lane!(vector_add, VAdd);
+    op_lv1_vrv2_lane!(vector_sub, VSub);
+    op_lv1_vrv2_lane!(vector_mult, VMult);
+    op_lv1_vrv2_lane_signed!(vector_min, VMin);
+    op_lv1_vrv2_lane_signed!(vector_max, VMax);
+
+    /// Reads a `size` bit value from guest memory in the unit's byte order
+    pub fn guest_mem_read(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        addr: impl Into<RValue<IntImmed>> + Clone,
+        size: u8,
+    ) {
+        self.ops.push(Operation::GuestReadMem(
+            Into::<LValue>::into(dest),
+            Into::<RValue<IntImmed>>::into(addr),
+            size,
+            None,
+        ));
+    }
+
+    /// Writes the low `size` bits of a value to guest memory in the unit's
+    /// byte order
+    pub fn guest_mem_write(
+        &mut self,
+        addr: impl Into<RValue<IntImmed>> + Clone,
+        value: impl Into<RValue<IntImmed>> + Clone,
+        size: u8,
+    ) {
+        self.ops.push(Operation::GuestWriteMem(
+            Into::<RValue<IntImmed>>::into(addr),
+            Into::<RValue<IntImmed>>::into(value),
+            size,
+            None,
+        ));
+    }
 
-    op_lv1_rv1_u8!(guest_mem_read, GuestReadMem);
-    op_lv0_rv2_u8!(guest_mem_write, GuestWriteMem);
+    /// Same as guest_mem_read, with an explicit byte order
+    pub fn guest_mem_read_endian(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        addr: impl Into<RValue<IntImmed>> + Clone,
+        size: u8,
+        endianness: Endianness,
+    ) {
+        self.ops.push(Operation::GuestReadMem(
+            Into::<LValue>::into(dest),
+            Into::<RValue<IntImmed>>::into(addr),
+            size,
+            Some(endianness),
+        ));
+    }
+
+    /// Same as guest_mem_write, with an explicit byte order
+    pub fn guest_mem_write_endian(
+        &mut self,
+        addr: impl Into<RValue<IntImmed>> + Clone,
+        value: impl Into<RValue<IntImmed>> + Clone,
+        size: u8,
+        endianness: Endianness,
+    ) {
+        self.ops.push(Operation::GuestWriteMem(
+            Into::<RValue<IntImmed>>::into(addr),
+            Into::<RValue<IntImmed>>::into(value),
+            size,
+            Some(endianness),
+        ));
+    }
 
     pub fn int_cmp(
         &mut self,
-        dest: (impl Into<LValue> + Clone),
+        dest: impl Into<LValue> + Clone,
         cmp: Comparator,
-        arg1: (impl Into<RValue<IntImmed>> + Clone),
-        arg2: (impl Into<RValue<IntImmed>> + Clone),
+        arg1: impl Into<RValue<IntImmed>> + Clone,
+        arg2: impl Into<RValue<IntImmed>> + Clone,
     ) {
         self.ops.push(Operation::ICmp(
             Into::<LValue>::into(dest),
@@ -180,12 +557,183 @@ impl BasicBlockBuilder {
         ));
     }
 
+    pub fn float_cmp(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        cmp: FloatComparator,
+        arg1: impl Into<RValue<FloatImmed>> + Clone,
+        arg2: impl Into<RValue<FloatImmed>> + Clone,
+    ) {
+        self.ops.push(Operation::FCmp(
+            Into::<LValue>::into(dest),
+            cmp,
+            Into::<RValue<FloatImmed>>::into(arg1),
+            Into::<RValue<FloatImmed>>::into(arg2),
+        ));
+    }
+
+    /// Converts an integer to the nearest float of type `ty`
+    pub fn int_to_float(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        arg1: impl Into<RValue<IntImmed>> + Clone,
+        ty: FloatType,
+        signed: bool,
+    ) {
+        self.ops.push(Operation::IntToFloat(
+            Into::<LValue>::into(dest),
+            Into::<RValue<IntImmed>>::into(arg1),
+            ty,
+            signed,
+        ));
+    }
+
+    /// Converts a float to an integer of type `ty`, rounding towards zero.
+    /// Values out of range saturate, and NaN converts to zero.
+    pub fn float_to_int(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        arg1: impl Into<RValue<FloatImmed>> + Clone,
+        ty: IntType,
+        signed: bool,
+    ) {
+        self.ops.push(Operation::FloatToInt(
+            Into::<LValue>::into(dest),
+            Into::<RValue<FloatImmed>>::into(arg1),
+            ty,
+            signed,
+        ));
+    }
+
+    /// Sets each lane of the result to all ones where the comparison
+    /// holds for the corresponding lanes of the operands, and zero elsewhere
+    pub fn vector_cmp(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        cmp: Comparator,
+        arg1: impl Into<RValue<VectorImmed>> + Clone,
+        arg2: impl Into<RValue<VectorImmed>> + Clone,
+        lane: LaneType,
+    ) {
+        self.ops.push(Operation::VCmp(
+            Into::<LValue>::into(dest),
+            cmp,
+            Into::<RValue<VectorImmed>>::into(arg1),
+            Into::<RValue<VectorImmed>>::into(arg2),
+            lane,
+        ));
+    }
+
+    /// Shifts every lane left by the same amount
+    pub fn vector_shift_left(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        arg1: impl Into<RValue<VectorImmed>> + Clone,
+        amount: impl Into<RValue<IntImmed>> + Clone,
+        lane: LaneType,
+    ) {
+        self.ops.push(Operation::VShiftLeft(
+            Into::<LValue>::into(dest),
+            Into::<RValue<VectorImmed>>::into(arg1),
+            Into::<RValue<IntImmed>>::into(amount),
+            lane,
+        ));
+    }
+
+    /// Shifts every lane right by the same amount
+    pub fn vector_shift_right(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        arg1: impl Into<RValue<VectorImmed>> + Clone,
+        amount: impl Into<RValue<IntImmed>> + Clone,
+        lane: LaneType,
+        signed: bool,
+    ) {
+        self.ops.push(Operation::VShiftRight(
+            Into::<LValue>::into(dest),
+            Into::<RValue<VectorImmed>>::into(arg1),
+            Into::<RValue<IntImmed>>::into(amount),
+            lane,
+            signed,
+        ));
+    }
+
+    /// Lane `i` of the result is lane `indices[i]` of the lanes of `arg1`
+    /// followed by the lanes of `arg2`. Indices past the last lane of `arg2`
+    /// produce a zero lane. There must be one index per lane of the result.
+    pub fn vector_shuffle(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        arg1: impl Into<RValue<VectorImmed>> + Clone,
+        arg2: impl Into<RValue<VectorImmed>> + Clone,
+        indices: &[u8],
+        lane: LaneType,
+    ) {
+        self.ops.push(Operation::VShuffle(
+            Into::<LValue>::into(dest),
+            Into::<RValue<VectorImmed>>::into(arg1),
+            Into::<RValue<VectorImmed>>::into(arg2),
+            indices.to_vec(),
+            lane,
+        ));
+    }
+
+    /// Copies `vector` with lane `idx` replaced by the low bits of `value`
+    pub fn vector_insert_lane(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        vector: impl Into<RValue<VectorImmed>> + Clone,
+        value: impl Into<RValue<IntImmed>> + Clone,
+        idx: u8,
+        lane: LaneType,
+    ) {
+        self.ops.push(Operation::VInsertLane(
+            Into::<LValue>::into(dest),
+            Into::<RValue<VectorImmed>>::into(vector),
+            Into::<RValue<IntImmed>>::into(value),
+            idx,
+            lane,
+        ));
+    }
+
+    /// Reads lane `idx` as an integer of the lane type
+    pub fn vector_extract_lane(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        vector: impl Into<RValue<VectorImmed>> + Clone,
+        idx: u8,
+        lane: LaneType,
+    ) {
+        self.ops.push(Operation::VExtractLane(
+            Into::<LValue>::into(dest),
+            Into::<RValue<VectorImmed>>::into(vector),
+            idx,
+            lane,
+        ));
+    }
+
+    /// Creates a vector with every lane set to the low bits of `value`
+    pub fn vector_splat(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        value: impl Into<RValue<IntImmed>> + Clone,
+        lane: LaneType,
+        ty: VectorType,
+    ) {
+        self.ops.push(Operation::VSplat(
+            Into::<LValue>::into(dest),
+            Into::<RValue<IntImmed>>::into(value),
+            lane,
+            ty,
+        ));
+    }
+
     pub fn select(
         &mut self,
-        cond: (impl Into<RValue<IntImmed>> + Clone),
-        dest: (impl Into<LValue> + Clone),
-        arg1: (impl Into<RValue<IntImmed>> + Clone),
-        arg2: (impl Into<RValue<IntImmed>> + Clone),
+        cond: impl Into<RValue<IntImmed>> + Clone,
+        dest: impl Into<LValue> + Clone,
+        arg1: impl Into<RValue<IntImmed>> + Clone,
+        arg2: impl Into<RValue<IntImmed>> + Clone,
     ) {
         self.ops.push(Operation::Select(
             Into::<RValue<IntImmed>>::into(cond),
@@ -195,9 +743,64 @@ impl BasicBlockBuilder {
         ));
     }
 
+    /// Records the flags of `op` applied to two values, upcast to the same
+    /// width the way the operation itself upcasts them, without writing a
+    /// result. Nothing is computed until a condition is read. To record the
+    /// flags of an operation that writes a result, use its `_with_flags`
+    /// form.
+    pub fn set_flags(
+        &mut self,
+        op: FlagOp,
+        arg1: impl Into<RValue<IntImmed>> + Clone,
+        arg2: impl Into<RValue<IntImmed>> + Clone,
+        signed: bool,
+    ) {
+        self.ops.push(Operation::SetFlags(
+            op,
+            Into::<RValue<IntImmed>>::into(arg1),
+            Into::<RValue<IntImmed>>::into(arg2),
+            signed,
+        ));
+    }
+
+    /// Writes whether `cond` holds as a Bool
+    pub fn read_condition(&mut self, dest: impl Into<LValue> + Clone, cond: Condition) {
+        self.ops
+            .push(Operation::ReadCondition(Into::<LValue>::into(dest), cond));
+    }
+
+    pub fn select_condition(
+        &mut self,
+        cond: Condition,
+        dest: impl Into<LValue> + Clone,
+        arg1: impl Into<RValue<IntImmed>> + Clone,
+        arg2: impl Into<RValue<IntImmed>> + Clone,
+    ) {
+        self.ops.push(Operation::SelectCondition(
+            cond,
+            Into::<LValue>::into(dest),
+            Into::<RValue<IntImmed>>::into(arg1),
+            Into::<RValue<IntImmed>>::into(arg2),
+        ));
+    }
+
+    pub fn finish_branch_condition(
+        mut self,
+        cond: Condition,
+        label_taken: BlockLabel,
+        label_not_taken: BlockLabel,
+    ) -> BasicBlock {
+        self.ops.push(Operation::BranchCondition(
+            cond,
+            label_taken,
+            label_not_taken,
+        ));
+        BasicBlock { ops: self.ops }
+    }
+
     pub fn finish_branch(
         mut self,
-        cond: (impl Into<RValue<IntImmed>> + Clone),
+        cond: impl Into<RValue<IntImmed>> + Clone,
         label_taken: BlockLabel,
         label_not_taken: BlockLabel,
     ) -> BasicBlock {
@@ -209,33 +812,275 @@ impl BasicBlockBuilder {
         BasicBlock { ops: self.ops }
     }
 
+    /// Compares two values like int_cmp, and branches to `label_taken`
+    /// if the comparison holds
+    pub fn finish_compare_branch(
+        mut self,
+        cmp: Comparator,
+        arg1: impl Into<RValue<IntImmed>> + Clone,
+        arg2: impl Into<RValue<IntImmed>> + Clone,
+        label_taken: BlockLabel,
+        label_not_taken: BlockLabel,
+    ) -> BasicBlock {
+        self.ops.push(Operation::CompareBranch(
+            cmp,
+            Into::<RValue<IntImmed>>::into(arg1),
+            Into::<RValue<IntImmed>>::into(arg2),
+            label_taken,
+            label_not_taken,
+        ));
+        BasicBlock { ops: self.ops }
+    }
+
+    /// Branches on a computed guest address. If `table` maps the address to
+    /// a block, execution continues there, otherwise the unit exits with the
+    /// address. Where the table has several entries for an address, the
+    /// first is used.
+    pub fn finish_indirect_branch(
+        mut self,
+        target: impl Into<RValue<IntImmed>> + Clone,
+        table: Vec<(u64, BlockLabel)>,
+    ) -> BasicBlock {
+        self.ops.push(Operation::IndirectBranch(
+            Into::<RValue<IntImmed>>::into(target),
+            table,
+        ));
+        BasicBlock { ops: self.ops }
+    }
+
     pub fn finish_exit(mut self, code: u8) -> BasicBlock {
         self.ops.push(Operation::Exit(code));
         BasicBlock { ops: self.ops }
     }
 
+    /// Marks the start of the guest instruction at `guest_pc`, which is
+    /// `len` bytes long. The operations up to the next marker, or the
+    /// end of the unit, implement that instruction.
+    pub fn instruction(&mut self, guest_pc: u64, len: u8) {
+        self.ops.push(Operation::Instruction(guest_pc, len));
+    }
+
+    /// Reads a value of type `ty` in native byte order from a host address.
+    /// If `aligned` is set, an address that is not a multiple of the
+    /// width traps instead of being read. Volatile reads are never elided
+    /// or merged, and unaligned ones read a byte at a time in address order.
+    ///
+    /// # Safety
+    /// The address must be valid for reads of `ty`'s width
+    /// whenever the block is executed.
     pub unsafe fn host_mem_read(
         &mut self,
-        dest: (impl Into<LValue> + Clone),
-        arg1: (impl Into<RValue<IntImmed>> + Clone),
+        dest: impl Into<LValue> + Clone,
+        addr: impl Into<RValue<IntImmed>> + Clone,
+        ty: IntType,
+        aligned: bool,
+        volatile: bool,
     ) {
         self.ops.push(Operation::HostReadMem(
             Into::<LValue>::into(dest),
-            Into::<RValue<IntImmed>>::into(arg1),
+            Into::<RValue<IntImmed>>::into(addr),
+            ty,
+            aligned,
+            volatile,
         ));
     }
 
+    /// Writes the low bits of a value as type `ty` in native byte order to
+    /// a host address, with the same alignment and volatile semantics as
+    /// host_mem_read
+    ///
+    /// # Safety
+    /// The address must be valid for writes of `ty`'s width
+    /// whenever the block is executed.
     pub unsafe fn host_mem_write(
         &mut self,
-        arg1: (impl Into<RValue<IntImmed>> + Clone),
-        arg2: (impl Into<RValue<IntImmed>> + Clone),
+        addr: impl Into<RValue<IntImmed>> + Clone,
+        value: impl Into<RValue<IntImmed>> + Clone,
+        ty: IntType,
+        aligned: bool,
+        volatile: bool,
     ) {
         self.ops.push(Operation::HostWriteMem(
-            Into::<RValue<IntImmed>>::into(arg1),
-            Into::<RValue<IntImmed>>::into(arg2),
+            Into::<RValue<IntImmed>>::into(addr),
+            Into::<RValue<IntImmed>>::into(value),
+            ty,
+            aligned,
+            volatile,
+        ));
+    }
+
+    /// Reads a struct of type `ty` from guest memory into a scratch value
+    pub fn guest_read_struct(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        addr: impl Into<RValue<IntImmed>> + Clone,
+        ty: StructType,
+    ) {
+        self.ops.push(Operation::GuestReadStruct(
+            Into::<LValue>::into(dest),
+            Into::<RValue<IntImmed>>::into(addr),
+            ty,
+        ));
+    }
+
+    pub fn guest_write_struct(
+        &mut self,
+        addr: impl Into<RValue<IntImmed>> + Clone,
+        value: impl Into<RValue<StructTypeImmed>> + Clone,
+    ) {
+        self.ops.push(Operation::GuestWriteStruct(
+            Into::<RValue<IntImmed>>::into(addr),
+            Into::<RValue<StructTypeImmed>>::into(value),
+        ));
+    }
+
+    /// Applies `op` with `value` to the `size` bit value at guest address
+    /// `addr` in a single atomic step, writing the previous value to `dest`.
+    /// Atomic accesses must be at most 64 bits and naturally aligned.
+    pub fn guest_atomic_rmw(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        op: AtomicOp,
+        addr: impl Into<RValue<IntImmed>> + Clone,
+        value: impl Into<RValue<IntImmed>> + Clone,
+        size: u8,
+        ordering: MemoryOrdering,
+    ) {
+        self.ops.push(Operation::GuestAtomicRmw(
+            Into::<LValue>::into(dest),
+            op,
+            Into::<RValue<IntImmed>>::into(addr),
+            Into::<RValue<IntImmed>>::into(value),
+            size,
+            ordering,
         ));
     }
 
-    // TODO
-    //pub unsafe fn fn_call(&mut self, ptr: (impl Into<RValue<IntImmed>> + Clone)) {}
+    /// Atomically replaces the `size` bit value at guest address `addr` with
+    /// `new` if it equals `expected`. The value it held is written to `dest`,
+    /// so the exchange succeeded if that equals `expected`.
+    pub fn guest_compare_exchange(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        addr: impl Into<RValue<IntImmed>> + Clone,
+        expected: impl Into<RValue<IntImmed>> + Clone,
+        new: impl Into<RValue<IntImmed>> + Clone,
+        size: u8,
+        ordering: MemoryOrdering,
+    ) {
+        self.ops.push(Operation::GuestCompareExchange(
+            Into::<LValue>::into(dest),
+            Into::<RValue<IntImmed>>::into(addr),
+            Into::<RValue<IntImmed>>::into(expected),
+            Into::<RValue<IntImmed>>::into(new),
+            size,
+            ordering,
+        ));
+    }
+
+    /// Atomically reads the `size` bit value at guest address `addr` and
+    /// reserves the location for the next store-conditional. A reservation
+    /// is kept when a run out of fuel is resumed, but not after the unit
+    /// exits.
+    pub fn guest_load_linked(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        addr: impl Into<RValue<IntImmed>> + Clone,
+        size: u8,
+        ordering: MemoryOrdering,
+    ) {
+        self.ops.push(Operation::GuestLoadLinked(
+            Into::<LValue>::into(dest),
+            Into::<RValue<IntImmed>>::into(addr),
+            size,
+            ordering,
+        ));
+    }
+
+    /// Writes `value` to guest address `addr` if the last load-linked
+    /// reserved that location with the same size, and it still holds the
+    /// value that was loaded. Whether the store happened is written to
+    /// `status` as a Bool. The reservation is cleared either way.
+    pub fn guest_store_conditional(
+        &mut self,
+        status: impl Into<LValue> + Clone,
+        addr: impl Into<RValue<IntImmed>> + Clone,
+        value: impl Into<RValue<IntImmed>> + Clone,
+        size: u8,
+        ordering: MemoryOrdering,
+    ) {
+        self.ops.push(Operation::GuestStoreConditional(
+            Into::<LValue>::into(status),
+            Into::<RValue<IntImmed>>::into(addr),
+            Into::<RValue<IntImmed>>::into(value),
+            size,
+            ordering,
+        ));
+    }
+
+    /// Orders guest memory accesses before the fence against those after it
+    pub fn fence(&mut self, ordering: MemoryOrdering) {
+        self.ops.push(Operation::Fence(ordering));
+    }
+
+    /// Reads field `idx` of a struct. Struct fields can only be
+    /// extracted to scratch values.
+    pub fn extract_field(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        value: impl Into<RValue<StructTypeImmed>> + Clone,
+        idx: u8,
+    ) {
+        self.ops.push(Operation::ExtractField(
+            Into::<LValue>::into(dest),
+            Into::<RValue<StructTypeImmed>>::into(value),
+            idx,
+        ));
+    }
+
+    /// Copies a struct to a scratch value with field `idx` replaced.
+    /// Integers are truncated or zero extended to the type of the field,
+    /// and structs must have the same type as the field.
+    pub fn insert_field(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        value: impl Into<RValue<StructTypeImmed>> + Clone,
+        field: impl Into<RValue<TypeImmed>> + Clone,
+        idx: u8,
+    ) {
+        self.ops.push(Operation::InsertField(
+            Into::<LValue>::into(dest),
+            Into::<RValue<StructTypeImmed>>::into(value),
+            Into::<RValue<TypeImmed>>::into(field),
+            idx,
+        ));
+    }
+
+    /// Calls the host function registered as `name`, writing its return
+    /// value to `dest`
+    pub fn fn_call(
+        &mut self,
+        dest: impl Into<LValue> + Clone,
+        name: impl Into<String>,
+        args: impl IntoIterator<Item = RValue<IntImmed>>,
+    ) {
+        self.ops.push(Operation::FnCall(
+            Some(Into::<LValue>::into(dest)),
+            name.into(),
+            args.into_iter().collect(),
+        ));
+    }
+
+    /// Same as fn_call, discarding any return value
+    pub fn fn_call_void(
+        &mut self,
+        name: impl Into<String>,
+        args: impl IntoIterator<Item = RValue<IntImmed>>,
+    ) {
+        self.ops.push(Operation::FnCall(
+            None,
+            name.into(),
+            args.into_iter().collect(),
+        ));
+    }
 }
@@ -1,7 +1,9 @@
 use crate::{
-    backend::{Compiler, Executable, PlatformDefaultBackend},
+    backend::{Compiler, Executable, ExitReason, Fuel, PlatformDefaultBackend, ResumePoint, Trap},
+    host::{HostFunctions, HostState, Signature},
     ir::reg::RegisterMap,
     unit::TranslationUnit,
+    IntImmed,
 };
 use std::{
     cell::RefCell,
@@ -11,13 +13,34 @@ use std::{
 #[derive(Default)]
 pub struct ExecutionContext<Backend: Compiler = PlatformDefaultBackend> {
     backend: RefCell<Backend>,
+    functions: HostFunctions,
 }
 
 impl<Backend: Compiler> ExecutionContext<Backend> {
+    pub fn new(backend: Backend) -> Self {
+        Self {
+            backend: RefCell::new(backend),
+            functions: HostFunctions::default(),
+        }
+    }
+
+    /// Registers a host function that units compiled by this context can
+    /// call by name. The function is given the state the unit is running
+    /// on and its arguments converted to the types in `signature`, and
+    /// must return a value of the declared return type.
+    pub fn register_fn(
+        &mut self,
+        name: impl Into<String>,
+        signature: Signature,
+        f: impl Fn(&mut HostState, &[IntImmed]) -> Result<Option<IntImmed>, Trap> + 'static,
+    ) {
+        self.functions.register(name, signature, f);
+    }
+
     pub fn compile<'ctx, 'state: 'ctx, State: RegisterMap + 'state>(
         &'ctx self,
         translation_unit: Box<TranslationUnit>,
-    ) -> Result<CompiledTranslationUnit<State, Backend>, String> {
+    ) -> Result<CompiledTranslationUnit<'ctx, 'state, State, Backend>, String> {
         let exec = self.compile_unit(&translation_unit)?;
 
         Ok(CompiledTranslationUnit {
@@ -29,9 +52,11 @@ impl<Backend: Compiler> ExecutionContext<Backend> {
 
     fn compile_unit<'state, State: RegisterMap + 'state>(
         &self,
-        unit: &Box<TranslationUnit>,
+        unit: &TranslationUnit,
     ) -> Result<Rc<dyn Executable<State> + 'state>, String> {
-        self.backend.borrow_mut().compile_unit(unit)
+        self.backend
+            .borrow_mut()
+            .compile_unit_with_functions(unit, &self.functions)
     }
 }
 
@@ -44,18 +69,36 @@ pub struct CompiledTranslationUnit<'ctx, 'state, State: RegisterMap, Backend: Co
 impl<'ctx, 'state, State: RegisterMap + 'state, Backend: Compiler>
     CompiledTranslationUnit<'ctx, 'state, State, Backend>
 {
-    pub unsafe fn execute(&mut self, state: &mut State) {
+    fn executable(&mut self) -> Rc<dyn Executable<State> + 'state> {
         if let Some(exec) = self.executable.upgrade() {
-            unsafe {
-                exec.execute(state);
-            }
+            exec
         } else {
             let exec = self.context.compile_unit(&self.translation_unit).unwrap();
             self.executable = Rc::downgrade(&exec);
-
-            unsafe {
-                exec.execute(state);
-            }
+            exec
         }
     }
+
+    /// # Safety
+    /// See [`Executable::execute`]
+    pub unsafe fn execute(&mut self, state: &mut State) -> ExitReason {
+        unsafe { self.executable().execute(state) }
+    }
+
+    /// # Safety
+    /// See [`Executable::execute`]
+    pub unsafe fn execute_with_fuel(&mut self, state: &mut State, fuel: Fuel) -> ExitReason {
+        unsafe { self.executable().execute_with_fuel(state, fuel) }
+    }
+
+    /// # Safety
+    /// See [`Executable::execute`]
+    pub unsafe fn resume(
+        &mut self,
+        state: &mut State,
+        from: ResumePoint,
+        fuel: Fuel,
+    ) -> ExitReason {
+        unsafe { self.executable().resume(state, from, fuel) }
+    }
 }
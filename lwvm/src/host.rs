@@ -0,0 +1,154 @@
+use crate::{
+    backend::Trap,
+    ir::{
+        reg::{Register, RegisterMap},
+        types::Endianness,
+    },
+    mem::GuestMemory,
+    IntImmed, IntType,
+};
+use std::{collections::BTreeMap, fmt, marker::PhantomData, rc::Rc};
+
+/// Argument and return types a host function is declared with. Arguments
+/// are truncated or zero extended to their declared type before the call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub args: Vec<IntType>,
+    pub ret: Option<IntType>,
+}
+
+impl Signature {
+    pub fn new(args: &[IntType], ret: Option<IntType>) -> Self {
+        Self {
+            args: args.to_vec(),
+            ret,
+        }
+    }
+}
+
+/// The state a host function was called with, along with the guest
+/// memory of the unit that called it
+pub struct HostState<'a> {
+    state: *mut u8,
+    regs: &'a [Register],
+    guest_memory: GuestMemory,
+    endianness: Endianness,
+    _state: PhantomData<&'a mut u8>,
+}
+
+impl<'a> HostState<'a> {
+    pub(crate) fn new<State: RegisterMap>(
+        state: &'a mut State,
+        regs: &'a [Register],
+        guest_memory: GuestMemory,
+        endianness: Endianness,
+    ) -> Self {
+        Self {
+            state: state as *mut State as *mut u8,
+            regs,
+            guest_memory,
+            endianness,
+            _state: PhantomData,
+        }
+    }
+
+    /// Reads a register, or returns None if there is no such register
+    /// or it is a vector register
+    pub fn register(&self, idx: u8) -> Option<IntImmed> {
+        let reg = self
+            .regs
+            .get(idx as usize)
+            .filter(|reg| !reg.ty.is_vector())?;
+        Some(unsafe { reg.load(self.state.add(reg.offset)) })
+    }
+
+    /// Writes a register, truncating the value to the register's width.
+    /// Returns false if there is no such register, or if it is a vector register.
+    pub fn set_register(&mut self, idx: u8, value: IntImmed) -> bool {
+        match self.regs.get(idx as usize) {
+            Some(reg) if !reg.ty.is_vector() => {
+                unsafe { reg.store(value.to_u128(), self.state.add(reg.offset)) };
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Reads a `size` bit value from guest memory in the calling unit's
+    /// default byte order
+    pub fn guest_read(&self, addr: u64, size: u8) -> Result<IntImmed, Trap> {
+        unsafe { self.guest_memory.read(addr, size, self.endianness) }
+    }
+
+    /// Writes the low `size` bits of `value` to guest memory in the calling
+    /// unit's default byte order
+    pub fn guest_write(&mut self, addr: u64, value: IntImmed, size: u8) -> Result<(), Trap> {
+        unsafe { self.guest_memory.write(addr, value, size, self.endianness) }
+    }
+}
+
+type HostFn = dyn Fn(&mut HostState, &[IntImmed]) -> Result<Option<IntImmed>, Trap>;
+
+/// A host function registered under a name
+pub struct HostFunction {
+    pub(crate) name: String,
+    pub(crate) signature: Signature,
+    f: Box<HostFn>,
+}
+
+impl fmt::Debug for HostFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostFunction")
+            .field("name", &self.name)
+            .field("signature", &self.signature)
+            .finish()
+    }
+}
+
+impl HostFunction {
+    /// Calls the function, which must return a value of its declared
+    /// return type, and nothing if it has none
+    pub(crate) fn call(
+        &self,
+        state: &mut HostState,
+        args: &[IntImmed],
+    ) -> Result<Option<IntImmed>, Trap> {
+        let ret = (self.f)(state, args)?;
+        if ret.map(|v| v.get_type()) != self.signature.ret {
+            return Err(Trap::TypeMismatch);
+        }
+        Ok(ret)
+    }
+}
+
+/// Host functions that units can call by name
+#[derive(Debug, Default, Clone)]
+pub struct HostFunctions {
+    functions: BTreeMap<String, Rc<HostFunction>>,
+}
+
+impl HostFunctions {
+    /// Registers `f` under `name`, replacing any function already registered
+    /// with that name. Units that were already compiled keep calling the
+    /// function they were compiled with.
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        signature: Signature,
+        f: impl Fn(&mut HostState, &[IntImmed]) -> Result<Option<IntImmed>, Trap> + 'static,
+    ) {
+        let name = name.into();
+        self.functions.insert(
+            name.clone(),
+            Rc::new(HostFunction {
+                name,
+                signature,
+                f: Box::new(f),
+            }),
+        );
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&Rc<HostFunction>> {
+        self.functions.get(name)
+    }
+}
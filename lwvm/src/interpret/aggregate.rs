@@ -0,0 +1,108 @@
+use super::{
+    lower::{Dest, Operand, StructOperand, ValueOperand},
+    Frame, InterpreterExecutable,
+};
+use crate::{
+    backend::Trap,
+    ir::{
+        reg::RegisterMap,
+        types::{StructType, StructTypeImmed, Type, TypeImmed},
+    },
+};
+
+impl<State: RegisterMap> InterpreterExecutable<State> {
+    fn rv_to_struct(
+        &self,
+        frame: &Frame<State>,
+        rv: &StructOperand,
+    ) -> Result<StructTypeImmed, Trap> {
+        match rv {
+            StructOperand::Immediate(s) => Ok(s.clone()),
+            StructOperand::Scratch(s) => frame.scratch.read_struct(*s).cloned(),
+        }
+    }
+
+    fn rv_to_value(&self, frame: &Frame<State>, rv: &ValueOperand) -> Result<TypeImmed, Trap> {
+        match rv {
+            ValueOperand::Immediate(v) => Ok(v.clone()),
+            ValueOperand::Register(reg) => Ok(TypeImmed::Integer(unsafe { reg.read(frame.state) })),
+            ValueOperand::Scratch(s) => frame.scratch.read_value(*s),
+        }
+    }
+
+    pub(super) fn op_guest_read_struct(
+        &self,
+        dest: u8,
+        addr: &Operand,
+        ty: &StructType,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let addr = self.rv_to_immed(frame, addr)?.to_u64();
+        let value = unsafe {
+            self.guest_memory
+                .read_struct(addr, ty, self.unit.endianness)?
+        };
+        frame.scratch.write_struct(dest, value);
+        Ok(())
+    }
+
+    pub(super) fn op_guest_write_struct(
+        &self,
+        addr: &Operand,
+        value: &StructOperand,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let addr = self.rv_to_immed(frame, addr)?.to_u64();
+        let value = self.rv_to_struct(frame, value)?;
+        unsafe {
+            self.guest_memory
+                .write_struct(addr, &value, self.unit.endianness)
+        }
+    }
+
+    /// Integer fields are written like any other integer result, and
+    /// struct fields can only be written to scratch values
+    pub(super) fn op_extract_field(
+        &self,
+        dest: &Dest,
+        value: &StructOperand,
+        idx: u8,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let value = self.rv_to_struct(frame, value)?;
+        match (value.field(idx).ok_or(Trap::InvalidField(idx))?, dest) {
+            (TypeImmed::Integer(i), _) => self.write_lvalue(dest, *i, false, frame),
+            (TypeImmed::Struct(s), Dest::Scratch(d)) => frame.scratch.write_struct(*d, s.clone()),
+            (TypeImmed::Struct(_), Dest::Register(..)) => return Err(Trap::TypeMismatch),
+        }
+        Ok(())
+    }
+
+    pub(super) fn op_insert_field(
+        &self,
+        dest: u8,
+        value: &StructOperand,
+        field: &ValueOperand,
+        idx: u8,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let mut value = self.rv_to_struct(frame, value)?;
+        let ty = value
+            .get_type()
+            .fields()
+            .get(idx as usize)
+            .ok_or(Trap::InvalidField(idx))?;
+        let field = match (self.rv_to_value(frame, field)?, ty) {
+            (TypeImmed::Integer(i), Type::Integer(ty)) => {
+                TypeImmed::Integer(ty.from_u128(i.to_u128()))
+            }
+            (field, _) => field,
+        };
+
+        if !value.set_field(idx, field) {
+            return Err(Trap::TypeMismatch);
+        }
+        frame.scratch.write_struct(dest, value);
+        Ok(())
+    }
+}
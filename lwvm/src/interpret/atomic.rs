@@ -0,0 +1,97 @@
+use super::{
+    lower::{Dest, Operand},
+    Frame, InterpreterExecutable,
+};
+use crate::{
+    backend::Trap,
+    ir::{
+        reg::RegisterMap,
+        types::{AtomicOp, IntImmed},
+    },
+    mem::{AtomicAccess, Reservation},
+};
+
+impl<State: RegisterMap> InterpreterExecutable<State> {
+    pub(super) fn op_guest_atomic_rmw(
+        &self,
+        dest: &Dest,
+        op: AtomicOp,
+        addr: &Operand,
+        value: &Operand,
+        access: AtomicAccess,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let addr = self.rv_to_immed(frame, addr)?.to_u64();
+        let value = self.rv_to_immed(frame, value)?.to_u64();
+        let old = unsafe { self.guest_memory.rmw_atomic(addr, op, value, access)? };
+        self.write_lvalue(dest, old, false, frame);
+        Ok(())
+    }
+
+    pub(super) fn op_guest_compare_exchange(
+        &self,
+        dest: &Dest,
+        addr: &Operand,
+        (expected, new): (&Operand, &Operand),
+        access: AtomicAccess,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let addr = self.rv_to_immed(frame, addr)?.to_u64();
+        let expected = self.rv_to_immed(frame, expected)?.to_u64();
+        let new = self.rv_to_immed(frame, new)?.to_u64();
+        let old = unsafe {
+            self.guest_memory
+                .compare_exchange(addr, expected, new, access)?
+        };
+        self.write_lvalue(dest, old, false, frame);
+        Ok(())
+    }
+
+    /// Reads a value and reserves its location for the next store-conditional
+    pub(super) fn op_guest_load_linked(
+        &self,
+        dest: &Dest,
+        addr: &Operand,
+        access: AtomicAccess,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let addr = self.rv_to_immed(frame, addr)?.to_u64();
+        let value = unsafe { self.guest_memory.load_atomic(addr, access)? };
+        frame.reservation = Some(Reservation {
+            addr,
+            size: access.size,
+            value: value.to_u64(),
+        });
+        self.write_lvalue(dest, value, false, frame);
+        Ok(())
+    }
+
+    /// Writes the value if the location is reserved and still holds the value
+    /// that was loaded, then clears the reservation. Whether the store was
+    /// performed is written to `dest` as a Bool.
+    pub(super) fn op_guest_store_conditional(
+        &self,
+        dest: &Dest,
+        addr: &Operand,
+        value: &Operand,
+        access: AtomicAccess,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let addr = self.rv_to_immed(frame, addr)?.to_u64();
+        let value = self.rv_to_immed(frame, value)?.to_u64();
+        let stored = match frame.reservation {
+            Some(reserved) if reserved.addr == addr && reserved.size == access.size => {
+                let old = unsafe {
+                    self.guest_memory
+                        .compare_exchange(addr, reserved.value, value, access)?
+                };
+                old.to_u64() == reserved.value
+            }
+            _ => false,
+        };
+
+        frame.reservation = None;
+        self.write_lvalue(dest, IntImmed::Bool(stored), false, frame);
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use super::{
+    lower::{Dest, Operand},
+    Frame, InterpreterExecutable,
+};
+use crate::{backend::Trap, ir::reg::RegisterMap, IntImmed};
+
+/// Rotates the low `width` bits of `value` left by `amount`
+fn rotate_left(value: u128, amount: u32, width: u32) -> u128 {
+    let amount = amount % width;
+    if amount == 0 {
+        return value;
+    }
+
+    let mask = u128::MAX >> (128 - width);
+    ((value << amount) | (value >> (width - amount))) & mask
+}
+
+/// Rotates the low `width` bits of `value` left by `amount` together
+/// with `carry` as their top bit, returning the rotated value and carry
+fn rotate_left_carry(value: u128, carry: bool, amount: u32, width: u32) -> (u128, bool) {
+    let amount = amount % (width + 1);
+    if amount == 0 {
+        return (value, carry);
+    }
+
+    // The shifts can be by the full 128 bits of a 129 bit rotate
+    let mask = u128::MAX >> (128 - width);
+    let rotated = value.checked_shl(amount).unwrap_or(0)
+        | (carry as u128) << (amount - 1)
+        | value.checked_shr(width + 1 - amount).unwrap_or(0);
+    (rotated & mask, (value >> (width - amount)) & 1 != 0)
+}
+
+impl<State: RegisterMap> InterpreterExecutable<State> {
+    /// Rotates keep the width of the value being rotated, and rotate by
+    /// the amount modulo that width
+    pub(super) fn op_rotate(
+        &self,
+        dest: &Dest,
+        arg1: &Operand,
+        arg2: &Operand,
+        left: bool,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let value = self.rv_to_immed(frame, arg1)?;
+        let amount = self.rv_to_immed(frame, arg2)?.to_u128();
+        let width = value.size() as u32;
+        let amount = (amount % width as u128) as u32;
+        let amount = if left { amount } else { width - amount };
+
+        let rotated = rotate_left(value.to_u128(), amount, width);
+        self.write_lvalue(dest, value.get_type().from_u128(rotated), false, frame);
+        Ok(())
+    }
+
+    /// Rotates a value and a carry bit together as a single value one bit
+    /// wider, by the amount modulo that width. Returns the rotated value
+    /// and the carry out.
+    pub(super) fn rotate_carry(
+        &self,
+        arg1: &Operand,
+        arg2: &Operand,
+        carry: &Operand,
+        left: bool,
+        frame: &Frame<State>,
+    ) -> Result<(IntImmed, bool), Trap> {
+        let value = self.rv_to_immed(frame, arg1)?;
+        let amount = self.rv_to_immed(frame, arg2)?.to_u128();
+        let carry = self.rv_to_immed(frame, carry)?.to_u128() != 0;
+        let width = value.size() as u32;
+        let amount = (amount % (width as u128 + 1)) as u32;
+        let amount = if left { amount } else { width + 1 - amount };
+
+        let (bits, carry) = rotate_left_carry(value.to_u128(), carry, amount, width);
+        Ok((value.get_type().from_u128(bits), carry))
+    }
+
+    /// Applies `op` to a value and its width in bits, keeping the
+    /// width of the value
+    pub(super) fn op_bits(
+        &self,
+        dest: &Dest,
+        arg1: &Operand,
+        op: fn(u128, u32) -> u128,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let value = self.rv_to_immed(frame, arg1)?;
+        let result = op(value.to_u128(), value.size() as u32);
+        self.write_lvalue(dest, value.get_type().from_u128(result), false, frame);
+        Ok(())
+    }
+}
+
+pub(super) fn count_leading_zeros(value: u128, width: u32) -> u128 {
+    (value.leading_zeros() - (128 - width)) as u128
+}
+
+pub(super) fn count_trailing_zeros(value: u128, width: u32) -> u128 {
+    value.trailing_zeros().min(width) as u128
+}
+
+pub(super) fn pop_count(value: u128, _width: u32) -> u128 {
+    value.count_ones() as u128
+}
+
+/// Bools and bytes are left unchanged
+pub(super) fn byte_swap(value: u128, width: u32) -> u128 {
+    if width < 8 {
+        return value;
+    }
+    value.swap_bytes() >> (128 - width)
+}
+
+pub(super) fn bit_reverse(value: u128, width: u32) -> u128 {
+    value.reverse_bits() >> (128 - width)
+}
@@ -0,0 +1,516 @@
+use super::{
+    lower::{Dest, Inst, Operand},
+    ExitAction, Frame, InterpreterBackend, InterpreterExecutable,
+};
+use crate::{
+    backend::{Compiler, Executable, ExitReason, Fuel, ResumePoint, Trap},
+    host::HostFunctions,
+    ir::{
+        reg::{Register, RegisterMap},
+        types::{Comparator, IntType},
+    },
+    mem::GuestMemory,
+    unit::TranslationUnit,
+    IntImmed,
+};
+use std::rc::Rc;
+
+/// Compiles units to one closure per operation, with operand kinds and
+/// register offsets resolved at compile time. Integer arithmetic, logic,
+/// shifts, extensions, comparisons, selects, guest loads and stores,
+/// instruction markers and branches are specialized. Values wider than
+/// 64 bits, forms that record flags, and all other operations are
+/// executed by the interpreter, so both backends always produce the same
+/// results.
+#[derive(Default)]
+pub struct ClosureBackend {
+    guest_memory: GuestMemory,
+}
+
+type Handler<State> = Box<
+    dyn Fn(
+        &InterpreterExecutable<State>,
+        &mut Frame<'_, State>,
+    ) -> Result<Option<ExitAction>, Trap>,
+>;
+
+pub struct ClosureExecutable<State: RegisterMap> {
+    exec: InterpreterExecutable<State>,
+    handlers: Vec<Handler<State>>,
+}
+
+/// Sign or zero extends the low `width` bits of `value` to 64 bits
+fn extend(value: u64, width: u8, signed: bool) -> u64 {
+    if width >= 64 {
+        return value;
+    }
+
+    let shift = 64 - width as u32;
+    if signed {
+        (((value << shift) as i64) >> shift) as u64
+    } else {
+        (value << shift) >> shift
+    }
+}
+
+/// Extends a value to 64 bits, keeping its type. Bools are never sign
+/// extended, matching how they upcast. 128 bit values don't fit.
+fn widen(value: IntImmed, signed: bool) -> Option<(u64, IntType)> {
+    match value {
+        IntImmed::I128(_) => None,
+        IntImmed::Bool(b) => Some((b as u64, IntType::Bool)),
+        _ => Some((
+            extend(value.to_u64(), value.size(), signed),
+            value.get_type(),
+        )),
+    }
+}
+
+/// A source operand, read as its value extended to 64 bits
+#[derive(Clone, Copy)]
+enum Src {
+    Immediate(u64, IntType),
+    Register(Register, bool),
+    Scratch(u8, bool),
+}
+
+impl Src {
+    /// Returns None for operands that are always wider than 64 bits
+    fn new(op: &Operand, signed: bool) -> Option<Self> {
+        match *op {
+            Operand::Immediate(i) => widen(i, signed).map(|(v, ty)| Self::Immediate(v, ty)),
+            Operand::Register(reg) if reg.ty.size() > 64 => None,
+            Operand::Register(reg) => Some(Self::Register(reg, signed)),
+            Operand::Scratch(s) => Some(Self::Scratch(s, signed)),
+        }
+    }
+
+    /// Reads the operand and its type, or None if the scratch value it
+    /// names turns out to be 128 bits wide
+    fn get<State: RegisterMap>(
+        &self,
+        frame: &Frame<State>,
+    ) -> Result<Option<(u64, IntType)>, Trap> {
+        Ok(match *self {
+            Self::Immediate(v, ty) => Some((v, ty)),
+            Self::Register(reg, signed) => widen(unsafe { reg.read(frame.state) }, signed),
+            Self::Scratch(s, signed) => widen(frame.scratch.read(s)?, signed),
+        })
+    }
+}
+
+/// Reads two operands along with the wider of their types, which is the
+/// type they upcast to
+fn operands<State: RegisterMap>(
+    arg1: &Src,
+    arg2: &Src,
+    frame: &Frame<State>,
+) -> Result<Option<(u64, u64, IntType)>, Trap> {
+    let (Some((v1, ty1)), Some((v2, ty2))) = (arg1.get(frame)?, arg2.get(frame)?) else {
+        return Ok(None);
+    };
+    let ty = if ty1.size() >= ty2.size() { ty1 } else { ty2 };
+    Ok(Some((v1, v2, ty)))
+}
+
+/// Writes the low bits of `value` as a `ty` to `dest` the way the
+/// interpreter does
+fn write<State: RegisterMap>(
+    dest: &Dest,
+    ty: IntType,
+    value: u64,
+    signed: bool,
+    frame: &mut Frame<State>,
+) {
+    let signed = signed && ty != IntType::Bool;
+    let value = extend(value, ty.size(), signed);
+    match dest {
+        Dest::Register(_, reg) => {
+            let value = if signed {
+                value as i64 as u128
+            } else {
+                value as u128
+            };
+            unsafe { reg.write(value, frame.state) }
+        }
+        Dest::Scratch(s) => frame.scratch.write(*s, ty.from_u64(value)),
+    }
+}
+
+/// Wraps `handler`, which returns None for values it can't handle,
+/// so that the interpreter executes `inst` for those instead
+fn or_interpret<State: RegisterMap>(
+    inst: &Inst,
+    handler: impl Fn(&mut Frame<State>) -> Result<Option<()>, Trap> + 'static,
+) -> Option<Handler<State>> {
+    let inst = inst.clone();
+    Some(Box::new(move |exec, frame| match handler(frame)? {
+        Some(()) => Ok(None),
+        None => exec.execute_inst(&inst, frame),
+    }))
+}
+
+/// Builds a closure for a binary operation, which is correct for any
+/// operation whose low bits only depend on the low bits of its operands.
+/// Two Bools combine by their own rules, which the interpreter handles.
+fn binary<State: RegisterMap>(
+    inst: &Inst,
+    (dest, arg1, arg2): (Dest, &Operand, &Operand),
+    signed: bool,
+    op: fn(u64, u64) -> u64,
+) -> Option<Handler<State>> {
+    let arg1 = Src::new(arg1, signed)?;
+    let arg2 = Src::new(arg2, signed)?;
+
+    or_interpret(inst, move |frame| {
+        let Some((v1, v2, ty)) = operands(&arg1, &arg2, frame)? else {
+            return Ok(None);
+        };
+        if ty == IntType::Bool {
+            return Ok(None);
+        }
+        write(&dest, ty, op(v1, v2), signed, frame);
+        Ok(Some(()))
+    })
+}
+
+/// Division and remainder trap on a zero divisor, and for signed
+/// operands on the most negative value divided by -1
+fn division<State: RegisterMap>(
+    inst: &Inst,
+    (dest, arg1, arg2): (Dest, &Operand, &Operand),
+    signed: bool,
+    remainder: bool,
+) -> Option<Handler<State>> {
+    let arg1 = Src::new(arg1, signed)?;
+    let arg2 = Src::new(arg2, signed)?;
+
+    or_interpret(inst, move |frame| {
+        let Some((v1, v2, ty)) = operands(&arg1, &arg2, frame)? else {
+            return Ok(None);
+        };
+        if ty == IntType::Bool {
+            return Ok(None);
+        }
+        if v2 == 0 {
+            return Err(Trap::DivideByZero);
+        }
+
+        let value = if signed {
+            let (v1, v2) = (v1 as i64, v2 as i64);
+            if v2 == -1 && v1 == i64::MIN >> (64 - ty.size()) {
+                return Err(Trap::IntegerOverflow);
+            }
+            if remainder {
+                v1.wrapping_rem(v2) as u64
+            } else {
+                v1.wrapping_div(v2) as u64
+            }
+        } else if remainder {
+            v1 % v2
+        } else {
+            v1 / v2
+        };
+        write(&dest, ty, value, signed, frame);
+        Ok(Some(()))
+    })
+}
+
+/// Shifts keep the type of the value being shifted, and saturate the
+/// amount the same way the interpreter does
+fn shift<State: RegisterMap>(
+    inst: &Inst,
+    (dest, value, amount): (Dest, &Operand, &Operand),
+    left: bool,
+    signed: bool,
+) -> Option<Handler<State>> {
+    let value = Src::new(value, signed)?;
+    let amount = Src::new(amount, false)?;
+
+    or_interpret(inst, move |frame| {
+        let (Some((value, ty)), Some((amount, _))) = (value.get(frame)?, amount.get(frame)?) else {
+            return Ok(None);
+        };
+        if ty == IntType::Bool {
+            return Ok(None);
+        }
+
+        let width = u64::from(ty.size());
+        let value = if left {
+            if amount >= width {
+                0
+            } else {
+                value << amount
+            }
+        } else if signed {
+            ((value as i64) >> amount.min(width - 1)) as u64
+        } else if amount >= width {
+            0
+        } else {
+            value >> amount
+        };
+        write(&dest, ty, value, signed && !left, frame);
+        Ok(Some(()))
+    })
+}
+
+fn extension<State: RegisterMap>(
+    inst: &Inst,
+    dest: Dest,
+    arg: &Operand,
+    ty: IntType,
+    signed: bool,
+) -> Option<Handler<State>> {
+    if matches!(ty, IntType::Bool | IntType::I128) {
+        return None;
+    }
+    let arg = Src::new(arg, signed)?;
+
+    or_interpret(inst, move |frame| {
+        let Some((value, _)) = arg.get(frame)? else {
+            return Ok(None);
+        };
+        write(&dest, ty, value, signed, frame);
+        Ok(Some(()))
+    })
+}
+
+fn not<State: RegisterMap>(inst: &Inst, dest: Dest, arg: &Operand) -> Option<Handler<State>> {
+    let arg = Src::new(arg, false)?;
+
+    or_interpret(inst, move |frame| {
+        let Some((value, ty)) = arg.get(frame)? else {
+            return Ok(None);
+        };
+        write(&dest, ty, !value, false, frame);
+        Ok(Some(()))
+    })
+}
+
+/// Compares two operands extended to 64 bits the way the comparison
+/// upcasts them
+fn comparison(cmp: Comparator) -> fn(u64, u64) -> bool {
+    match cmp {
+        Comparator::EQ => |a, b| a == b,
+        Comparator::NEQ => |a, b| a != b,
+        Comparator::SLT => |a, b| (a as i64) < (b as i64),
+        Comparator::SGT => |a, b| (a as i64) > (b as i64),
+        Comparator::SLE => |a, b| (a as i64) <= (b as i64),
+        Comparator::SGE => |a, b| (a as i64) >= (b as i64),
+        Comparator::ULT => |a, b| a < b,
+        Comparator::UGT => |a, b| a > b,
+        Comparator::ULE => |a, b| a <= b,
+        Comparator::UGE => |a, b| a >= b,
+    }
+}
+
+fn compare<State: RegisterMap>(
+    inst: &Inst,
+    dest: Dest,
+    cmp: Comparator,
+    arg1: &Operand,
+    arg2: &Operand,
+) -> Option<Handler<State>> {
+    let arg1 = Src::new(arg1, cmp.is_signed())?;
+    let arg2 = Src::new(arg2, cmp.is_signed())?;
+    let cmp = comparison(cmp);
+
+    or_interpret(inst, move |frame| {
+        let Some((v1, v2, _)) = operands(&arg1, &arg2, frame)? else {
+            return Ok(None);
+        };
+        write(&dest, IntType::Bool, cmp(v1, v2) as u64, false, frame);
+        Ok(Some(()))
+    })
+}
+
+/// Branches only need to know whether their condition is zero, so any
+/// operand can be used without knowing its width
+fn branch<State: RegisterMap>(
+    cond: Operand,
+    taken: usize,
+    not_taken: usize,
+    blocks: usize,
+) -> Option<Handler<State>> {
+    // Branches to unknown labels trap in the interpreter
+    if taken >= blocks || not_taken >= blocks {
+        return None;
+    }
+
+    Some(Box::new(move |exec, frame| {
+        let value = exec.rv_to_immed(frame, &cond)?.to_u128();
+        let target = if value == 0 { not_taken } else { taken };
+        Ok(Some(ExitAction::BranchTo(target)))
+    }))
+}
+
+fn compare_branch<State: RegisterMap>(
+    inst: &Inst,
+    cmp: Comparator,
+    (arg1, arg2): (&Operand, &Operand),
+    (taken, not_taken): (usize, usize),
+    blocks: usize,
+) -> Option<Handler<State>> {
+    // Branches to unknown labels trap in the interpreter
+    if taken >= blocks || not_taken >= blocks {
+        return None;
+    }
+
+    let arg1 = Src::new(arg1, cmp.is_signed())?;
+    let arg2 = Src::new(arg2, cmp.is_signed())?;
+    let cmp = comparison(cmp);
+    let inst = inst.clone();
+
+    Some(Box::new(move |exec, frame| {
+        let Some((v1, v2, _)) = operands(&arg1, &arg2, frame)? else {
+            return exec.execute_inst(&inst, frame);
+        };
+        let target = if cmp(v1, v2) { taken } else { not_taken };
+        Ok(Some(ExitAction::BranchTo(target)))
+    }))
+}
+
+fn specialize<State: RegisterMap>(inst: &Inst, blocks: usize) -> Option<Handler<State>> {
+    match inst {
+        Inst::Add(d, a, b, s, false) => binary(inst, (*d, a, b), *s, u64::wrapping_add),
+        Inst::Sub(d, a, b, s, false) => binary(inst, (*d, a, b), *s, u64::wrapping_sub),
+        Inst::Mult(d, a, b, s) => binary(inst, (*d, a, b), *s, u64::wrapping_mul),
+        Inst::Div(d, a, b, s) => division(inst, (*d, a, b), *s, false),
+        Inst::Rem(d, a, b, s) => division(inst, (*d, a, b), *s, true),
+        Inst::LShift(d, a, b) => shift(inst, (*d, a, b), true, false),
+        Inst::RShift(d, a, b, s) => shift(inst, (*d, a, b), false, *s),
+        Inst::SignExtend(d, a, ty) => extension(inst, *d, a, *ty, true),
+        Inst::ZeroExtend(d, a, ty) => extension(inst, *d, a, *ty, false),
+        Inst::And(d, a, b, false) => binary(inst, (*d, a, b), false, |a, b| a & b),
+        Inst::Or(d, a, b, false) => binary(inst, (*d, a, b), false, |a, b| a | b),
+        Inst::Xor(d, a, b, false) => binary(inst, (*d, a, b), false, |a, b| a ^ b),
+        Inst::Not(d, a) => not(inst, *d, a),
+        Inst::ICmp(d, cmp, a, b) => compare(inst, *d, *cmp, a, b),
+        Inst::Select(c, d, a, b) => {
+            let (c, d, a, b) = (*c, *d, *a, *b);
+            Some(Box::new(move |exec, frame| {
+                exec.op_select(&c, &d, &a, &b, frame)?;
+                Ok(None)
+            }))
+        }
+        Inst::GuestReadMem(d, addr, size, endianness) => {
+            let (d, addr, access) = (*d, *addr, (*size, *endianness));
+            Some(Box::new(move |exec, frame| {
+                exec.op_guest_read(&d, &addr, access, frame)?;
+                Ok(None)
+            }))
+        }
+        Inst::GuestWriteMem(addr, value, size, endianness) => {
+            let (addr, value, access) = (*addr, *value, (*size, *endianness));
+            Some(Box::new(move |exec, frame| {
+                exec.op_guest_write(&addr, &value, access, frame)?;
+                Ok(None)
+            }))
+        }
+        Inst::Branch(c, t, nt) => branch(*c, *t, *nt, blocks),
+        Inst::CompareBranch(cmp, a, b, t, nt) => {
+            compare_branch(inst, *cmp, (a, b), (*t, *nt), blocks)
+        }
+        Inst::Exit(code) => {
+            let code = *code;
+            Some(Box::new(move |_, _| Ok(Some(ExitAction::Exit(code)))))
+        }
+        Inst::Instruction(pc, len) => {
+            let instruction = Some((*pc, *len));
+            Some(Box::new(move |_, frame| {
+                frame.retire_instruction();
+                frame.instruction = instruction;
+                Ok(None)
+            }))
+        }
+        Inst::Trap(trap) => {
+            let trap = trap.clone();
+            Some(Box::new(move |_, _| Err(trap.clone())))
+        }
+        _ => None,
+    }
+}
+
+impl ClosureBackend {
+    /// Creates a backend which serves guest memory accesses from `guest_memory`
+    pub fn new(guest_memory: GuestMemory) -> Self {
+        Self { guest_memory }
+    }
+
+    /// Compiles a unit into an executable that can be used directly.
+    /// Function calls in the unit trap.
+    pub fn compile<State: RegisterMap>(
+        &self,
+        unit: &TranslationUnit,
+    ) -> Result<ClosureExecutable<State>, String> {
+        self.compile_with_functions(unit, &HostFunctions::default())
+    }
+
+    /// Same as compile, but resolves function calls against `functions`
+    pub fn compile_with_functions<State: RegisterMap>(
+        &self,
+        unit: &TranslationUnit,
+        functions: &HostFunctions,
+    ) -> Result<ClosureExecutable<State>, String> {
+        let exec = InterpreterBackend::new(self.guest_memory)
+            .compile_with_functions::<State>(unit, functions)?;
+        let blocks = exec.unit.blocks.len();
+
+        let handlers = exec
+            .unit
+            .insts
+            .iter()
+            .map(|inst| {
+                let inst = inst.clone();
+                specialize(&inst, blocks).unwrap_or_else(|| {
+                    Box::new(
+                        move |exec: &InterpreterExecutable<State>, frame: &mut Frame<State>| {
+                            exec.execute_inst(&inst, frame)
+                        },
+                    )
+                })
+            })
+            .collect();
+
+        Ok(ClosureExecutable { exec, handlers })
+    }
+}
+
+impl Compiler for ClosureBackend {
+    fn compile_unit<'a, State: RegisterMap + 'a>(
+        &mut self,
+        unit: &TranslationUnit,
+    ) -> Result<Rc<dyn Executable<State> + 'a>, String> {
+        Ok(Rc::new(self.compile::<State>(unit)?))
+    }
+
+    fn compile_unit_with_functions<'a, State: RegisterMap + 'a>(
+        &mut self,
+        unit: &TranslationUnit,
+        functions: &HostFunctions,
+    ) -> Result<Rc<dyn Executable<State> + 'a>, String> {
+        Ok(Rc::new(
+            self.compile_with_functions::<State>(unit, functions)?,
+        ))
+    }
+}
+
+impl<State: RegisterMap> Executable<State> for ClosureExecutable<State> {
+    unsafe fn execute_with_fuel(&self, state: &mut State, fuel: Fuel) -> ExitReason {
+        let mut frame = Frame::new(state, &self.exec.unit);
+        let start = self.exec.unit.entry_point();
+        self.exec.run(&mut frame, start, fuel, |frame, _, pc| {
+            (self.handlers[pc])(&self.exec, frame)
+        })
+    }
+
+    unsafe fn resume(&self, state: &mut State, from: ResumePoint, fuel: Fuel) -> ExitReason {
+        let (mut frame, start) = match Frame::resume(state, from, self.exec.origin()) {
+            Ok(resumed) => resumed,
+            Err(trap) => return self.exec.rejected(trap),
+        };
+        self.exec.run(&mut frame, start, fuel, |frame, _, pc| {
+            (self.handlers[pc])(&self.exec, frame)
+        })
+    }
+}
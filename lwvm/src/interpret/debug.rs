@@ -0,0 +1,230 @@
+use super::{ExitAction, Frame, InterpreterExecutable};
+use crate::{
+    backend::{ExitReason, ExitStatus},
+    ir::reg::{Register, RegisterMap},
+    BlockLabel, IntImmed,
+};
+use std::collections::BTreeSet;
+
+/// Why a debugger stopped executing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugStop {
+    /// The requested step completed
+    Step,
+    /// Execution reached a breakpoint. The operation at the
+    /// breakpoint has not been executed yet.
+    Breakpoint,
+    /// The unit exited. Further steps return the same reason.
+    Exited(ExitReason),
+}
+
+/// A paused execution of a unit, which can be single-stepped and inspected.
+/// Execution starts paused before the first operation of the entry block.
+pub struct Debugger<'a, State: RegisterMap> {
+    exec: &'a InterpreterExecutable<State>,
+    frame: Frame<'a, State>,
+    regs: Vec<Register>,
+    breakpoints: BTreeSet<usize>,
+    block: usize,
+    pc: usize,
+    ops_retired: u64,
+    blocks_retired: u64,
+    exited: Option<ExitReason>,
+}
+
+impl<State: RegisterMap> InterpreterExecutable<State> {
+    pub fn debug<'a>(&'a self, state: &'a mut State) -> Debugger<'a, State> {
+        let block = self.unit.entry;
+        Debugger {
+            exec: self,
+            frame: Frame::new(state, &self.unit),
+            regs: State::register_offsets(),
+            breakpoints: BTreeSet::new(),
+            block,
+            pc: self.unit.blocks[block].start,
+            ops_retired: 0,
+            blocks_retired: 0,
+            exited: None,
+        }
+    }
+}
+
+impl<'a, State: RegisterMap> Debugger<'a, State> {
+    /// Converts a (block label, operation index) pair to an instruction index
+    fn locate(&self, label: &str, index: usize) -> Result<usize, String> {
+        let blocks = &self.exec.unit.blocks;
+        let block = blocks
+            .iter()
+            .position(|b| b.label == label)
+            .ok_or(format!("No such block {}", label))?;
+
+        let start = blocks[block].start;
+        let end = blocks
+            .get(block + 1)
+            .map_or(self.exec.unit.insts.len(), |b| b.start);
+        if index >= end - start {
+            return Err(format!("Block {} has no operation {}", label, index));
+        }
+
+        Ok(start + index)
+    }
+
+    /// Pauses execution before the operation at `index` in block `label`
+    pub fn add_breakpoint(&mut self, label: &str, index: usize) -> Result<(), String> {
+        let pc = self.locate(label, index)?;
+        self.breakpoints.insert(pc);
+        Ok(())
+    }
+
+    /// Returns whether there was a breakpoint to remove
+    pub fn remove_breakpoint(&mut self, label: &str, index: usize) -> bool {
+        match self.locate(label, index) {
+            Ok(pc) => self.breakpoints.remove(&pc),
+            Err(_) => false,
+        }
+    }
+
+    /// Block label and operation index of the next operation to execute,
+    /// or None if the unit has exited
+    pub fn position(&self) -> Option<(&BlockLabel, usize)> {
+        if self.exited.is_some() {
+            return None;
+        }
+
+        let block = &self.exec.unit.blocks[self.block];
+        Some((&block.label, self.pc - block.start))
+    }
+
+    /// Guest PC and length of the guest instruction being executed,
+    /// or None if no instruction marker has been reached
+    pub fn guest_instruction(&self) -> Option<(u64, u8)> {
+        self.frame.instruction
+    }
+
+    pub fn state(&self) -> &State {
+        self.frame.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut State {
+        self.frame.state
+    }
+
+    /// Returns None if there is no such register, or if it is a vector register
+    pub fn register(&self, idx: u8) -> Option<IntImmed> {
+        let reg = self
+            .regs
+            .get(idx as usize)
+            .filter(|reg| !reg.ty.is_vector())?;
+        Some(unsafe { reg.read(self.frame.state) })
+    }
+
+    /// Writes a register, truncating the value to the register's width.
+    /// Returns false if there is no such register, or if it is a vector register.
+    pub fn set_register(&mut self, idx: u8, value: IntImmed) -> bool {
+        match self.regs.get(idx as usize) {
+            Some(reg) if !reg.ty.is_vector() => {
+                unsafe { reg.write(value.to_u128(), self.frame.state) };
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the scratch value, if an integer has been written to it
+    pub fn scratch(&self, idx: u8) -> Option<IntImmed> {
+        self.frame.scratch.get(idx)
+    }
+
+    pub fn set_scratch(&mut self, idx: u8, value: IntImmed) {
+        self.frame.scratch.write(idx, value);
+    }
+
+    /// Executes the next operation, returning whether it was a block terminator,
+    /// or None once the unit has exited
+    unsafe fn step(&mut self) -> Option<bool> {
+        if self.exited.is_some() {
+            return None;
+        }
+
+        let action = match self.exec.step(&mut self.frame, self.block, self.pc) {
+            Ok(action) => action,
+            Err(trap) => {
+                let reason = ExitReason {
+                    status: ExitStatus::Trap(trap),
+                    block: self.exec.unit.blocks[self.block].label.clone(),
+                    ops_retired: self.ops_retired,
+                    blocks_retired: self.blocks_retired,
+                    guest_pc: self.frame.guest_pc(),
+                    instructions_retired: self.frame.instructions_retired,
+                };
+                self.exited = Some(reason);
+                return None;
+            }
+        };
+        self.ops_retired += 1;
+
+        let status = match action {
+            None => {
+                self.pc += 1;
+                return Some(false);
+            }
+            Some(ExitAction::Exit(code)) => ExitStatus::Exit(code),
+            Some(ExitAction::Jump(addr)) => ExitStatus::Jump(addr),
+            Some(ExitAction::BranchTo(idx)) => {
+                self.blocks_retired += 1;
+                self.block = idx;
+                self.pc = self.exec.unit.blocks[idx].start;
+                return Some(true);
+            }
+        };
+
+        let guest_pc = self.frame.guest_pc();
+        self.frame.retire_instruction();
+        self.exited = Some(ExitReason {
+            status,
+            block: self.exec.unit.blocks[self.block].label.clone(),
+            ops_retired: self.ops_retired,
+            blocks_retired: self.blocks_retired + 1,
+            guest_pc,
+            instructions_retired: self.frame.instructions_retired,
+        });
+        None
+    }
+
+    /// Executes until `done` returns true after an operation, or until a breakpoint or exit
+    unsafe fn run_until(&mut self, mut done: impl FnMut(bool) -> bool) -> DebugStop {
+        loop {
+            match self.step() {
+                None => return DebugStop::Exited(self.exited.clone().unwrap()),
+                Some(terminated) if done(terminated) => return DebugStop::Step,
+                Some(_) if self.breakpoints.contains(&self.pc) => return DebugStop::Breakpoint,
+                Some(_) => (),
+            }
+        }
+    }
+
+    /// Executes a single operation
+    ///
+    /// # Safety
+    /// See [`crate::backend::Executable::execute`]
+    pub unsafe fn step_op(&mut self) -> DebugStop {
+        self.run_until(|_| true)
+    }
+
+    /// Executes up to and including the terminator of the current block,
+    /// stopping early at breakpoints
+    ///
+    /// # Safety
+    /// See [`crate::backend::Executable::execute`]
+    pub unsafe fn step_block(&mut self) -> DebugStop {
+        self.run_until(|terminated| terminated)
+    }
+
+    /// Executes until a breakpoint is reached or the unit exits
+    ///
+    /// # Safety
+    /// See [`crate::backend::Executable::execute`]
+    pub unsafe fn run(&mut self) -> DebugStop {
+        self.run_until(|_| false)
+    }
+}
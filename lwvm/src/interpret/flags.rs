@@ -0,0 +1,108 @@
+use crate::{
+    ir::types::{Condition, FlagOp},
+    IntImmed,
+};
+
+/// The operands of the last flag-setting operation. Individual flags are
+/// only computed when a condition is read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct LazyFlags {
+    op: FlagOp,
+    arg1: u128,
+    arg2: u128,
+    width: u8,
+}
+
+impl LazyFlags {
+    pub(crate) fn new(op: FlagOp, arg1: IntImmed, arg2: IntImmed, signed: bool) -> Self {
+        let (arg1, arg2) = IntImmed::upcast(&arg1, &arg2, signed);
+        Self {
+            op,
+            arg1: arg1.to_u128(),
+            arg2: arg2.to_u128(),
+            width: arg1.size(),
+        }
+    }
+
+    fn mask(&self) -> u128 {
+        u128::MAX >> (128 - self.width as u32)
+    }
+
+    fn sign_bit(&self) -> u128 {
+        1 << (self.width - 1)
+    }
+
+    fn result(&self) -> u128 {
+        let result = match self.op {
+            FlagOp::Add => self.arg1.wrapping_add(self.arg2),
+            FlagOp::Sub => self.arg1.wrapping_sub(self.arg2),
+            FlagOp::And => self.arg1 & self.arg2,
+            FlagOp::Or => self.arg1 | self.arg2,
+            FlagOp::Xor => self.arg1 ^ self.arg2,
+        };
+        result & self.mask()
+    }
+
+    fn zero(&self) -> bool {
+        self.result() == 0
+    }
+
+    fn sign(&self) -> bool {
+        self.result() & self.sign_bit() != 0
+    }
+
+    fn carry(&self) -> bool {
+        match self.op {
+            FlagOp::Add => {
+                let (sum, overflow) = self.arg1.overflowing_add(self.arg2);
+                overflow || sum > self.mask()
+            }
+            FlagOp::Sub => self.arg1 < self.arg2,
+            FlagOp::And | FlagOp::Or | FlagOp::Xor => false,
+        }
+    }
+
+    fn overflow(&self) -> bool {
+        let (a, b, result) = (self.arg1, self.arg2, self.result());
+        let overflow = match self.op {
+            FlagOp::Add => (a ^ result) & (b ^ result),
+            FlagOp::Sub => (a ^ b) & (a ^ result),
+            FlagOp::And | FlagOp::Or | FlagOp::Xor => 0,
+        };
+        overflow & self.sign_bit() != 0
+    }
+
+    fn parity(&self) -> bool {
+        (self.result() as u8).count_ones() % 2 == 0
+    }
+
+    fn aux_carry(&self) -> bool {
+        match self.op {
+            FlagOp::Add | FlagOp::Sub => (self.arg1 ^ self.arg2 ^ self.result()) & 0x10 != 0,
+            FlagOp::And | FlagOp::Or | FlagOp::Xor => false,
+        }
+    }
+
+    pub(crate) fn test(&self, cond: Condition) -> bool {
+        match cond {
+            Condition::Zero => self.zero(),
+            Condition::NotZero => !self.zero(),
+            Condition::Sign => self.sign(),
+            Condition::NotSign => !self.sign(),
+            Condition::Carry => self.carry(),
+            Condition::NotCarry => !self.carry(),
+            Condition::Overflow => self.overflow(),
+            Condition::NotOverflow => !self.overflow(),
+            Condition::ParityEven => self.parity(),
+            Condition::ParityOdd => !self.parity(),
+            Condition::AuxCarry => self.aux_carry(),
+            Condition::NotAuxCarry => !self.aux_carry(),
+            Condition::UnsignedGreater => !self.carry() && !self.zero(),
+            Condition::UnsignedLessEqual => self.carry() || self.zero(),
+            Condition::SignedLess => self.sign() != self.overflow(),
+            Condition::SignedGreaterEqual => self.sign() == self.overflow(),
+            Condition::SignedGreater => !self.zero() && self.sign() == self.overflow(),
+            Condition::SignedLessEqual => self.zero() || self.sign() != self.overflow(),
+        }
+    }
+}
@@ -0,0 +1,989 @@
+use crate::{
+    backend::Trap,
+    host::{HostFunction, HostFunctions},
+    ir::{
+        ops::Operation,
+        reg::Register,
+        types::{
+            AtomicOp, BlockLabel, Comparator, Condition, Endianness, FlagOp, FloatComparator,
+            FloatImmed, FloatType, IntType, LaneType, MemoryOrdering, MultSignedness, RValue,
+            StructType, StructTypeImmed, TypeImmed, VectorImmed, VectorType,
+        },
+    },
+    mem::{AtomicAccess, HostAccess},
+    unit::TranslationUnit,
+    IntImmed, LValue,
+};
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// A source operand with its register already resolved
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Operand {
+    Immediate(IntImmed),
+    Register(Register),
+    Scratch(u8),
+}
+
+/// A vector source operand with its register already resolved
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum VectorOperand {
+    Immediate(VectorImmed),
+    Register(Register),
+    Scratch(u8),
+}
+
+/// A struct source operand. Registers cannot hold structs, so
+/// struct values only live in immediates and scratch values.
+#[derive(Debug, Clone)]
+pub(crate) enum StructOperand {
+    Immediate(StructTypeImmed),
+    Scratch(u8),
+}
+
+/// A source operand that may hold an integer or a struct
+#[derive(Debug, Clone)]
+pub(crate) enum ValueOperand {
+    Immediate(TypeImmed),
+    Register(Register),
+    Scratch(u8),
+}
+
+/// A destination with its register already resolved. Registers keep
+/// their index so that they can be reported back as an LValue.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Dest {
+    Register(u8, Register),
+    Scratch(u8),
+}
+
+impl Dest {
+    pub(crate) fn to_lvalue(self) -> LValue {
+        match self {
+            Self::Register(r, _) => LValue::Register(r),
+            Self::Scratch(s) => LValue::Scratch(s),
+        }
+    }
+
+    pub(crate) fn to_operand(self) -> Operand {
+        match self {
+            Self::Register(_, reg) => Operand::Register(reg),
+            Self::Scratch(s) => Operand::Scratch(s),
+        }
+    }
+}
+
+/// Interpreter form of an Operation. Branch targets are
+/// indices into LoweredUnit::blocks, float immediates
+/// are replaced by their bit pattern, and function calls
+/// hold the function they resolved to.
+#[derive(Debug, Clone)]
+pub(crate) enum Inst {
+    Add(Dest, Operand, Operand, bool, bool),
+    Sub(Dest, Operand, Operand, bool, bool),
+
+    Mult(Dest, Operand, Operand, bool),
+    Div(Dest, Operand, Operand, bool),
+    Rem(Dest, Operand, Operand, bool),
+
+    AddCarry(Dest, Dest, Operand, Operand, Operand, bool),
+    SubBorrow(Dest, Dest, Operand, Operand, Operand, bool),
+    AddOverflow(Dest, Dest, Operand, Operand, bool),
+    SubOverflow(Dest, Dest, Operand, Operand, bool),
+    MultOverflow(Dest, Dest, Operand, Operand, bool),
+    MultWide(Dest, Dest, Operand, Operand, MultSignedness),
+    MultHigh(Dest, Operand, Operand, MultSignedness),
+
+    LShift(Dest, Operand, Operand),
+    RShift(Dest, Operand, Operand, bool),
+    SignExtend(Dest, Operand, IntType),
+    ZeroExtend(Dest, Operand, IntType),
+
+    RotateLeft(Dest, Operand, Operand),
+    RotateRight(Dest, Operand, Operand),
+    RotateLeftCarry(Dest, Dest, Operand, Operand, Operand),
+    RotateRightCarry(Dest, Dest, Operand, Operand, Operand),
+    CountLeadingZeros(Dest, Operand),
+    CountTrailingZeros(Dest, Operand),
+    PopCount(Dest, Operand),
+    ByteSwap(Dest, Operand),
+    BitReverse(Dest, Operand),
+
+    And(Dest, Operand, Operand, bool),
+    Or(Dest, Operand, Operand, bool),
+    Xor(Dest, Operand, Operand, bool),
+    Not(Dest, Operand),
+
+    FAdd(Dest, Operand, Operand),
+    FSub(Dest, Operand, Operand),
+    FMult(Dest, Operand, Operand),
+    FDiv(Dest, Operand, Operand),
+    FSqrt(Dest, Operand),
+    FCmp(Dest, FloatComparator, Operand, Operand),
+
+    IntToFloat(Dest, Operand, FloatType, bool),
+    FloatToInt(Dest, Operand, IntType, bool),
+    FloatToBits(Dest, Operand),
+    BitsToFloat(Dest, Operand),
+
+    VAdd(Dest, VectorOperand, VectorOperand, LaneType),
+    VSub(Dest, VectorOperand, VectorOperand, LaneType),
+    VMult(Dest, VectorOperand, VectorOperand, LaneType),
+    VMin(Dest, VectorOperand, VectorOperand, LaneType, bool),
+    VMax(Dest, VectorOperand, VectorOperand, LaneType, bool),
+    VCmp(Dest, Comparator, VectorOperand, VectorOperand, LaneType),
+    VShiftLeft(Dest, VectorOperand, Operand, LaneType),
+    VShiftRight(Dest, VectorOperand, Operand, LaneType, bool),
+    VShuffle(Dest, VectorOperand, VectorOperand, Vec<u8>, LaneType),
+    VInsertLane(Dest, VectorOperand, Operand, u8, LaneType),
+    VExtractLane(Dest, VectorOperand, u8, LaneType),
+    VSplat(Dest, Operand, LaneType, VectorType),
+
+    HostReadMem(Dest, Operand, HostAccess),
+    HostWriteMem(Operand, Operand, HostAccess),
+    GuestReadMem(Dest, Operand, u8, Endianness),
+    GuestWriteMem(Operand, Operand, u8, Endianness),
+    GuestReadStruct(u8, Operand, StructType),
+    GuestWriteStruct(Operand, StructOperand),
+    GuestAtomicRmw(Dest, AtomicOp, Operand, Operand, AtomicAccess),
+    GuestCompareExchange(Dest, Operand, Operand, Operand, AtomicAccess),
+    GuestLoadLinked(Dest, Operand, AtomicAccess),
+    GuestStoreConditional(Dest, Operand, Operand, AtomicAccess),
+    Fence(MemoryOrdering),
+    Call(Option<Dest>, Rc<HostFunction>, Vec<Operand>),
+
+    ExtractField(Dest, StructOperand, u8),
+    InsertField(u8, StructOperand, ValueOperand, u8),
+
+    ICmp(Dest, Comparator, Operand, Operand),
+    Select(Operand, Dest, Operand, Operand),
+
+    SetFlags(FlagOp, Operand, Operand, bool),
+    ReadCondition(Dest, Condition),
+    SelectCondition(Condition, Dest, Operand, Operand),
+
+    Branch(Operand, usize, usize),
+    BranchCondition(Condition, usize, usize),
+    CompareBranch(Comparator, Operand, Operand, usize, usize),
+    /// Jump table sorted by guest address, with one entry per address
+    IndirectBranch(Operand, Box<[(u64, usize)]>),
+    Exit(u8),
+
+    Instruction(u64, u8),
+
+    /// Stands in for an operation that can never execute, such as
+    /// one that references a register the state does not have
+    Trap(Trap),
+}
+
+impl Inst {
+    /// Mnemonic used to identify the instruction when tracing
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Add(..) => "add",
+            Self::Sub(..) => "sub",
+            Self::Mult(..) => "mult",
+            Self::Div(..) => "div",
+            Self::Rem(..) => "rem",
+            Self::AddCarry(..) => "add_carry",
+            Self::SubBorrow(..) => "sub_borrow",
+            Self::AddOverflow(..) => "add_overflow",
+            Self::SubOverflow(..) => "sub_overflow",
+            Self::MultOverflow(..) => "mult_overflow",
+            Self::MultWide(..) => "mult_wide",
+            Self::MultHigh(..) => "mult_high",
+            Self::LShift(..) => "shift_left",
+            Self::RShift(..) => "shift_right",
+            Self::SignExtend(..) => "sign_extend",
+            Self::ZeroExtend(..) => "zero_extend",
+            Self::RotateLeft(..) => "rotate_left",
+            Self::RotateRight(..) => "rotate_right",
+            Self::RotateLeftCarry(..) => "rotate_left_carry",
+            Self::RotateRightCarry(..) => "rotate_right_carry",
+            Self::CountLeadingZeros(..) => "count_leading_zeros",
+            Self::CountTrailingZeros(..) => "count_trailing_zeros",
+            Self::PopCount(..) => "pop_count",
+            Self::ByteSwap(..) => "byte_swap",
+            Self::BitReverse(..) => "bit_reverse",
+            Self::And(..) => "and",
+            Self::Or(..) => "or",
+            Self::Xor(..) => "xor",
+            Self::Not(..) => "not",
+            Self::FAdd(..) => "float_add",
+            Self::FSub(..) => "float_sub",
+            Self::FMult(..) => "float_mult",
+            Self::FDiv(..) => "float_div",
+            Self::FSqrt(..) => "float_sqrt",
+            Self::FCmp(..) => "float_cmp",
+            Self::IntToFloat(..) => "int_to_float",
+            Self::FloatToInt(..) => "float_to_int",
+            Self::FloatToBits(..) => "float_to_bits",
+            Self::BitsToFloat(..) => "bits_to_float",
+            Self::VAdd(..) => "vector_add",
+            Self::VSub(..) => "vector_sub",
+            Self::VMult(..) => "vector_mult",
+            Self::VMin(..) => "vector_min",
+            Self::VMax(..) => "vector_max",
+            Self::VCmp(..) => "vector_cmp",
+            Self::VShiftLeft(..) => "vector_shift_left",
+            Self::VShiftRight(..) => "vector_shift_right",
+            Self::VShuffle(..) => "vector_shuffle",
+            Self::VInsertLane(..) => "vector_insert_lane",
+            Self::VExtractLane(..) => "vector_extract_lane",
+            Self::VSplat(..) => "vector_splat",
+            Self::HostReadMem(..) => "host_mem_read",
+            Self::HostWriteMem(..) => "host_mem_write",
+            Self::GuestReadMem(..) => "guest_mem_read",
+            Self::GuestWriteMem(..) => "guest_mem_write",
+            Self::GuestReadStruct(..) => "guest_read_struct",
+            Self::GuestWriteStruct(..) => "guest_write_struct",
+            Self::GuestAtomicRmw(..) => "guest_atomic_rmw",
+            Self::GuestCompareExchange(..) => "guest_compare_exchange",
+            Self::GuestLoadLinked(..) => "guest_load_linked",
+            Self::GuestStoreConditional(..) => "guest_store_conditional",
+            Self::Fence(_) => "fence",
+            Self::Call(..) => "fn_call",
+            Self::ExtractField(..) => "extract_field",
+            Self::InsertField(..) => "insert_field",
+            Self::ICmp(..) => "int_cmp",
+            Self::Select(..) => "select",
+            Self::SetFlags(..) => "set_flags",
+            Self::ReadCondition(..) => "read_condition",
+            Self::SelectCondition(..) => "select_condition",
+            Self::Branch(..) => "branch",
+            Self::BranchCondition(..) => "branch_condition",
+            Self::CompareBranch(..) => "compare_branch",
+            Self::IndirectBranch(..) => "indirect_branch",
+            Self::Exit(..) => "exit",
+            Self::Instruction(..) => "instruction",
+            Self::Trap(_) => "trap",
+        }
+    }
+
+    /// Integer and float source operands of the instruction, in the order
+    /// they appear in it. Vector and struct operands are not included.
+    pub(crate) fn sources(&self) -> Vec<Operand> {
+        match self {
+            Self::Add(_, a, b, ..)
+            | Self::Sub(_, a, b, ..)
+            | Self::Mult(_, a, b, _)
+            | Self::Div(_, a, b, _)
+            | Self::Rem(_, a, b, _)
+            | Self::LShift(_, a, b)
+            | Self::RShift(_, a, b, _)
+            | Self::RotateLeft(_, a, b)
+            | Self::RotateRight(_, a, b)
+            | Self::And(_, a, b, _)
+            | Self::Or(_, a, b, _)
+            | Self::Xor(_, a, b, _)
+            | Self::FAdd(_, a, b)
+            | Self::FSub(_, a, b)
+            | Self::FMult(_, a, b)
+            | Self::FDiv(_, a, b)
+            | Self::FCmp(_, _, a, b)
+            | Self::HostWriteMem(a, b, _)
+            | Self::GuestWriteMem(a, b, ..)
+            | Self::GuestAtomicRmw(_, _, a, b, ..)
+            | Self::GuestStoreConditional(_, a, b, ..)
+            | Self::ICmp(_, _, a, b)
+            | Self::SetFlags(_, a, b, _)
+            | Self::SelectCondition(_, _, a, b)
+            | Self::CompareBranch(_, a, b, ..) => vec![*a, *b],
+            Self::SignExtend(_, a, _)
+            | Self::ZeroExtend(_, a, _)
+            | Self::Not(_, a)
+            | Self::CountLeadingZeros(_, a)
+            | Self::CountTrailingZeros(_, a)
+            | Self::PopCount(_, a)
+            | Self::ByteSwap(_, a)
+            | Self::BitReverse(_, a)
+            | Self::FSqrt(_, a)
+            | Self::IntToFloat(_, a, ..)
+            | Self::FloatToInt(_, a, ..)
+            | Self::FloatToBits(_, a)
+            | Self::BitsToFloat(_, a)
+            | Self::VShiftLeft(_, _, a, _)
+            | Self::VShiftRight(_, _, a, ..)
+            | Self::VInsertLane(_, _, a, ..)
+            | Self::VSplat(_, a, ..)
+            | Self::HostReadMem(_, a, _)
+            | Self::GuestReadMem(_, a, ..)
+            | Self::GuestLoadLinked(_, a, ..)
+            | Self::GuestReadStruct(_, a, _)
+            | Self::GuestWriteStruct(a, _)
+            | Self::Branch(a, _, _)
+            | Self::IndirectBranch(a, _) => vec![*a],
+            Self::Select(c, _, a, b) => vec![*c, *a, *b],
+            Self::AddCarry(_, _, a, b, c, _)
+            | Self::SubBorrow(_, _, a, b, c, _)
+            | Self::RotateLeftCarry(_, _, a, b, c)
+            | Self::RotateRightCarry(_, _, a, b, c)
+            | Self::GuestCompareExchange(_, a, b, c, ..) => vec![*a, *b, *c],
+            Self::AddOverflow(_, _, a, b, _)
+            | Self::SubOverflow(_, _, a, b, _)
+            | Self::MultOverflow(_, _, a, b, _)
+            | Self::MultWide(_, _, a, b, _)
+            | Self::MultHigh(_, a, b, _) => vec![*a, *b],
+            Self::Call(_, _, args) => args.clone(),
+            Self::VAdd(..)
+            | Self::VSub(..)
+            | Self::VMult(..)
+            | Self::VMin(..)
+            | Self::VMax(..)
+            | Self::VCmp(..)
+            | Self::VShuffle(..)
+            | Self::VExtractLane(..)
+            | Self::ExtractField(..)
+            | Self::InsertField(..)
+            | Self::ReadCondition(..)
+            | Self::BranchCondition(..)
+            | Self::Fence(_)
+            | Self::Exit(_)
+            | Self::Instruction(..)
+            | Self::Trap(_) => vec![],
+        }
+    }
+
+    /// Integer or float destination of the instruction. Field extracts are
+    /// not included, as they may produce a struct.
+    pub(crate) fn dest(&self) -> Option<Dest> {
+        match self {
+            Self::Add(d, ..)
+            | Self::Sub(d, ..)
+            | Self::Mult(d, ..)
+            | Self::Div(d, ..)
+            | Self::Rem(d, ..)
+            | Self::AddCarry(d, ..)
+            | Self::SubBorrow(d, ..)
+            | Self::AddOverflow(d, ..)
+            | Self::SubOverflow(d, ..)
+            | Self::MultOverflow(d, ..)
+            | Self::MultWide(d, ..)
+            | Self::MultHigh(d, ..)
+            | Self::LShift(d, ..)
+            | Self::RShift(d, ..)
+            | Self::SignExtend(d, ..)
+            | Self::ZeroExtend(d, ..)
+            | Self::RotateLeft(d, ..)
+            | Self::RotateRight(d, ..)
+            | Self::RotateLeftCarry(d, ..)
+            | Self::RotateRightCarry(d, ..)
+            | Self::CountLeadingZeros(d, ..)
+            | Self::CountTrailingZeros(d, ..)
+            | Self::PopCount(d, ..)
+            | Self::ByteSwap(d, ..)
+            | Self::BitReverse(d, ..)
+            | Self::And(d, ..)
+            | Self::Or(d, ..)
+            | Self::Xor(d, ..)
+            | Self::Not(d, ..)
+            | Self::FAdd(d, ..)
+            | Self::FSub(d, ..)
+            | Self::FMult(d, ..)
+            | Self::FDiv(d, ..)
+            | Self::FSqrt(d, ..)
+            | Self::FCmp(d, ..)
+            | Self::IntToFloat(d, ..)
+            | Self::FloatToInt(d, ..)
+            | Self::FloatToBits(d, ..)
+            | Self::BitsToFloat(d, ..)
+            | Self::VExtractLane(d, ..)
+            | Self::HostReadMem(d, ..)
+            | Self::GuestReadMem(d, ..)
+            | Self::GuestAtomicRmw(d, ..)
+            | Self::GuestCompareExchange(d, ..)
+            | Self::GuestLoadLinked(d, ..)
+            | Self::GuestStoreConditional(d, ..)
+            | Self::ICmp(d, ..)
+            | Self::Select(_, d, ..)
+            | Self::ReadCondition(d, _)
+            | Self::SelectCondition(_, d, ..) => Some(*d),
+            Self::Call(d, ..) => *d,
+            Self::VAdd(..)
+            | Self::VSub(..)
+            | Self::VMult(..)
+            | Self::VMin(..)
+            | Self::VMax(..)
+            | Self::VCmp(..)
+            | Self::VShiftLeft(..)
+            | Self::VShiftRight(..)
+            | Self::VShuffle(..)
+            | Self::VInsertLane(..)
+            | Self::VSplat(..)
+            | Self::HostWriteMem(..)
+            | Self::GuestWriteMem(..)
+            | Self::GuestReadStruct(..)
+            | Self::GuestWriteStruct(..)
+            | Self::Fence(_)
+            | Self::ExtractField(..)
+            | Self::InsertField(..)
+            | Self::SetFlags(..)
+            | Self::Branch(..)
+            | Self::BranchCondition(..)
+            | Self::CompareBranch(..)
+            | Self::IndirectBranch(..)
+            | Self::Exit(_)
+            | Self::Instruction(..)
+            | Self::Trap(_) => None,
+        }
+    }
+}
+
+pub(crate) struct LoweredBlock {
+    pub(crate) label: BlockLabel,
+    /// Index of the first instruction of the block in LoweredUnit::insts
+    pub(crate) start: usize,
+}
+
+/// A translation unit flattened into a single instruction array,
+/// with the blocks laid out in the order they were added
+pub(crate) struct LoweredUnit {
+    pub(crate) insts: Vec<Inst>,
+    pub(crate) blocks: Vec<LoweredBlock>,
+    pub(crate) entry: usize,
+    /// Byte order of guest memory accesses that do not specify one
+    pub(crate) endianness: Endianness,
+    /// Labels branched to that have no block. A branch target of
+    /// `blocks.len() + i` refers to `unresolved[i]`.
+    pub(crate) unresolved: Vec<BlockLabel>,
+    /// One more than the highest scratch index the unit uses
+    pub(crate) scratch_slots: usize,
+    /// Revision of the translation unit this was lowered from
+    pub(crate) revision: u64,
+}
+
+impl LoweredUnit {
+    /// The entry block and the index of its first instruction
+    pub(crate) fn entry_point(&self) -> (usize, usize) {
+        (self.entry, self.blocks[self.entry].start)
+    }
+
+    /// Returns the trap raised by branching to `target`, if it has no block
+    pub(crate) fn check_target(&self, target: usize) -> Result<usize, Trap> {
+        match target.checked_sub(self.blocks.len()) {
+            Some(idx) => Err(Trap::UnknownLabel(self.unresolved[idx].clone())),
+            None => Ok(target),
+        }
+    }
+}
+
+struct Lowerer<'a> {
+    unit: &'a TranslationUnit,
+    regs: &'a [Register],
+    functions: &'a HostFunctions,
+    unresolved: Vec<BlockLabel>,
+    scratch_slots: Cell<usize>,
+}
+
+impl Lowerer<'_> {
+    /// Resolves an LValue that must be a vector register if `vector` is
+    /// set, and a non-vector register otherwise
+    fn typed_dest(&self, lv: &LValue, vector: bool) -> Result<Dest, Trap> {
+        match lv {
+            LValue::Register(r) => {
+                let reg = self
+                    .regs
+                    .get(*r as usize)
+                    .ok_or(Trap::InvalidRegister(*r))?;
+                if reg.ty.is_vector() != vector {
+                    return Err(Trap::TypeMismatch);
+                }
+                Ok(Dest::Register(*r, *reg))
+            }
+            LValue::Scratch(s) => {
+                let slots = self.scratch_slots.get().max(*s as usize + 1);
+                self.scratch_slots.set(slots);
+                Ok(Dest::Scratch(*s))
+            }
+        }
+    }
+
+    fn dest(&self, lv: &LValue) -> Result<Dest, Trap> {
+        self.typed_dest(lv, false)
+    }
+
+    fn vector_dest(&self, lv: &LValue) -> Result<Dest, Trap> {
+        self.typed_dest(lv, true)
+    }
+
+    fn vector_operand(&self, rv: &RValue<VectorImmed>) -> Result<VectorOperand, Trap> {
+        match rv {
+            RValue::Immediate(v) => Ok(VectorOperand::Immediate(*v)),
+            RValue::LValue(lv) => Ok(match self.vector_dest(lv)? {
+                Dest::Register(_, reg) => VectorOperand::Register(reg),
+                Dest::Scratch(s) => VectorOperand::Scratch(s),
+            }),
+        }
+    }
+
+    /// Resolves an LValue that must be able to hold a struct,
+    /// which only scratch values can
+    fn struct_dest(&self, lv: &LValue) -> Result<u8, Trap> {
+        match self.dest(lv)? {
+            Dest::Register(..) => Err(Trap::TypeMismatch),
+            Dest::Scratch(s) => Ok(s),
+        }
+    }
+
+    fn struct_operand(&self, rv: &RValue<StructTypeImmed>) -> Result<StructOperand, Trap> {
+        match rv {
+            RValue::Immediate(s) => Ok(StructOperand::Immediate(s.clone())),
+            RValue::LValue(lv) => Ok(StructOperand::Scratch(self.struct_dest(lv)?)),
+        }
+    }
+
+    fn value_operand(&self, rv: &RValue<TypeImmed>) -> Result<ValueOperand, Trap> {
+        match rv {
+            RValue::Immediate(v) => Ok(ValueOperand::Immediate(v.clone())),
+            RValue::LValue(lv) => Ok(match self.dest(lv)? {
+                Dest::Register(_, reg) => ValueOperand::Register(reg),
+                Dest::Scratch(s) => ValueOperand::Scratch(s),
+            }),
+        }
+    }
+
+    fn operand(&self, rv: &RValue<IntImmed>) -> Result<Operand, Trap> {
+        match rv {
+            RValue::Immediate(i) => Ok(Operand::Immediate(*i)),
+            RValue::LValue(lv) => Ok(self.dest(lv)?.to_operand()),
+        }
+    }
+
+    fn float_operand(&self, rv: &RValue<FloatImmed>) -> Result<Operand, Trap> {
+        match rv {
+            RValue::Immediate(f) => Ok(Operand::Immediate(f.to_bits())),
+            RValue::LValue(lv) => Ok(self.dest(lv)?.to_operand()),
+        }
+    }
+
+    /// Resolves a branch target. Unknown labels are given an index past
+    /// the last block, so that branching to them traps.
+    fn target(&mut self, label: &BlockLabel) -> usize {
+        if let Some(idx) = self.unit.labels.get(label) {
+            return *idx;
+        }
+
+        let idx = match self.unresolved.iter().position(|l| l == label) {
+            Some(idx) => idx,
+            None => {
+                self.unresolved.push(label.clone());
+                self.unresolved.len() - 1
+            }
+        };
+        self.unit.blocks.len() + idx
+    }
+
+    /// Atomic accesses always use the unit's byte order
+    fn atomic_access(&self, size: u8, ordering: MemoryOrdering) -> AtomicAccess {
+        AtomicAccess {
+            size,
+            ordering,
+            endianness: self.unit.endianness,
+        }
+    }
+
+    /// Lowers an operation, or returns the trap it raises when executed
+    fn lower_op(&mut self, op: &Operation) -> Result<Inst, Trap> {
+        Ok(match op {
+            Operation::Add(d, a, b, s, f) => {
+                Inst::Add(self.dest(d)?, self.operand(a)?, self.operand(b)?, *s, *f)
+            }
+            Operation::Sub(d, a, b, s, f) => {
+                Inst::Sub(self.dest(d)?, self.operand(a)?, self.operand(b)?, *s, *f)
+            }
+            Operation::Mult(d, a, b, s) => {
+                Inst::Mult(self.dest(d)?, self.operand(a)?, self.operand(b)?, *s)
+            }
+            Operation::Div(d, a, b, s) => {
+                Inst::Div(self.dest(d)?, self.operand(a)?, self.operand(b)?, *s)
+            }
+            Operation::Rem(d, a, b, s) => {
+                Inst::Rem(self.dest(d)?, self.operand(a)?, self.operand(b)?, *s)
+            }
+            Operation::AddCarry(d, o, a, b, c, s) => Inst::AddCarry(
+                self.dest(d)?,
+                self.dest(o)?,
+                self.operand(a)?,
+                self.operand(b)?,
+                self.operand(c)?,
+                *s,
+            ),
+            Operation::SubBorrow(d, o, a, b, c, s) => Inst::SubBorrow(
+                self.dest(d)?,
+                self.dest(o)?,
+                self.operand(a)?,
+                self.operand(b)?,
+                self.operand(c)?,
+                *s,
+            ),
+            Operation::AddOverflow(d, o, a, b, s) => Inst::AddOverflow(
+                self.dest(d)?,
+                self.dest(o)?,
+                self.operand(a)?,
+                self.operand(b)?,
+                *s,
+            ),
+            Operation::SubOverflow(d, o, a, b, s) => Inst::SubOverflow(
+                self.dest(d)?,
+                self.dest(o)?,
+                self.operand(a)?,
+                self.operand(b)?,
+                *s,
+            ),
+            Operation::MultOverflow(d, o, a, b, s) => Inst::MultOverflow(
+                self.dest(d)?,
+                self.dest(o)?,
+                self.operand(a)?,
+                self.operand(b)?,
+                *s,
+            ),
+            Operation::MultWide(lo, hi, a, b, s) => Inst::MultWide(
+                self.dest(lo)?,
+                self.dest(hi)?,
+                self.operand(a)?,
+                self.operand(b)?,
+                *s,
+            ),
+            Operation::MultHigh(d, a, b, s) => {
+                Inst::MultHigh(self.dest(d)?, self.operand(a)?, self.operand(b)?, *s)
+            }
+            Operation::LShift(d, a, b) => {
+                Inst::LShift(self.dest(d)?, self.operand(a)?, self.operand(b)?)
+            }
+            Operation::RShift(d, a, b, s) => {
+                Inst::RShift(self.dest(d)?, self.operand(a)?, self.operand(b)?, *s)
+            }
+            Operation::SignExtend(d, a, ty) => {
+                Inst::SignExtend(self.dest(d)?, self.operand(a)?, *ty)
+            }
+            Operation::ZeroExtend(d, a, ty) => {
+                Inst::ZeroExtend(self.dest(d)?, self.operand(a)?, *ty)
+            }
+            Operation::RotateLeft(d, a, b) => {
+                Inst::RotateLeft(self.dest(d)?, self.operand(a)?, self.operand(b)?)
+            }
+            Operation::RotateRight(d, a, b) => {
+                Inst::RotateRight(self.dest(d)?, self.operand(a)?, self.operand(b)?)
+            }
+            Operation::RotateLeftCarry(d, c_out, a, b, c) => Inst::RotateLeftCarry(
+                self.dest(d)?,
+                self.dest(c_out)?,
+                self.operand(a)?,
+                self.operand(b)?,
+                self.operand(c)?,
+            ),
+            Operation::RotateRightCarry(d, c_out, a, b, c) => Inst::RotateRightCarry(
+                self.dest(d)?,
+                self.dest(c_out)?,
+                self.operand(a)?,
+                self.operand(b)?,
+                self.operand(c)?,
+            ),
+            Operation::CountLeadingZeros(d, a) => {
+                Inst::CountLeadingZeros(self.dest(d)?, self.operand(a)?)
+            }
+            Operation::CountTrailingZeros(d, a) => {
+                Inst::CountTrailingZeros(self.dest(d)?, self.operand(a)?)
+            }
+            Operation::PopCount(d, a) => Inst::PopCount(self.dest(d)?, self.operand(a)?),
+            Operation::ByteSwap(d, a) => Inst::ByteSwap(self.dest(d)?, self.operand(a)?),
+            Operation::BitReverse(d, a) => Inst::BitReverse(self.dest(d)?, self.operand(a)?),
+            Operation::And(d, a, b, f) => {
+                Inst::And(self.dest(d)?, self.operand(a)?, self.operand(b)?, *f)
+            }
+            Operation::Or(d, a, b, f) => {
+                Inst::Or(self.dest(d)?, self.operand(a)?, self.operand(b)?, *f)
+            }
+            Operation::Xor(d, a, b, f) => {
+                Inst::Xor(self.dest(d)?, self.operand(a)?, self.operand(b)?, *f)
+            }
+            Operation::Not(d, a) => Inst::Not(self.dest(d)?, self.operand(a)?),
+            Operation::FAdd(d, a, b) => Inst::FAdd(
+                self.dest(d)?,
+                self.float_operand(a)?,
+                self.float_operand(b)?,
+            ),
+            Operation::FSub(d, a, b) => Inst::FSub(
+                self.dest(d)?,
+                self.float_operand(a)?,
+                self.float_operand(b)?,
+            ),
+            Operation::FMult(d, a, b) => Inst::FMult(
+                self.dest(d)?,
+                self.float_operand(a)?,
+                self.float_operand(b)?,
+            ),
+            Operation::FDiv(d, a, b) => Inst::FDiv(
+                self.dest(d)?,
+                self.float_operand(a)?,
+                self.float_operand(b)?,
+            ),
+            Operation::FSqrt(d, a) => Inst::FSqrt(self.dest(d)?, self.float_operand(a)?),
+            Operation::FCmp(d, cmp, a, b) => Inst::FCmp(
+                self.dest(d)?,
+                *cmp,
+                self.float_operand(a)?,
+                self.float_operand(b)?,
+            ),
+            Operation::IntToFloat(d, a, ty, s) => {
+                Inst::IntToFloat(self.dest(d)?, self.operand(a)?, *ty, *s)
+            }
+            Operation::FloatToInt(d, a, ty, s) => {
+                Inst::FloatToInt(self.dest(d)?, self.float_operand(a)?, *ty, *s)
+            }
+            Operation::FloatToBits(d, a) => {
+                Inst::FloatToBits(self.dest(d)?, self.float_operand(a)?)
+            }
+            Operation::BitsToFloat(d, a) => Inst::BitsToFloat(self.dest(d)?, self.operand(a)?),
+            Operation::VAdd(d, a, b, lane) => Inst::VAdd(
+                self.vector_dest(d)?,
+                self.vector_operand(a)?,
+                self.vector_operand(b)?,
+                *lane,
+            ),
+            Operation::VSub(d, a, b, lane) => Inst::VSub(
+                self.vector_dest(d)?,
+                self.vector_operand(a)?,
+                self.vector_operand(b)?,
+                *lane,
+            ),
+            Operation::VMult(d, a, b, lane) => Inst::VMult(
+                self.vector_dest(d)?,
+                self.vector_operand(a)?,
+                self.vector_operand(b)?,
+                *lane,
+            ),
+            Operation::VMin(d, a, b, lane, s) => Inst::VMin(
+                self.vector_dest(d)?,
+                self.vector_operand(a)?,
+                self.vector_operand(b)?,
+                *lane,
+                *s,
+            ),
+            Operation::VMax(d, a, b, lane, s) => Inst::VMax(
+                self.vector_dest(d)?,
+                self.vector_operand(a)?,
+                self.vector_operand(b)?,
+                *lane,
+                *s,
+            ),
+            Operation::VCmp(d, cmp, a, b, lane) => Inst::VCmp(
+                self.vector_dest(d)?,
+                *cmp,
+                self.vector_operand(a)?,
+                self.vector_operand(b)?,
+                *lane,
+            ),
+            Operation::VShiftLeft(d, a, b, lane) => Inst::VShiftLeft(
+                self.vector_dest(d)?,
+                self.vector_operand(a)?,
+                self.operand(b)?,
+                *lane,
+            ),
+            Operation::VShiftRight(d, a, b, lane, s) => Inst::VShiftRight(
+                self.vector_dest(d)?,
+                self.vector_operand(a)?,
+                self.operand(b)?,
+                *lane,
+                *s,
+            ),
+            Operation::VShuffle(d, a, b, indices, lane) => Inst::VShuffle(
+                self.vector_dest(d)?,
+                self.vector_operand(a)?,
+                self.vector_operand(b)?,
+                indices.clone(),
+                *lane,
+            ),
+            Operation::VInsertLane(d, v, a, idx, lane) => Inst::VInsertLane(
+                self.vector_dest(d)?,
+                self.vector_operand(v)?,
+                self.operand(a)?,
+                *idx,
+                *lane,
+            ),
+            Operation::VExtractLane(d, v, idx, lane) => {
+                Inst::VExtractLane(self.dest(d)?, self.vector_operand(v)?, *idx, *lane)
+            }
+            Operation::VSplat(d, a, lane, ty) => {
+                Inst::VSplat(self.vector_dest(d)?, self.operand(a)?, *lane, *ty)
+            }
+            Operation::HostReadMem(d, a, ty, aligned, volatile) => Inst::HostReadMem(
+                self.dest(d)?,
+                self.operand(a)?,
+                HostAccess::new(*ty, *aligned, *volatile),
+            ),
+            Operation::HostWriteMem(a, v, ty, aligned, volatile) => Inst::HostWriteMem(
+                self.operand(a)?,
+                self.operand(v)?,
+                HostAccess::new(*ty, *aligned, *volatile),
+            ),
+            Operation::GuestReadMem(d, a, size, endianness) => Inst::GuestReadMem(
+                self.dest(d)?,
+                self.operand(a)?,
+                *size,
+                endianness.unwrap_or(self.unit.endianness),
+            ),
+            Operation::GuestWriteMem(a, v, size, endianness) => Inst::GuestWriteMem(
+                self.operand(a)?,
+                self.operand(v)?,
+                *size,
+                endianness.unwrap_or(self.unit.endianness),
+            ),
+            Operation::GuestAtomicRmw(d, op, a, v, size, ordering) => Inst::GuestAtomicRmw(
+                self.dest(d)?,
+                *op,
+                self.operand(a)?,
+                self.operand(v)?,
+                self.atomic_access(*size, *ordering),
+            ),
+            Operation::GuestCompareExchange(d, a, e, n, size, ordering) => {
+                Inst::GuestCompareExchange(
+                    self.dest(d)?,
+                    self.operand(a)?,
+                    self.operand(e)?,
+                    self.operand(n)?,
+                    self.atomic_access(*size, *ordering),
+                )
+            }
+            Operation::GuestLoadLinked(d, a, size, ordering) => Inst::GuestLoadLinked(
+                self.dest(d)?,
+                self.operand(a)?,
+                self.atomic_access(*size, *ordering),
+            ),
+            Operation::GuestStoreConditional(d, a, v, size, ordering) => {
+                Inst::GuestStoreConditional(
+                    self.dest(d)?,
+                    self.operand(a)?,
+                    self.operand(v)?,
+                    self.atomic_access(*size, *ordering),
+                )
+            }
+            Operation::Fence(ordering) => Inst::Fence(*ordering),
+            Operation::GuestReadStruct(d, a, ty) => {
+                Inst::GuestReadStruct(self.struct_dest(d)?, self.operand(a)?, ty.clone())
+            }
+            Operation::GuestWriteStruct(a, v) => {
+                Inst::GuestWriteStruct(self.operand(a)?, self.struct_operand(v)?)
+            }
+            Operation::ExtractField(d, v, idx) => {
+                Inst::ExtractField(self.dest(d)?, self.struct_operand(v)?, *idx)
+            }
+            Operation::InsertField(d, v, f, idx) => Inst::InsertField(
+                self.struct_dest(d)?,
+                self.struct_operand(v)?,
+                self.value_operand(f)?,
+                *idx,
+            ),
+            Operation::FnCall(d, name, args) => {
+                let function = self
+                    .functions
+                    .get(name)
+                    .ok_or_else(|| Trap::UnknownFunction(name.clone()))?;
+                if args.len() != function.signature.args.len()
+                    || (d.is_some() && function.signature.ret.is_none())
+                {
+                    return Err(Trap::TypeMismatch);
+                }
+
+                Inst::Call(
+                    d.as_ref().map(|d| self.dest(d)).transpose()?,
+                    function.clone(),
+                    args.iter()
+                        .map(|a| self.operand(a))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            Operation::ICmp(d, cmp, a, b) => {
+                Inst::ICmp(self.dest(d)?, *cmp, self.operand(a)?, self.operand(b)?)
+            }
+            Operation::Select(c, d, a, b) => Inst::Select(
+                self.operand(c)?,
+                self.dest(d)?,
+                self.operand(a)?,
+                self.operand(b)?,
+            ),
+            Operation::Branch(c, t, nt) => {
+                Inst::Branch(self.operand(c)?, self.target(t), self.target(nt))
+            }
+            Operation::SetFlags(op, a, b, s) => {
+                Inst::SetFlags(*op, self.operand(a)?, self.operand(b)?, *s)
+            }
+            Operation::ReadCondition(d, cond) => Inst::ReadCondition(self.dest(d)?, *cond),
+            Operation::SelectCondition(cond, d, a, b) => {
+                Inst::SelectCondition(*cond, self.dest(d)?, self.operand(a)?, self.operand(b)?)
+            }
+            Operation::BranchCondition(cond, t, nt) => {
+                Inst::BranchCondition(*cond, self.target(t), self.target(nt))
+            }
+            Operation::CompareBranch(cmp, a, b, t, nt) => Inst::CompareBranch(
+                *cmp,
+                self.operand(a)?,
+                self.operand(b)?,
+                self.target(t),
+                self.target(nt),
+            ),
+            Operation::IndirectBranch(target, table) => {
+                let mut table = table
+                    .iter()
+                    .map(|(addr, label)| (*addr, self.target(label)))
+                    .collect::<Vec<_>>();
+                // Stable, so the first entry for an address is kept
+                table.sort_by_key(|(addr, _)| *addr);
+                table.dedup_by_key(|(addr, _)| *addr);
+                Inst::IndirectBranch(self.operand(target)?, table.into_boxed_slice())
+            }
+            Operation::Exit(code) => Inst::Exit(*code),
+            Operation::Instruction(pc, len) => Inst::Instruction(*pc, *len),
+        })
+    }
+}
+
+/// Lowers a unit for a state with the given register layout, resolving
+/// function calls against `functions`
+pub(crate) fn lower(
+    unit: &TranslationUnit,
+    regs: &[Register],
+    functions: &HostFunctions,
+) -> Result<LoweredUnit, String> {
+    let entry = unit
+        .entrypoint
+        .ok_or(String::from("Translation unit has no entrypoint"))?;
+
+    let mut lowerer = Lowerer {
+        unit,
+        regs,
+        functions,
+        unresolved: Vec::new(),
+        scratch_slots: Cell::new(0),
+    };
+    let mut insts = Vec::new();
+    let mut blocks = Vec::with_capacity(unit.blocks.len());
+
+    let mut labels = vec![None; unit.blocks.len()];
+    for (label, idx) in unit.labels.iter() {
+        labels[*idx] = Some(label);
+    }
+
+    for (block, label) in unit.blocks.iter().zip(labels) {
+        // A block replaced by a later block with the same label is unreachable,
+        // and is only kept so that block indices stay valid
+        blocks.push(LoweredBlock {
+            label: label.cloned().unwrap_or_default(),
+            start: insts.len(),
+        });
+
+        for op in block.ops.iter() {
+            insts.push(lowerer.lower_op(op).unwrap_or_else(Inst::Trap));
+        }
+    }
+
+    Ok(LoweredUnit {
+        insts,
+        blocks,
+        entry,
+        endianness: unit.endianness,
+        unresolved: lowerer.unresolved,
+        scratch_slots: lowerer.scratch_slots.get(),
+        revision: unit.revision,
+    })
+}
@@ -1,49 +1,300 @@
+mod aggregate;
+mod atomic;
+mod bits;
+mod closure;
+mod debug;
+mod flags;
+mod lower;
+mod profile;
+mod trace;
+mod vector;
+
 use crate::{
-    backend::{Compiler, Executable},
+    backend::{Compiler, Executable, ExitReason, ExitStatus, Fuel, ResumePoint, Trap},
+    host::{HostFunction, HostFunctions, HostState},
     ir::{
-        ops::Operation,
-        reg::{Register, RegisterMap, RegisterType},
-        types::{BlockLabel, RValue, ZippedIntImmed},
+        reg::{Register, RegisterMap, RegisterType, ScratchFile},
+        types::{
+            Comparator, Condition, Endianness, FlagOp, FloatComparator, FloatImmed, FloatType,
+            IntType, MultSignedness, ZippedIntImmed,
+        },
     },
+    mem::{GuestMemory, HostAccess, Reservation},
     unit::TranslationUnit,
-    IntImmed, LValue,
+    IntImmed,
 };
+use lower::{lower, Dest, Inst, LoweredUnit, Operand};
+use profile::RunProfile;
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::ops::{BitAnd, BitOr, BitXor};
 use std::rc::Rc;
 
+pub use closure::{ClosureBackend, ClosureExecutable};
+pub use debug::{DebugStop, Debugger};
+use flags::LazyFlags;
+pub use profile::{BlockProfile, Profiler};
+pub use trace::{TraceOp, Tracer};
+
 #[derive(Default)]
-pub struct InterpreterBackend {}
+pub struct InterpreterBackend {
+    guest_memory: GuestMemory,
+    tracer: Option<Rc<RefCell<dyn Tracer>>>,
+    profiler: Option<Rc<RefCell<Profiler>>>,
+}
+
+impl InterpreterBackend {
+    /// Creates an interpreter which serves guest memory accesses from `guest_memory`
+    pub fn new(guest_memory: GuestMemory) -> Self {
+        Self {
+            guest_memory,
+            tracer: None,
+            profiler: None,
+        }
+    }
+
+    /// Calls `tracer` around every operation executed by units compiled
+    /// from now on
+    pub fn with_tracer(mut self, tracer: Rc<RefCell<dyn Tracer>>) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Counts block entries, retired operations and branch edges taken
+    /// into `profiler` for units compiled from now on. Counts are added
+    /// when a run exits, traps or runs out of fuel.
+    pub fn with_profiler(mut self, profiler: Rc<RefCell<Profiler>>) -> Self {
+        self.profiler = Some(profiler);
+        self
+    }
+}
+
+/// Operations that report whether their result overflowed
+#[derive(Clone, Copy)]
+enum OverflowOp {
+    Add,
+    Sub,
+    Mult,
+}
+
+/// Multiplies two 128 bit values, returning the low and high halves of the
+/// 256 bit product
+fn mult_u128(a: u128, b: u128) -> (u128, u128) {
+    let (a0, a1) = (a as u64 as u128, a >> 64);
+    let (b0, b1) = (b as u64 as u128, b >> 64);
+    let (p00, p01, p10, p11) = (a0 * b0, a0 * b1, a1 * b0, a1 * b1);
+
+    let mid = (p00 >> 64) + (p01 as u64 as u128) + (p10 as u64 as u128);
+    let low = (p00 as u64 as u128) | (mid << 64);
+    let high = p11 + (p01 >> 64) + (p10 >> 64) + (mid >> 64);
+    (low, high)
+}
 
 enum ExitAction {
     Exit(u8),
+    /// Leave the unit for the given guest address
+    Jump(u64),
     BranchTo(usize),
 }
 
-pub struct InterpreterExecutable {
-    unit: TranslationUnit,
+/// A unit lowered for the register layout of `State`
+pub struct InterpreterExecutable<State: RegisterMap> {
+    unit: LoweredUnit,
     regs: Vec<Register>,
+    guest_memory: GuestMemory,
+    tracer: Option<Rc<RefCell<dyn Tracer>>>,
+    profiler: Option<Rc<RefCell<Profiler>>>,
+    _state: PhantomData<fn(&mut State)>,
+}
+
+/// Interpreter state held by a ResumePoint: the block or instruction
+/// boundary at which a run stopped, and the scratch values, flags and
+/// load-linked reservation live at that point
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SuspendedRun {
+    origin: Origin,
+    block: usize,
+    pc: usize,
+    scratch: ScratchFile,
+    flags: Option<LazyFlags>,
+    instruction: Option<(u64, u8)>,
+    reservation: Option<Reservation>,
+}
+
+/// What a run was executing. A suspended run can only be continued by an
+/// executable with the same origin: one compiled from the same revision of
+/// the same unit, over the same guest memory. Executables recompiled from
+/// an unchanged unit are interchangeable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Origin {
+    revision: u64,
+    guest_memory: GuestMemory,
+}
+
+/// Mutable state of a single execution of a unit
+struct Frame<'a, State: RegisterMap> {
+    state: &'a mut State,
+    scratch: ScratchFile,
+    flags: Option<LazyFlags>,
+    /// Guest PC and length of the instruction being executed,
+    /// if an instruction marker has been reached
+    instruction: Option<(u64, u8)>,
+    /// Guest instructions completed during this run
+    instructions_retired: u64,
+    /// Location reserved by the last load-linked access
+    reservation: Option<Reservation>,
+}
+
+impl<'a, State: RegisterMap> Frame<'a, State> {
+    fn new(state: &'a mut State, unit: &LoweredUnit) -> Self {
+        Self {
+            state,
+            scratch: ScratchFile::new(unit.scratch_slots),
+            flags: None,
+            instruction: None,
+            instructions_retired: 0,
+            reservation: None,
+        }
+    }
+
+    /// Restores the frame of a run suspended by an executable with the
+    /// given origin, returning it along with the block and instruction to
+    /// continue from
+    fn resume(
+        state: &'a mut State,
+        from: ResumePoint,
+        origin: Origin,
+    ) -> Result<(Self, (usize, usize)), Trap> {
+        let from = match from.downcast::<SuspendedRun>() {
+            Ok(from) if from.origin == origin => from,
+            _ => return Err(Trap::ForeignResumePoint),
+        };
+        let frame = Self {
+            state,
+            scratch: from.scratch,
+            flags: from.flags,
+            instruction: from.instruction,
+            instructions_retired: 0,
+            reservation: from.reservation,
+        };
+        Ok((frame, (from.block, from.pc)))
+    }
+
+    /// Captures the state needed for an executable with the given origin
+    /// to continue the run at `pc` of `block`
+    fn suspend(&mut self, origin: Origin, block: usize, pc: usize) -> ResumePoint {
+        ResumePoint::new(SuspendedRun {
+            origin,
+            block,
+            pc,
+            scratch: std::mem::take(&mut self.scratch),
+            flags: self.flags,
+            instruction: self.instruction,
+            reservation: self.reservation,
+        })
+    }
+
+    /// Completes the current guest instruction, if there is one
+    fn retire_instruction(&mut self) {
+        if self.instruction.take().is_some() {
+            self.instructions_retired += 1;
+        }
+    }
+
+    /// Guest instructions completed so far, counting the current one as
+    /// complete. Used when the run is stopping at an instruction boundary.
+    fn instructions_at_boundary(&self) -> u64 {
+        self.instructions_retired + self.instruction.is_some() as u64
+    }
+
+    fn guest_pc(&self) -> Option<u64> {
+        self.instruction.map(|(pc, _)| pc)
+    }
+}
+
+/// Applies a wrapping binary operation to a pair of upcast values,
+/// keeping the width of the operands.
+macro_rules! zipped_unsigned {
+    ($args:expr, |$b1:ident, $b2:ident| $bool:expr, $op:ident) => {
+        match $args {
+            ZippedIntImmed::Bool($b1, $b2) => $bool,
+            ZippedIntImmed::I8(v1, v2) => IntImmed::I8(v1.$op(v2)),
+            ZippedIntImmed::I16(v1, v2) => IntImmed::I16(v1.$op(v2)),
+            ZippedIntImmed::I32(v1, v2) => IntImmed::I32(v1.$op(v2)),
+            ZippedIntImmed::I64(v1, v2) => IntImmed::I64(v1.$op(v2)),
+            ZippedIntImmed::I128(v1, v2) => IntImmed::I128(v1.$op(v2)),
+        }
+    };
+}
+
+/// Same as zipped_unsigned, but applies a checked operation to the two's
+/// complement interpretation of the operands. Evaluates to None if the
+/// operation overflows.
+macro_rules! zipped_signed_checked {
+    ($args:expr, |$b1:ident, $b2:ident| $bool:expr, $op:ident) => {
+        match $args {
+            ZippedIntImmed::Bool($b1, $b2) => Some($bool),
+            ZippedIntImmed::I8(v1, v2) => (v1 as i8).$op(v2 as i8).map(|v| IntImmed::I8(v as u8)),
+            ZippedIntImmed::I16(v1, v2) => {
+                (v1 as i16).$op(v2 as i16).map(|v| IntImmed::I16(v as u16))
+            }
+            ZippedIntImmed::I32(v1, v2) => {
+                (v1 as i32).$op(v2 as i32).map(|v| IntImmed::I32(v as u32))
+            }
+            ZippedIntImmed::I64(v1, v2) => {
+                (v1 as i64).$op(v2 as i64).map(|v| IntImmed::I64(v as u64))
+            }
+            ZippedIntImmed::I128(v1, v2) => (v1 as i128)
+                .$op(v2 as i128)
+                .map(|v| IntImmed::I128(v as u128)),
+        }
+    };
 }
 
-impl InterpreterExecutable {
-    fn rv_to_immed<State: RegisterMap>(&self, state: &State, rv: &RValue<IntImmed>) -> IntImmed {
+impl<State: RegisterMap> InterpreterExecutable<State> {
+    fn rv_to_immed(&self, frame: &Frame<State>, rv: &Operand) -> Result<IntImmed, Trap> {
         match rv {
-            RValue::Immediate(i) => *i,
-            RValue::LValue(lv) => match lv {
-                LValue::Register(r) => unsafe { self.regs[*r as usize].read(state) },
-                LValue::Scratch(_) => unimplemented!(),
-            },
+            Operand::Immediate(i) => Ok(*i),
+            Operand::Register(reg) => Ok(unsafe { reg.read(frame.state) }),
+            Operand::Scratch(s) => frame.scratch.read(*s),
         }
     }
 
-    fn op_add<State: RegisterMap>(
+    /// Writes a result to its destination. Registers narrower than the
+    /// value are truncated, and wider registers are sign extended if
+    /// `signed` is set and zero extended otherwise. Scratch values
+    /// take the type of the result.
+    fn write_lvalue(&self, dest: &Dest, value: IntImmed, signed: bool, frame: &mut Frame<State>) {
+        let reg = match dest {
+            Dest::Register(_, reg) => reg,
+            Dest::Scratch(s) => return frame.scratch.write(*s, value),
+        };
+
+        let value = if signed {
+            value.to_i128() as u128
+        } else {
+            value.to_u128()
+        };
+
+        unsafe {
+            reg.write(value, frame.state);
+        }
+    }
+
+    fn op_add(
         &self,
-        dest: &LValue,
-        arg1: &RValue<IntImmed>,
-        arg2: &RValue<IntImmed>,
+        dest: &Dest,
+        arg1: &Operand,
+        arg2: &Operand,
         signed: bool,
-        state: &mut State,
-    ) {
-        let arg1 = self.rv_to_immed(state, arg1);
-        let arg2 = self.rv_to_immed(state, arg2);
+        flags: bool,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let arg1 = self.rv_to_immed(frame, arg1)?;
+        let arg2 = self.rv_to_immed(frame, arg2)?;
+        if flags {
+            frame.flags = Some(LazyFlags::new(FlagOp::Add, arg1, arg2, signed));
+        }
         let args = IntImmed::upcast_zip(&arg1, &arg2, signed);
         let value = match args {
             ZippedIntImmed::Bool(v1, v2) => IntImmed::I8(if v1 && v2 { 2 } else if v1 || v2 { 1 } else { 0 }),
@@ -51,32 +302,27 @@ impl InterpreterExecutable {
             ZippedIntImmed::I16(v1, v2) => IntImmed::I16(v1.wrapping_add(v2)),
             ZippedIntImmed::I32(v1, v2) => IntImmed::I32(v1.wrapping_add(v2)),
             ZippedIntImmed::I64(v1, v2) => IntImmed::I64(v1.wrapping_add(v2)),
+            ZippedIntImmed::I128(v1, v2) => IntImmed::I128(v1.wrapping_add(v2)),
         };
 
-        let value = if signed {
-            value.to_i64() as u64
-        } else {
-            value.to_u64()
-        };
-
-        match dest {
-            LValue::Register(r) => unsafe {
-                self.regs[*r as usize].write(value, state);
-            },
-            LValue::Scratch(_) => unimplemented!(),
-        }
+        self.write_lvalue(dest, value, signed, frame);
+        Ok(())
     }
 
-    fn op_sub<State: RegisterMap>(
+    fn op_sub(
         &self,
-        dest: &LValue,
-        arg1: &RValue<IntImmed>,
-        arg2: &RValue<IntImmed>,
+        dest: &Dest,
+        arg1: &Operand,
+        arg2: &Operand,
         signed: bool,
-        state: &mut State,
-    ) {
-        let arg1 = self.rv_to_immed(state, arg1);
-        let arg2 = self.rv_to_immed(state, arg2);
+        flags: bool,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let arg1 = self.rv_to_immed(frame, arg1)?;
+        let arg2 = self.rv_to_immed(frame, arg2)?;
+        if flags {
+            frame.flags = Some(LazyFlags::new(FlagOp::Sub, arg1, arg2, signed));
+        }
         let args = IntImmed::upcast_zip(&arg1, &arg2, signed);
         let value = match args {
             ZippedIntImmed::Bool(v1, v2) => IntImmed::I8(if v1 == v2 { 0 } else if v1 { 1 } else { 0xff }),
@@ -84,58 +330,1039 @@ impl InterpreterExecutable {
             ZippedIntImmed::I16(v1, v2) => IntImmed::I16(v1.wrapping_sub(v2)),
             ZippedIntImmed::I32(v1, v2) => IntImmed::I32(v1.wrapping_sub(v2)),
             ZippedIntImmed::I64(v1, v2) => IntImmed::I64(v1.wrapping_sub(v2)),
+            ZippedIntImmed::I128(v1, v2) => IntImmed::I128(v1.wrapping_sub(v2)),
         };
 
+        self.write_lvalue(dest, value, signed, frame);
+        Ok(())
+    }
+
+    fn op_mult(
+        &self,
+        dest: &Dest,
+        arg1: &Operand,
+        arg2: &Operand,
+        signed: bool,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let arg1 = self.rv_to_immed(frame, arg1)?;
+        let arg2 = self.rv_to_immed(frame, arg2)?;
+        let args = IntImmed::upcast_zip(&arg1, &arg2, signed);
+
+        // The low bits of a product do not depend on signedness
+        let value = zipped_unsigned!(args, |v1, v2| IntImmed::Bool(v1 && v2), wrapping_mul);
+        self.write_lvalue(dest, value, signed, frame);
+        Ok(())
+    }
+
+    /// Computes `arg1 op arg2`, adding or subtracting `carry` for adds and
+    /// subs, along with whether the result overflowed: out of the unsigned
+    /// range of the operands' width, or out of the signed range if `signed`
+    /// is set. Bools are treated as 8 bit values.
+    fn overflow_arith(
+        &self,
+        op: OverflowOp,
+        arg1: &Operand,
+        arg2: &Operand,
+        carry: Option<&Operand>,
+        signed: bool,
+        frame: &Frame<State>,
+    ) -> Result<(IntImmed, bool), Trap> {
+        let arg1 = self.rv_to_immed(frame, arg1)?;
+        let arg2 = self.rv_to_immed(frame, arg2)?;
+        let carry = match carry {
+            Some(carry) => (self.rv_to_immed(frame, carry)?.to_u128() != 0) as u128,
+            None => 0,
+        };
+
+        let (arg1, arg2) = IntImmed::upcast(&arg1, &arg2, signed);
+        let ty = match arg1.get_type() {
+            IntType::Bool => IntType::I8,
+            ty => ty,
+        };
+        let width = u32::from(ty.size());
+        let mask = u128::MAX >> (128 - width);
+        let sign = 1u128 << (width - 1);
+        let (a, b) = (arg1.to_u128(), arg2.to_u128());
+        // Sign extends a value of the operands' width to 128 bits
+        let extend = |v: u128| ((v << (128 - width)) as i128) >> (128 - width);
+
+        let (result, overflow) = match op {
+            OverflowOp::Add => {
+                let (sum, o1) = a.overflowing_add(b);
+                let (sum, o2) = sum.overflowing_add(carry);
+                let result = sum & mask;
+                let overflow = if signed {
+                    (a ^ result) & (b ^ result) & sign != 0
+                } else {
+                    o1 || o2 || sum > mask
+                };
+                (result, overflow)
+            }
+            OverflowOp::Sub => {
+                let result = a.wrapping_sub(b).wrapping_sub(carry) & mask;
+                let overflow = if signed {
+                    (a ^ b) & (a ^ result) & sign != 0
+                } else {
+                    a < b || a - b < carry
+                };
+                (result, overflow)
+            }
+            OverflowOp::Mult => {
+                let result = a.wrapping_mul(b) & mask;
+                let overflow = if signed {
+                    match extend(a).checked_mul(extend(b)) {
+                        Some(product) => extend(product as u128) != product,
+                        None => true,
+                    }
+                } else {
+                    a.checked_mul(b).map_or(true, |product| product > mask)
+                };
+                (result, overflow)
+            }
+        };
+
+        Ok((ty.from_u128(result), overflow))
+    }
+
+    /// Computes the full product of two values as its low and high halves,
+    /// each at the width of the wider operand. Bools count as 8 bit values.
+    fn widening_mult(
+        &self,
+        arg1: &Operand,
+        arg2: &Operand,
+        signedness: MultSignedness,
+        frame: &Frame<State>,
+    ) -> Result<(IntImmed, IntImmed), Trap> {
+        let arg1 = self.rv_to_immed(frame, arg1)?;
+        let arg2 = self.rv_to_immed(frame, arg2)?;
+        let ty = match std::cmp::max_by_key(arg1, arg2, |arg| arg.size()).get_type() {
+            IntType::Bool => IntType::I8,
+            ty => ty,
+        };
+        let width = u32::from(ty.size());
+
+        let (signed1, signed2) = signedness.operands();
+        let extend = |arg: IntImmed, signed: bool| match signed {
+            true => arg.to_i128() as u128,
+            false => arg.to_u128(),
+        };
+        let (a, b) = (extend(arg1, signed1), extend(arg2, signed2));
+
+        let (low, high) = if width <= 64 {
+            // The exact product of two extended 64 bit values fits in 128 bits
+            let product = a.wrapping_mul(b);
+            (product, product >> width)
+        } else {
+            // Multiplying as unsigned counts a negative operand as 2^128 too
+            // large, which adds the other operand to the high half
+            let (low, mut high) = mult_u128(a, b);
+            if signed1 && (a as i128) < 0 {
+                high = high.wrapping_sub(b);
+            }
+            if signed2 && (b as i128) < 0 {
+                high = high.wrapping_sub(a);
+            }
+            (low, high)
+        };
+
+        Ok((ty.from_u128(low), ty.from_u128(high)))
+    }
+
+    /// Writes the result of an overflow or carry reporting operation. The
+    /// flag is written last, so it is kept if both destinations are the same.
+    fn write_overflow(
+        &self,
+        dest: &Dest,
+        overflow_dest: &Dest,
+        (value, overflow): (IntImmed, bool),
+        signed: bool,
+        frame: &mut Frame<State>,
+    ) {
+        self.write_lvalue(dest, value, signed, frame);
+        self.write_lvalue(overflow_dest, IntImmed::Bool(overflow), false, frame);
+    }
+
+    /// Division rounds towards zero. Signed division of the most negative
+    /// value by -1 traps, as the result is not representable.
+    fn op_div(
+        &self,
+        dest: &Dest,
+        arg1: &Operand,
+        arg2: &Operand,
+        signed: bool,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let arg1 = self.rv_to_immed(frame, arg1)?;
+        let arg2 = self.rv_to_immed(frame, arg2)?;
+        if arg2.to_u128() == 0 {
+            return Err(Trap::DivideByZero);
+        }
+
+        let args = IntImmed::upcast_zip(&arg1, &arg2, signed);
         let value = if signed {
-            value.to_i64() as u64
+            zipped_signed_checked!(args, |v1, _v2| IntImmed::Bool(v1), checked_div)
+                .ok_or(Trap::IntegerOverflow)?
         } else {
-            value.to_u64()
+            zipped_unsigned!(args, |v1, _v2| IntImmed::Bool(v1), wrapping_div)
         };
+        self.write_lvalue(dest, value, signed, frame);
+        Ok(())
+    }
 
-        match dest {
-            LValue::Register(r) => unsafe {
-                self.regs[*r as usize].write(value, state);
+    /// The remainder takes the sign of the dividend for signed operands.
+    /// Like division, the remainder of the most negative value and -1 traps.
+    fn op_rem(
+        &self,
+        dest: &Dest,
+        arg1: &Operand,
+        arg2: &Operand,
+        signed: bool,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let arg1 = self.rv_to_immed(frame, arg1)?;
+        let arg2 = self.rv_to_immed(frame, arg2)?;
+        if arg2.to_u128() == 0 {
+            return Err(Trap::DivideByZero);
+        }
+
+        let args = IntImmed::upcast_zip(&arg1, &arg2, signed);
+        let value = if signed {
+            zipped_signed_checked!(args, |_v1, _v2| IntImmed::Bool(false), checked_rem)
+                .ok_or(Trap::IntegerOverflow)?
+        } else {
+            zipped_unsigned!(args, |_v1, _v2| IntImmed::Bool(false), wrapping_rem)
+        };
+        self.write_lvalue(dest, value, signed, frame);
+        Ok(())
+    }
+
+    /// Shifts keep the width of the value being shifted. Shifting left by
+    /// at least the width of the value produces zero.
+    fn op_lshift(
+        &self,
+        dest: &Dest,
+        arg1: &Operand,
+        arg2: &Operand,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let value = self.rv_to_immed(frame, arg1)?;
+        let amount = self.rv_to_immed(frame, arg2)?.to_u128();
+
+        let value = if amount >= value.size() as u128 {
+            value.get_type().from_u64(0)
+        } else {
+            match value {
+                IntImmed::Bool(b) => IntImmed::Bool(b),
+                IntImmed::I8(v) => IntImmed::I8(v << amount),
+                IntImmed::I16(v) => IntImmed::I16(v << amount),
+                IntImmed::I32(v) => IntImmed::I32(v << amount),
+                IntImmed::I64(v) => IntImmed::I64(v << amount),
+                IntImmed::I128(v) => IntImmed::I128(v << amount),
+            }
+        };
+        self.write_lvalue(dest, value, false, frame);
+        Ok(())
+    }
+
+    /// Signed right shifts are arithmetic, and fill the value with its sign
+    /// bit when shifting by at least its width. Unsigned right shifts are
+    /// logical, and produce zero in that case.
+    fn op_rshift(
+        &self,
+        dest: &Dest,
+        arg1: &Operand,
+        arg2: &Operand,
+        signed: bool,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let value = self.rv_to_immed(frame, arg1)?;
+        let amount = self.rv_to_immed(frame, arg2)?.to_u128();
+        let width = value.size() as u128;
+
+        let value = if signed {
+            let amount = amount.min(width - 1);
+            match value {
+                IntImmed::Bool(b) => IntImmed::Bool(b),
+                IntImmed::I8(v) => IntImmed::I8(((v as i8) >> amount) as u8),
+                IntImmed::I16(v) => IntImmed::I16(((v as i16) >> amount) as u16),
+                IntImmed::I32(v) => IntImmed::I32(((v as i32) >> amount) as u32),
+                IntImmed::I64(v) => IntImmed::I64(((v as i64) >> amount) as u64),
+                IntImmed::I128(v) => IntImmed::I128(((v as i128) >> amount) as u128),
+            }
+        } else if amount >= width {
+            value.get_type().from_u64(0)
+        } else {
+            match value {
+                IntImmed::Bool(b) => IntImmed::Bool(b),
+                IntImmed::I8(v) => IntImmed::I8(v >> amount),
+                IntImmed::I16(v) => IntImmed::I16(v >> amount),
+                IntImmed::I32(v) => IntImmed::I32(v >> amount),
+                IntImmed::I64(v) => IntImmed::I64(v >> amount),
+                IntImmed::I128(v) => IntImmed::I128(v >> amount),
+            }
+        };
+        self.write_lvalue(dest, value, signed, frame);
+        Ok(())
+    }
+
+    /// Converts the value to `ty`, truncating it if `ty` is narrower
+    fn op_extend(
+        &self,
+        dest: &Dest,
+        arg1: &Operand,
+        ty: IntType,
+        signed: bool,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let value = self.rv_to_immed(frame, arg1)?.cast(ty, signed);
+        self.write_lvalue(dest, value, signed, frame);
+        Ok(())
+    }
+
+    fn op_and(
+        &self,
+        dest: &Dest,
+        arg1: &Operand,
+        arg2: &Operand,
+        flags: bool,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let arg1 = self.rv_to_immed(frame, arg1)?;
+        let arg2 = self.rv_to_immed(frame, arg2)?;
+        if flags {
+            frame.flags = Some(LazyFlags::new(FlagOp::And, arg1, arg2, false));
+        }
+        let args = IntImmed::upcast_zip(&arg1, &arg2, false);
+
+        let value = zipped_unsigned!(args, |v1, v2| IntImmed::Bool(v1 & v2), bitand);
+        self.write_lvalue(dest, value, false, frame);
+        Ok(())
+    }
+
+    fn op_or(
+        &self,
+        dest: &Dest,
+        arg1: &Operand,
+        arg2: &Operand,
+        flags: bool,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let arg1 = self.rv_to_immed(frame, arg1)?;
+        let arg2 = self.rv_to_immed(frame, arg2)?;
+        if flags {
+            frame.flags = Some(LazyFlags::new(FlagOp::Or, arg1, arg2, false));
+        }
+        let args = IntImmed::upcast_zip(&arg1, &arg2, false);
+
+        let value = zipped_unsigned!(args, |v1, v2| IntImmed::Bool(v1 | v2), bitor);
+        self.write_lvalue(dest, value, false, frame);
+        Ok(())
+    }
+
+    fn op_xor(
+        &self,
+        dest: &Dest,
+        arg1: &Operand,
+        arg2: &Operand,
+        flags: bool,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let arg1 = self.rv_to_immed(frame, arg1)?;
+        let arg2 = self.rv_to_immed(frame, arg2)?;
+        if flags {
+            frame.flags = Some(LazyFlags::new(FlagOp::Xor, arg1, arg2, false));
+        }
+        let args = IntImmed::upcast_zip(&arg1, &arg2, false);
+
+        let value = zipped_unsigned!(args, |v1, v2| IntImmed::Bool(v1 ^ v2), bitxor);
+        self.write_lvalue(dest, value, false, frame);
+        Ok(())
+    }
+
+    fn op_not(&self, dest: &Dest, arg1: &Operand, frame: &mut Frame<State>) -> Result<(), Trap> {
+        let value = match self.rv_to_immed(frame, arg1)? {
+            IntImmed::Bool(b) => IntImmed::Bool(!b),
+            IntImmed::I8(v) => IntImmed::I8(!v),
+            IntImmed::I16(v) => IntImmed::I16(!v),
+            IntImmed::I32(v) => IntImmed::I32(!v),
+            IntImmed::I64(v) => IntImmed::I64(!v),
+            IntImmed::I128(v) => IntImmed::I128(!v),
+        };
+        self.write_lvalue(dest, value, false, frame);
+        Ok(())
+    }
+
+    fn rv_to_float(&self, frame: &Frame<State>, rv: &Operand) -> Result<FloatImmed, Trap> {
+        let bits = self.rv_to_immed(frame, rv)?;
+        FloatImmed::from_bits(bits).ok_or(Trap::InvalidFloatWidth(bits.size()))
+    }
+
+    /// Float results written to a float register are converted to the
+    /// register's precision. Anywhere else they are stored as their bit pattern.
+    fn write_float(&self, dest: &Dest, value: FloatImmed, frame: &mut Frame<State>) {
+        let value = match dest {
+            Dest::Register(_, reg) => match reg.ty {
+                RegisterType::F32 => value.cast(FloatType::F32),
+                RegisterType::F64 => value.cast(FloatType::F64),
+                _ => value,
             },
-            LValue::Scratch(_) => unimplemented!(),
+            Dest::Scratch(_) => value,
+        };
+        self.write_lvalue(dest, value.to_bits(), false, frame);
+    }
+
+    /// Applies `op` in the wider of the two operand types. The operation is
+    /// always computed in f64, which has enough precision that rounding an
+    /// exact result through it gives the correctly rounded f32.
+    fn op_float_binary(
+        &self,
+        dest: &Dest,
+        arg1: &Operand,
+        arg2: &Operand,
+        op: fn(f64, f64) -> f64,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let arg1 = self.rv_to_float(frame, arg1)?;
+        let arg2 = self.rv_to_float(frame, arg2)?;
+        let ty = if arg1.get_type() == FloatType::F64 || arg2.get_type() == FloatType::F64 {
+            FloatType::F64
+        } else {
+            FloatType::F32
+        };
+
+        let value = ty.from_f64(op(arg1.to_f64(), arg2.to_f64()));
+        self.write_float(dest, value, frame);
+        Ok(())
+    }
+
+    fn op_float_sqrt(
+        &self,
+        dest: &Dest,
+        arg1: &Operand,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let value = self.rv_to_float(frame, arg1)?;
+        let value = value.get_type().from_f64(value.to_f64().sqrt());
+        self.write_float(dest, value, frame);
+        Ok(())
+    }
+
+    fn op_float_cmp(
+        &self,
+        dest: &Dest,
+        cmp: &FloatComparator,
+        arg1: &Operand,
+        arg2: &Operand,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let arg1 = self.rv_to_float(frame, arg1)?.to_f64();
+        let arg2 = self.rv_to_float(frame, arg2)?.to_f64();
+
+        let result = match cmp {
+            FloatComparator::EQ => arg1 == arg2,
+            FloatComparator::NEQ => arg1 != arg2,
+            FloatComparator::LT => arg1 < arg2,
+            FloatComparator::LE => arg1 <= arg2,
+            FloatComparator::GT => arg1 > arg2,
+            FloatComparator::GE => arg1 >= arg2,
+            FloatComparator::UNO => arg1.is_nan() || arg2.is_nan(),
+        };
+        self.write_lvalue(dest, IntImmed::Bool(result), false, frame);
+        Ok(())
+    }
+
+    fn op_int_to_float(
+        &self,
+        dest: &Dest,
+        arg1: &Operand,
+        ty: FloatType,
+        signed: bool,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let value = self.rv_to_immed(frame, arg1)?;
+
+        // Converting straight from the integer rounds once, where going
+        // through f64 could round twice for f32
+        let value = match (ty, signed) {
+            (FloatType::F32, true) => FloatImmed::F32(value.to_i128() as f32),
+            (FloatType::F32, false) => FloatImmed::F32(value.to_u128() as f32),
+            (FloatType::F64, true) => FloatImmed::F64(value.to_i128() as f64),
+            (FloatType::F64, false) => FloatImmed::F64(value.to_u128() as f64),
+        };
+        self.write_float(dest, value, frame);
+        Ok(())
+    }
+
+    /// Rounds towards zero, saturating at the bounds of `ty`. NaN converts to zero.
+    fn op_float_to_int(
+        &self,
+        dest: &Dest,
+        arg1: &Operand,
+        ty: IntType,
+        signed: bool,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let value = self.rv_to_float(frame, arg1)?.to_f64();
+
+        let value = match (ty, signed) {
+            (IntType::Bool, _) => IntImmed::Bool(value.trunc() != 0.0 && !value.is_nan()),
+            (IntType::I8, true) => IntImmed::I8(value as i8 as u8),
+            (IntType::I8, false) => IntImmed::I8(value as u8),
+            (IntType::I16, true) => IntImmed::I16(value as i16 as u16),
+            (IntType::I16, false) => IntImmed::I16(value as u16),
+            (IntType::I32, true) => IntImmed::I32(value as i32 as u32),
+            (IntType::I32, false) => IntImmed::I32(value as u32),
+            (IntType::I64, true) => IntImmed::I64(value as i64 as u64),
+            (IntType::I64, false) => IntImmed::I64(value as u64),
+            (IntType::I128, true) => IntImmed::I128(value as i128 as u128),
+            (IntType::I128, false) => IntImmed::I128(value as u128),
+        };
+        self.write_lvalue(dest, value, signed, frame);
+        Ok(())
+    }
+
+    fn op_float_to_bits(
+        &self,
+        dest: &Dest,
+        arg1: &Operand,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let value = self.rv_to_float(frame, arg1)?;
+        self.write_lvalue(dest, value.to_bits(), false, frame);
+        Ok(())
+    }
+
+    fn op_bits_to_float(
+        &self,
+        dest: &Dest,
+        arg1: &Operand,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let value = self.rv_to_float(frame, arg1)?;
+        self.write_float(dest, value, frame);
+        Ok(())
+    }
+
+    fn op_host_read(
+        &self,
+        dest: &Dest,
+        addr: &Operand,
+        access: HostAccess,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let addr = self.rv_to_immed(frame, addr)?.to_u64();
+        let value = unsafe { access.load(addr)? };
+        self.write_lvalue(dest, value, false, frame);
+        Ok(())
+    }
+
+    fn op_host_write(
+        &self,
+        addr: &Operand,
+        value: &Operand,
+        access: HostAccess,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let addr = self.rv_to_immed(frame, addr)?.to_u64();
+        let value = self.rv_to_immed(frame, value)?;
+        unsafe { access.store(addr, value) }
+    }
+
+    fn op_guest_read(
+        &self,
+        dest: &Dest,
+        addr: &Operand,
+        (size, endianness): (u8, Endianness),
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let addr = self.rv_to_immed(frame, addr)?.to_u64();
+        let value = unsafe { self.guest_memory.read(addr, size, endianness)? };
+        self.write_lvalue(dest, value, false, frame);
+        Ok(())
+    }
+
+    fn op_guest_write(
+        &self,
+        addr: &Operand,
+        value: &Operand,
+        (size, endianness): (u8, Endianness),
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let addr = self.rv_to_immed(frame, addr)?.to_u64();
+        let value = self.rv_to_immed(frame, value)?;
+        unsafe { self.guest_memory.write(addr, value, size, endianness) }
+    }
+
+    fn op_call(
+        &self,
+        dest: &Option<Dest>,
+        function: &HostFunction,
+        args: &[Operand],
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let args = function
+            .signature
+            .args
+            .iter()
+            .zip(args)
+            .map(|(ty, arg)| Ok(ty.from_u128(self.rv_to_immed(frame, arg)?.to_u128())))
+            .collect::<Result<Vec<IntImmed>, Trap>>()?;
+
+        let mut host = HostState::new(
+            &mut *frame.state,
+            &self.regs,
+            self.guest_memory,
+            self.unit.endianness,
+        );
+        let ret = function.call(&mut host, &args)?;
+        if let (Some(dest), Some(value)) = (dest, ret) {
+            self.write_lvalue(dest, value, false, frame);
         }
+        Ok(())
     }
 
-    fn op_branch<State: RegisterMap>(
+    /// Compares two values after upcasting them to the same width
+    fn compare(
         &self,
-        cond: &RValue<IntImmed>,
-        taken: &BlockLabel,
-        not_taken: &BlockLabel,
-        state: &mut State,
-    ) -> ExitAction {
-        let value = self.rv_to_immed(state, cond).to_u64();
-        let branch_sel = if value == 0 { not_taken } else { taken };
+        cmp: &Comparator,
+        arg1: &Operand,
+        arg2: &Operand,
+        frame: &Frame<State>,
+    ) -> Result<bool, Trap> {
+        let arg1 = self.rv_to_immed(frame, arg1)?;
+        let arg2 = self.rv_to_immed(frame, arg2)?;
+        let (arg1, arg2) = IntImmed::upcast(&arg1, &arg2, cmp.is_signed());
 
-        let idx = self.unit.labels.get(branch_sel).unwrap();
-        ExitAction::BranchTo(*idx)
+        Ok(match cmp {
+            Comparator::EQ => arg1.to_u128() == arg2.to_u128(),
+            Comparator::NEQ => arg1.to_u128() != arg2.to_u128(),
+            Comparator::SLT => arg1.to_i128() < arg2.to_i128(),
+            Comparator::SGT => arg1.to_i128() > arg2.to_i128(),
+            Comparator::SLE => arg1.to_i128() <= arg2.to_i128(),
+            Comparator::SGE => arg1.to_i128() >= arg2.to_i128(),
+            Comparator::ULT => arg1.to_u128() < arg2.to_u128(),
+            Comparator::UGT => arg1.to_u128() > arg2.to_u128(),
+            Comparator::ULE => arg1.to_u128() <= arg2.to_u128(),
+            Comparator::UGE => arg1.to_u128() >= arg2.to_u128(),
+        })
     }
 
-    fn execute_block<State: RegisterMap>(
+    /// Compares two values, producing a Bool
+    fn op_icmp(
         &self,
-        ops: &Vec<Operation>,
-        state: &mut State,
-    ) -> ExitAction {
-        for op in ops {
-            match op {
-                Operation::Add(dest, arg1, arg2, signed) => {
-                    self.op_add(dest, arg1, arg2, *signed, state)
-                },
-                Operation::Sub(dest, arg1, arg2, signed) => {
-                    self.op_sub(dest, arg1, arg2, *signed, state)
-                }
-                Operation::Exit(code) => return ExitAction::Exit(*code),
-                Operation::Branch(cond, taken, not_taken) => {
-                    return self.op_branch(cond, taken, not_taken, state)
+        dest: &Dest,
+        cmp: &Comparator,
+        arg1: &Operand,
+        arg2: &Operand,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let result = self.compare(cmp, arg1, arg2, frame)?;
+        self.write_lvalue(dest, IntImmed::Bool(result), false, frame);
+        Ok(())
+    }
+
+    fn op_select(
+        &self,
+        cond: &Operand,
+        dest: &Dest,
+        arg1: &Operand,
+        arg2: &Operand,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let cond = self.rv_to_immed(frame, cond)?.to_u128();
+        let value = if cond != 0 {
+            self.rv_to_immed(frame, arg1)?
+        } else {
+            self.rv_to_immed(frame, arg2)?
+        };
+        self.write_lvalue(dest, value, false, frame);
+        Ok(())
+    }
+
+    fn op_set_flags(
+        &self,
+        op: FlagOp,
+        arg1: &Operand,
+        arg2: &Operand,
+        signed: bool,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let arg1 = self.rv_to_immed(frame, arg1)?;
+        let arg2 = self.rv_to_immed(frame, arg2)?;
+        frame.flags = Some(LazyFlags::new(op, arg1, arg2, signed));
+        Ok(())
+    }
+
+    fn test_condition(&self, cond: Condition, frame: &Frame<State>) -> Result<bool, Trap> {
+        let flags = frame.flags.as_ref().ok_or(Trap::UndefinedFlags)?;
+        Ok(flags.test(cond))
+    }
+
+    fn op_select_condition(
+        &self,
+        cond: Condition,
+        dest: &Dest,
+        arg1: &Operand,
+        arg2: &Operand,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let value = if self.test_condition(cond, frame)? {
+            self.rv_to_immed(frame, arg1)?
+        } else {
+            self.rv_to_immed(frame, arg2)?
+        };
+        self.write_lvalue(dest, value, false, frame);
+        Ok(())
+    }
+
+    fn op_branch(
+        &self,
+        cond: &Operand,
+        taken: usize,
+        not_taken: usize,
+        frame: &mut Frame<State>,
+    ) -> Result<ExitAction, Trap> {
+        let value = self.rv_to_immed(frame, cond)?.to_u128();
+        let target = if value == 0 { not_taken } else { taken };
+        Ok(ExitAction::BranchTo(self.unit.check_target(target)?))
+    }
+
+    /// Executes a single instruction, returning how control leaves
+    /// the current block if the instruction is a terminator
+    fn execute_inst(
+        &self,
+        inst: &Inst,
+        frame: &mut Frame<State>,
+    ) -> Result<Option<ExitAction>, Trap> {
+        match inst {
+            Inst::Add(dest, arg1, arg2, signed, flags) => {
+                self.op_add(dest, arg1, arg2, *signed, *flags, frame)?
+            }
+            Inst::Sub(dest, arg1, arg2, signed, flags) => {
+                self.op_sub(dest, arg1, arg2, *signed, *flags, frame)?
+            }
+            Inst::Mult(dest, arg1, arg2, signed) => {
+                self.op_mult(dest, arg1, arg2, *signed, frame)?
+            }
+            Inst::AddCarry(dest, overflow, arg1, arg2, carry, signed) => {
+                let result =
+                    self.overflow_arith(OverflowOp::Add, arg1, arg2, Some(carry), *signed, frame)?;
+                self.write_overflow(dest, overflow, result, *signed, frame)
+            }
+            Inst::SubBorrow(dest, overflow, arg1, arg2, borrow, signed) => {
+                let result =
+                    self.overflow_arith(OverflowOp::Sub, arg1, arg2, Some(borrow), *signed, frame)?;
+                self.write_overflow(dest, overflow, result, *signed, frame)
+            }
+            // Without a carry in, these are the same as the carry operations
+            Inst::AddOverflow(dest, overflow, arg1, arg2, signed) => {
+                let result =
+                    self.overflow_arith(OverflowOp::Add, arg1, arg2, None, *signed, frame)?;
+                self.write_overflow(dest, overflow, result, *signed, frame)
+            }
+            Inst::SubOverflow(dest, overflow, arg1, arg2, signed) => {
+                let result =
+                    self.overflow_arith(OverflowOp::Sub, arg1, arg2, None, *signed, frame)?;
+                self.write_overflow(dest, overflow, result, *signed, frame)
+            }
+            Inst::MultOverflow(dest, overflow, arg1, arg2, signed) => {
+                let result =
+                    self.overflow_arith(OverflowOp::Mult, arg1, arg2, None, *signed, frame)?;
+                self.write_overflow(dest, overflow, result, *signed, frame)
+            }
+            Inst::MultWide(lo, hi, arg1, arg2, signedness) => {
+                let (low, high) = self.widening_mult(arg1, arg2, *signedness, frame)?;
+                let signed = *signedness != MultSignedness::Unsigned;
+                self.write_lvalue(lo, low, signed, frame);
+                self.write_lvalue(hi, high, signed, frame);
+            }
+            Inst::MultHigh(dest, arg1, arg2, signedness) => {
+                let (_, high) = self.widening_mult(arg1, arg2, *signedness, frame)?;
+                let signed = *signedness != MultSignedness::Unsigned;
+                self.write_lvalue(dest, high, signed, frame);
+            }
+            Inst::Div(dest, arg1, arg2, signed) => self.op_div(dest, arg1, arg2, *signed, frame)?,
+            Inst::Rem(dest, arg1, arg2, signed) => self.op_rem(dest, arg1, arg2, *signed, frame)?,
+            Inst::LShift(dest, arg1, arg2) => self.op_lshift(dest, arg1, arg2, frame)?,
+            Inst::RShift(dest, arg1, arg2, signed) => {
+                self.op_rshift(dest, arg1, arg2, *signed, frame)?
+            }
+            Inst::SignExtend(dest, arg1, ty) => self.op_extend(dest, arg1, *ty, true, frame)?,
+            Inst::ZeroExtend(dest, arg1, ty) => self.op_extend(dest, arg1, *ty, false, frame)?,
+            Inst::RotateLeft(dest, arg1, arg2) => self.op_rotate(dest, arg1, arg2, true, frame)?,
+            Inst::RotateRight(dest, arg1, arg2) => {
+                self.op_rotate(dest, arg1, arg2, false, frame)?
+            }
+            Inst::RotateLeftCarry(dest, carry_out, arg1, arg2, carry) => {
+                let result = self.rotate_carry(arg1, arg2, carry, true, frame)?;
+                self.write_overflow(dest, carry_out, result, false, frame)
+            }
+            Inst::RotateRightCarry(dest, carry_out, arg1, arg2, carry) => {
+                let result = self.rotate_carry(arg1, arg2, carry, false, frame)?;
+                self.write_overflow(dest, carry_out, result, false, frame)
+            }
+            Inst::CountLeadingZeros(dest, arg1) => {
+                self.op_bits(dest, arg1, bits::count_leading_zeros, frame)?
+            }
+            Inst::CountTrailingZeros(dest, arg1) => {
+                self.op_bits(dest, arg1, bits::count_trailing_zeros, frame)?
+            }
+            Inst::PopCount(dest, arg1) => self.op_bits(dest, arg1, bits::pop_count, frame)?,
+            Inst::ByteSwap(dest, arg1) => self.op_bits(dest, arg1, bits::byte_swap, frame)?,
+            Inst::BitReverse(dest, arg1) => self.op_bits(dest, arg1, bits::bit_reverse, frame)?,
+            Inst::And(dest, arg1, arg2, flags) => self.op_and(dest, arg1, arg2, *flags, frame)?,
+            Inst::Or(dest, arg1, arg2, flags) => self.op_or(dest, arg1, arg2, *flags, frame)?,
+            Inst::Xor(dest, arg1, arg2, flags) => self.op_xor(dest, arg1, arg2, *flags, frame)?,
+            Inst::Not(dest, arg1) => self.op_not(dest, arg1, frame)?,
+            Inst::FAdd(dest, arg1, arg2) => {
+                self.op_float_binary(dest, arg1, arg2, |a, b| a + b, frame)?
+            }
+            Inst::FSub(dest, arg1, arg2) => {
+                self.op_float_binary(dest, arg1, arg2, |a, b| a - b, frame)?
+            }
+            Inst::FMult(dest, arg1, arg2) => {
+                self.op_float_binary(dest, arg1, arg2, |a, b| a * b, frame)?
+            }
+            Inst::FDiv(dest, arg1, arg2) => {
+                self.op_float_binary(dest, arg1, arg2, |a, b| a / b, frame)?
+            }
+            Inst::FSqrt(dest, arg1) => self.op_float_sqrt(dest, arg1, frame)?,
+            Inst::FCmp(dest, cmp, arg1, arg2) => self.op_float_cmp(dest, cmp, arg1, arg2, frame)?,
+            Inst::IntToFloat(dest, arg1, ty, signed) => {
+                self.op_int_to_float(dest, arg1, *ty, *signed, frame)?
+            }
+            Inst::FloatToInt(dest, arg1, ty, signed) => {
+                self.op_float_to_int(dest, arg1, *ty, *signed, frame)?
+            }
+            Inst::FloatToBits(dest, arg1) => self.op_float_to_bits(dest, arg1, frame)?,
+            Inst::BitsToFloat(dest, arg1) => self.op_bits_to_float(dest, arg1, frame)?,
+            Inst::VAdd(dest, arg1, arg2, lane) => {
+                self.op_vector_binary(dest, arg1, arg2, *lane, u64::wrapping_add, frame)?
+            }
+            Inst::VSub(dest, arg1, arg2, lane) => {
+                self.op_vector_binary(dest, arg1, arg2, *lane, u64::wrapping_sub, frame)?
+            }
+            Inst::VMult(dest, arg1, arg2, lane) => {
+                self.op_vector_binary(dest, arg1, arg2, *lane, u64::wrapping_mul, frame)?
+            }
+            Inst::VMin(dest, arg1, arg2, lane, signed) => {
+                let cmp = if *signed {
+                    Comparator::SLT
+                } else {
+                    Comparator::ULT
+                };
+                self.op_vector_pick(dest, cmp, arg1, arg2, *lane, frame)?
+            }
+            Inst::VMax(dest, arg1, arg2, lane, signed) => {
+                let cmp = if *signed {
+                    Comparator::SGT
+                } else {
+                    Comparator::UGT
+                };
+                self.op_vector_pick(dest, cmp, arg1, arg2, *lane, frame)?
+            }
+            Inst::VCmp(dest, cmp, arg1, arg2, lane) => {
+                self.op_vector_cmp(dest, cmp, arg1, arg2, *lane, frame)?
+            }
+            Inst::VShiftLeft(dest, arg1, arg2, lane) => {
+                self.op_vector_shift_left(dest, arg1, arg2, *lane, frame)?
+            }
+            Inst::VShiftRight(dest, arg1, arg2, lane, signed) => {
+                self.op_vector_shift_right(dest, arg1, arg2, *lane, *signed, frame)?
+            }
+            Inst::VShuffle(dest, arg1, arg2, indices, lane) => {
+                self.op_vector_shuffle(dest, arg1, arg2, indices, *lane, frame)?
+            }
+            Inst::VInsertLane(dest, vector, value, idx, lane) => {
+                self.op_vector_insert_lane(dest, vector, value, *idx, *lane, frame)?
+            }
+            Inst::VExtractLane(dest, vector, idx, lane) => {
+                self.op_vector_extract_lane(dest, vector, *idx, *lane, frame)?
+            }
+            Inst::VSplat(dest, value, lane, ty) => {
+                self.op_vector_splat(dest, value, *lane, *ty, frame)?
+            }
+            Inst::HostReadMem(dest, addr, access) => {
+                self.op_host_read(dest, addr, *access, frame)?
+            }
+            Inst::HostWriteMem(addr, value, access) => {
+                self.op_host_write(addr, value, *access, frame)?
+            }
+            Inst::GuestReadMem(dest, addr, size, endianness) => {
+                self.op_guest_read(dest, addr, (*size, *endianness), frame)?
+            }
+            Inst::GuestWriteMem(addr, value, size, endianness) => {
+                self.op_guest_write(addr, value, (*size, *endianness), frame)?
+            }
+            Inst::GuestReadStruct(dest, addr, ty) => {
+                self.op_guest_read_struct(*dest, addr, ty, frame)?
+            }
+            Inst::GuestWriteStruct(addr, value) => {
+                self.op_guest_write_struct(addr, value, frame)?
+            }
+            Inst::ExtractField(dest, value, idx) => {
+                self.op_extract_field(dest, value, *idx, frame)?
+            }
+            Inst::InsertField(dest, value, field, idx) => {
+                self.op_insert_field(*dest, value, field, *idx, frame)?
+            }
+            Inst::GuestAtomicRmw(dest, op, addr, value, access) => {
+                self.op_guest_atomic_rmw(dest, *op, addr, value, *access, frame)?
+            }
+            Inst::GuestCompareExchange(dest, addr, expected, new, access) => {
+                self.op_guest_compare_exchange(dest, addr, (expected, new), *access, frame)?
+            }
+            Inst::GuestLoadLinked(dest, addr, access) => {
+                self.op_guest_load_linked(dest, addr, *access, frame)?
+            }
+            Inst::GuestStoreConditional(dest, addr, value, access) => {
+                self.op_guest_store_conditional(dest, addr, value, *access, frame)?
+            }
+            Inst::Fence(ordering) => GuestMemory::fence(*ordering),
+            Inst::Call(dest, function, args) => self.op_call(dest, function, args, frame)?,
+            Inst::ICmp(dest, cmp, arg1, arg2) => self.op_icmp(dest, cmp, arg1, arg2, frame)?,
+            Inst::Select(cond, dest, arg1, arg2) => {
+                self.op_select(cond, dest, arg1, arg2, frame)?
+            }
+            Inst::SetFlags(op, arg1, arg2, signed) => {
+                self.op_set_flags(*op, arg1, arg2, *signed, frame)?
+            }
+            Inst::ReadCondition(dest, cond) => {
+                let value = self.test_condition(*cond, frame)?;
+                self.write_lvalue(dest, IntImmed::Bool(value), false, frame)
+            }
+            Inst::SelectCondition(cond, dest, arg1, arg2) => {
+                self.op_select_condition(*cond, dest, arg1, arg2, frame)?
+            }
+            Inst::Exit(code) => return Ok(Some(ExitAction::Exit(*code))),
+            Inst::Branch(cond, taken, not_taken) => {
+                return self.op_branch(cond, *taken, *not_taken, frame).map(Some)
+            }
+            Inst::CompareBranch(cmp, arg1, arg2, taken, not_taken) => {
+                let target = if self.compare(cmp, arg1, arg2, frame)? {
+                    *taken
+                } else {
+                    *not_taken
+                };
+                return Ok(Some(ExitAction::BranchTo(self.unit.check_target(target)?)));
+            }
+            Inst::BranchCondition(cond, taken, not_taken) => {
+                let target = if self.test_condition(*cond, frame)? {
+                    *taken
+                } else {
+                    *not_taken
+                };
+                return Ok(Some(ExitAction::BranchTo(self.unit.check_target(target)?)));
+            }
+            Inst::IndirectBranch(target, table) => {
+                let addr = self.rv_to_immed(frame, target)?.to_u64();
+                return match table.binary_search_by_key(&addr, |(addr, _)| *addr) {
+                    Ok(idx) => Ok(Some(ExitAction::BranchTo(
+                        self.unit.check_target(table[idx].1)?,
+                    ))),
+                    Err(_) => Ok(Some(ExitAction::Jump(addr))),
+                };
+            }
+            Inst::Trap(trap) => return Err(trap.clone()),
+            Inst::Instruction(pc, len) => {
+                frame.retire_instruction();
+                frame.instruction = Some((*pc, *len));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl<State: RegisterMap> InterpreterExecutable<State> {
+    /// Executes the instruction at `pc`, which is part of `block`
+    fn step(
+        &self,
+        frame: &mut Frame<State>,
+        block: usize,
+        pc: usize,
+    ) -> Result<Option<ExitAction>, Trap> {
+        let inst = &self.unit.insts[pc];
+        match &self.tracer {
+            Some(tracer) => {
+                let op = TraceOp {
+                    block: &self.unit.blocks[block].label,
+                    index: pc - self.unit.blocks[block].start,
+                    name: inst.name(),
+                };
+                self.execute_inst_traced(inst, &op, &mut *tracer.borrow_mut(), frame)
+            }
+            None => self.execute_inst(inst, frame),
+        }
+    }
+
+    fn execute_inst_traced(
+        &self,
+        inst: &Inst,
+        op: &TraceOp,
+        tracer: &mut dyn Tracer,
+        frame: &mut Frame<State>,
+    ) -> Result<Option<ExitAction>, Trap> {
+        let mut operands = Vec::new();
+        let mut result = Ok(());
+        for src in inst.sources() {
+            match self.rv_to_immed(frame, &src) {
+                Ok(value) => operands.push(value),
+                Err(trap) => {
+                    result = Err(trap);
+                    break;
                 }
-                o => panic!("Unimplemented operation {:?}", o),
             }
         }
+        tracer.before_op(op, &operands);
+
+        let action = match result.and_then(|_| self.execute_inst(inst, frame)) {
+            Ok(action) => action,
+            Err(trap) => {
+                tracer.on_trap(op, &trap);
+                return Err(trap);
+            }
+        };
+
+        let write = match inst.dest() {
+            Some(dest) => Some((
+                dest.to_lvalue(),
+                self.rv_to_immed(frame, &dest.to_operand())?,
+            )),
+            None => None,
+        };
+        tracer.after_op(op, write);
 
-        panic!("Non-terminating block");
+        Ok(action)
+    }
+}
+
+impl InterpreterBackend {
+    /// Compiles a unit into an executable that can be used directly,
+    /// for example to debug it. Function calls in the unit trap.
+    pub fn compile<State: RegisterMap>(
+        &self,
+        unit: &TranslationUnit,
+    ) -> Result<InterpreterExecutable<State>, String> {
+        self.compile_with_functions(unit, &HostFunctions::default())
+    }
+
+    /// Same as compile, but resolves function calls against `functions`
+    pub fn compile_with_functions<State: RegisterMap>(
+        &self,
+        unit: &TranslationUnit,
+        functions: &HostFunctions,
+    ) -> Result<InterpreterExecutable<State>, String> {
+        let regs = State::register_offsets();
+        Ok(InterpreterExecutable {
+            unit: lower(unit, &regs, functions)?,
+            regs,
+            guest_memory: self.guest_memory,
+            tracer: self.tracer.clone(),
+            profiler: self.profiler.clone(),
+            _state: PhantomData,
+        })
     }
 }
 
@@ -144,23 +1371,182 @@ impl Compiler for InterpreterBackend {
         &mut self,
         unit: &TranslationUnit,
     ) -> Result<std::rc::Rc<dyn Executable<State> + 'a>, String> {
-        Ok(Rc::new(InterpreterExecutable {
-            unit: unit.clone(),
-            regs: State::register_offsets(),
-        }))
+        Ok(Rc::new(self.compile::<State>(unit)?))
+    }
+
+    fn compile_unit_with_functions<'a, State: RegisterMap + 'a>(
+        &mut self,
+        unit: &TranslationUnit,
+        functions: &HostFunctions,
+    ) -> Result<std::rc::Rc<dyn Executable<State> + 'a>, String> {
+        Ok(Rc::new(
+            self.compile_with_functions::<State>(unit, functions)?,
+        ))
     }
 }
 
-impl<State: RegisterMap> Executable<State> for InterpreterExecutable {
-    unsafe fn execute(&self, state: &mut State) {
-        let mut idx = self.unit.entrypoint.unwrap();
+impl<State: RegisterMap> InterpreterExecutable<State> {
+    /// Runs the unit from instruction `pc` of `block` until it exits or runs out of fuel.
+    /// `step` executes the instruction at a (block, pc) pair.
+    fn run(
+        &self,
+        frame: &mut Frame<State>,
+        (block, pc): (usize, usize),
+        fuel: Fuel,
+        step: impl Fn(&mut Frame<State>, usize, usize) -> Result<Option<ExitAction>, Trap>,
+    ) -> ExitReason {
+        match &self.profiler {
+            Some(profiler) => {
+                let mut profile = RunProfile::new(&self.unit);
+                let reason = self.run_blocks(frame, (block, pc), fuel, Some(&mut profile), step);
+                profile.flush(&self.unit, &mut profiler.borrow_mut());
+                reason
+            }
+            None => self.run_blocks(frame, (block, pc), fuel, None, step),
+        }
+    }
+
+    fn origin(&self) -> Origin {
+        Origin {
+            revision: self.unit.revision,
+            guest_memory: self.guest_memory,
+        }
+    }
+
+    /// The reason a run ended before executing anything
+    fn rejected(&self, trap: Trap) -> ExitReason {
+        ExitReason {
+            status: ExitStatus::Trap(trap),
+            block: self.unit.blocks[self.unit.entry].label.clone(),
+            ops_retired: 0,
+            blocks_retired: 0,
+            guest_pc: None,
+            instructions_retired: 0,
+        }
+    }
+
+    fn run_blocks(
+        &self,
+        frame: &mut Frame<State>,
+        (mut block, mut pc): (usize, usize),
+        fuel: Fuel,
+        mut profile: Option<&mut RunProfile>,
+        step: impl Fn(&mut Frame<State>, usize, usize) -> Result<Option<ExitAction>, Trap>,
+    ) -> ExitReason {
+        // First instruction of the current block executed in this run
+        let mut entered = pc;
+        let mut ops_retired = 0;
+        let mut blocks_retired = 0;
+
+        if let Some(profile) = profile.as_deref_mut() {
+            if pc == self.unit.blocks[block].start {
+                profile.enter(block);
+            }
+        }
+
+        let reason =
+            |status, block: usize, frame: &Frame<State>, ops_retired, blocks_retired| ExitReason {
+                status,
+                block: self.unit.blocks[block].label.clone(),
+                ops_retired,
+                blocks_retired,
+                guest_pc: frame.guest_pc(),
+                instructions_retired: frame.instructions_retired,
+            };
+
         loop {
-            let block = &(self.unit.blocks[idx]);
-            let exit_action = self.execute_block(&block.ops, state);
-            match exit_action {
-                ExitAction::Exit(_) => return,
-                ExitAction::BranchTo(branch_idx) => idx = branch_idx,
+            if let Inst::Instruction(..) = self.unit.insts[pc] {
+                if fuel.exhausted_at_instruction(frame.instructions_at_boundary()) {
+                    frame.retire_instruction();
+                    if let Some(profile) = profile.as_deref_mut() {
+                        profile.retire(block, (pc - entered) as u64);
+                    }
+
+                    let status = ExitStatus::OutOfFuel(frame.suspend(self.origin(), block, pc));
+                    let mut reason = reason(status, block, frame, ops_retired, blocks_retired);
+                    if let Inst::Instruction(guest_pc, _) = self.unit.insts[pc] {
+                        reason.guest_pc = Some(guest_pc);
+                    }
+                    return reason;
+                }
             }
+
+            let action = match step(frame, block, pc) {
+                Ok(action) => action,
+                Err(trap) => {
+                    if let Some(profile) = profile.as_deref_mut() {
+                        profile.retire(block, (pc - entered) as u64);
+                    }
+
+                    let status = ExitStatus::Trap(trap);
+                    return reason(status, block, frame, ops_retired, blocks_retired);
+                }
+            };
+            ops_retired += 1;
+
+            if action.is_some() {
+                if let Some(profile) = profile.as_deref_mut() {
+                    profile.retire(block, (pc - entered) as u64 + 1);
+                }
+            }
+
+            let status = match action {
+                None => {
+                    pc += 1;
+                    continue;
+                }
+                Some(ExitAction::Exit(code)) => ExitStatus::Exit(code),
+                Some(ExitAction::Jump(addr)) => ExitStatus::Jump(addr),
+                Some(ExitAction::BranchTo(idx)) => {
+                    blocks_retired += 1;
+                    if let Some(profile) = profile.as_deref_mut() {
+                        profile.edge(block, idx);
+                    }
+
+                    if fuel.exhausted(ops_retired, blocks_retired) {
+                        let start = self.unit.blocks[idx].start;
+                        let status = ExitStatus::OutOfFuel(frame.suspend(self.origin(), idx, start));
+                        return reason(status, block, frame, ops_retired, blocks_retired);
+                    }
+
+                    block = idx;
+                    pc = self.unit.blocks[block].start;
+                    entered = pc;
+                    if let Some(profile) = profile.as_deref_mut() {
+                        profile.enter(block);
+                    }
+                    continue;
+                }
+            };
+
+            // The unit is being left, which completes the current instruction
+            let guest_pc = frame.guest_pc();
+            frame.retire_instruction();
+            let mut reason = reason(status, block, frame, ops_retired, blocks_retired + 1);
+            reason.guest_pc = guest_pc;
+            return reason;
         }
     }
 }
+
+impl<State: RegisterMap> Executable<State> for InterpreterExecutable<State> {
+    unsafe fn execute_with_fuel(&self, state: &mut State, fuel: Fuel) -> ExitReason {
+        let mut frame = Frame::new(state, &self.unit);
+        self.run(
+            &mut frame,
+            self.unit.entry_point(),
+            fuel,
+            |frame, block, pc| self.step(frame, block, pc),
+        )
+    }
+
+    unsafe fn resume(&self, state: &mut State, from: ResumePoint, fuel: Fuel) -> ExitReason {
+        let (mut frame, start) = match Frame::resume(state, from, self.origin()) {
+            Ok(resumed) => resumed,
+            Err(trap) => return self.rejected(trap),
+        };
+        self.run(&mut frame, start, fuel, |frame, block, pc| {
+            self.step(frame, block, pc)
+        })
+    }
+}
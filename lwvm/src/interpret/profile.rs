@@ -0,0 +1,102 @@
+use super::lower::LoweredUnit;
+use crate::BlockLabel;
+use std::collections::{BTreeMap, HashMap};
+
+/// Execution counts of a single block
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockProfile {
+    /// Number of times execution entered the block
+    pub entries: u64,
+    /// Number of operations retired in the block, including its terminator
+    pub ops_retired: u64,
+    /// Number of times each branch edge out of the block was taken,
+    /// keyed by the label of the block branched to
+    pub edges: BTreeMap<BlockLabel, u64>,
+}
+
+/// Collects block execution counts from the units it is attached to.
+/// Counts are keyed by block label, so units sharing a label are merged.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    blocks: HashMap<BlockLabel, BlockProfile>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn block(&self, label: &str) -> Option<&BlockProfile> {
+        self.blocks.get(label)
+    }
+
+    /// All blocks executed so far, hottest (most operations retired) first
+    pub fn report(&self) -> Vec<(&BlockLabel, &BlockProfile)> {
+        let mut report: Vec<_> = self.blocks.iter().collect();
+        report.sort_by(|(l1, p1), (l2, p2)| p2.ops_retired.cmp(&p1.ops_retired).then(l1.cmp(l2)));
+        report
+    }
+
+    pub fn reset(&mut self) {
+        self.blocks.clear();
+    }
+}
+
+#[derive(Default)]
+struct RunCounts {
+    entries: u64,
+    ops_retired: u64,
+    /// Target block index and count. Branches add at most two edges, and
+    /// indirect branches one for each jump table target they take.
+    edges: Vec<(usize, u64)>,
+}
+
+/// Counts of a single run indexed by block, which are merged into a
+/// Profiler once the run ends to keep label lookups out of the hot loop
+pub(crate) struct RunProfile {
+    blocks: Vec<RunCounts>,
+}
+
+impl RunProfile {
+    pub(crate) fn new(unit: &LoweredUnit) -> Self {
+        Self {
+            blocks: unit.blocks.iter().map(|_| RunCounts::default()).collect(),
+        }
+    }
+
+    pub(crate) fn enter(&mut self, block: usize) {
+        self.blocks[block].entries += 1;
+    }
+
+    pub(crate) fn retire(&mut self, block: usize, ops: u64) {
+        self.blocks[block].ops_retired += ops;
+    }
+
+    pub(crate) fn edge(&mut self, from: usize, to: usize) {
+        let edges = &mut self.blocks[from].edges;
+        match edges.iter_mut().find(|(target, _)| *target == to) {
+            Some((_, count)) => *count += 1,
+            None => edges.push((to, 1)),
+        }
+    }
+
+    pub(crate) fn flush(self, unit: &LoweredUnit, profiler: &mut Profiler) {
+        for (block, counts) in unit.blocks.iter().zip(self.blocks) {
+            // A run resumed part way through a block retires ops in it
+            // without entering it
+            if counts.entries == 0 && counts.ops_retired == 0 {
+                continue;
+            }
+
+            let profile = profiler.blocks.entry(block.label.clone()).or_default();
+            profile.entries += counts.entries;
+            profile.ops_retired += counts.ops_retired;
+            for (target, count) in counts.edges {
+                *profile
+                    .edges
+                    .entry(unit.blocks[target].label.clone())
+                    .or_default() += count;
+            }
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use crate::{backend::Trap, BlockLabel, IntImmed, LValue};
+
+/// Identifies the operation being traced
+#[derive(Debug, Clone, Copy)]
+pub struct TraceOp<'a> {
+    pub block: &'a BlockLabel,
+    /// Index of the operation within its block
+    pub index: usize,
+    /// Name of the BasicBlockBuilder method that emits the operation
+    pub name: &'static str,
+}
+
+/// Receives every operation executed by an interpreter, along with the
+/// values it reads and writes
+pub trait Tracer {
+    /// Called before an operation executes, with the values of its
+    /// source operands in the order they appear in the operation
+    fn before_op(&mut self, op: &TraceOp, operands: &[IntImmed]);
+
+    /// Called after an operation executes, with its destination and
+    /// the value the destination holds afterwards
+    fn after_op(&mut self, op: &TraceOp, write: Option<(LValue, IntImmed)>);
+
+    /// Called instead of after_op when an operation traps. If one of its
+    /// source operands could not be read, before_op was given only the
+    /// operands before it.
+    fn on_trap(&mut self, _op: &TraceOp, _trap: &Trap) {}
+}
@@ -0,0 +1,287 @@
+use super::{
+    lower::{Dest, Operand, VectorOperand},
+    Frame, InterpreterExecutable,
+};
+use crate::{
+    backend::Trap,
+    ir::{
+        reg::RegisterMap,
+        types::{LaneType, VectorImmed, VectorType},
+    },
+    Comparator,
+};
+
+/// Sign extends the low `bits` bits of a lane value
+fn lane_signed(value: u64, bits: u8) -> i64 {
+    let shift = 64 - bits as u32;
+    ((value << shift) as i64) >> shift
+}
+
+fn lane_cmp(cmp: &Comparator, a: u64, b: u64, bits: u8) -> bool {
+    match cmp {
+        Comparator::EQ => a == b,
+        Comparator::NEQ => a != b,
+        Comparator::SLT => lane_signed(a, bits) < lane_signed(b, bits),
+        Comparator::SGT => lane_signed(a, bits) > lane_signed(b, bits),
+        Comparator::SLE => lane_signed(a, bits) <= lane_signed(b, bits),
+        Comparator::SGE => lane_signed(a, bits) >= lane_signed(b, bits),
+        Comparator::ULT => a < b,
+        Comparator::UGT => a > b,
+        Comparator::ULE => a <= b,
+        Comparator::UGE => a >= b,
+    }
+}
+
+impl<State: RegisterMap> InterpreterExecutable<State> {
+    fn rv_to_vector(&self, frame: &Frame<State>, rv: &VectorOperand) -> Result<VectorImmed, Trap> {
+        match rv {
+            VectorOperand::Immediate(v) => Ok(*v),
+            VectorOperand::Register(reg) => Ok(unsafe { reg.read_vector(frame.state) }),
+            VectorOperand::Scratch(s) => frame.scratch.read_vector(*s),
+        }
+    }
+
+    /// Vectors written to a register must be the width of the register
+    fn write_vector(
+        &self,
+        dest: &Dest,
+        value: VectorImmed,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        match dest {
+            Dest::Register(_, reg) => {
+                if reg.ty.size() as usize != value.bytes().len() * 8 {
+                    return Err(Trap::TypeMismatch);
+                }
+                unsafe { reg.write_vector(&value, frame.state) };
+            }
+            Dest::Scratch(s) => frame.scratch.write_vector(*s, value),
+        }
+        Ok(())
+    }
+
+    /// Applies `op` to each pair of zero extended lanes of two vectors
+    /// of the same width, keeping the low bits of each result
+    pub(super) fn op_vector_binary(
+        &self,
+        dest: &Dest,
+        arg1: &VectorOperand,
+        arg2: &VectorOperand,
+        lane: LaneType,
+        op: impl Fn(u64, u64) -> u64,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let arg1 = self.rv_to_vector(frame, arg1)?;
+        let arg2 = self.rv_to_vector(frame, arg2)?;
+        if arg1.get_type() != arg2.get_type() {
+            return Err(Trap::TypeMismatch);
+        }
+
+        let mut value = arg1;
+        for i in 0..arg1.lanes(lane) {
+            value.set_lane(lane, i, op(arg1.lane(lane, i), arg2.lane(lane, i)));
+        }
+        self.write_vector(dest, value, frame)
+    }
+
+    /// Picks the lane of `arg1` where `cmp` holds for the pair of lanes,
+    /// and the lane of `arg2` elsewhere
+    pub(super) fn op_vector_pick(
+        &self,
+        dest: &Dest,
+        cmp: Comparator,
+        arg1: &VectorOperand,
+        arg2: &VectorOperand,
+        lane: LaneType,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let bits = lane.size();
+        self.op_vector_binary(
+            dest,
+            arg1,
+            arg2,
+            lane,
+            |a, b| if lane_cmp(&cmp, a, b, bits) { a } else { b },
+            frame,
+        )
+    }
+
+    pub(super) fn op_vector_cmp(
+        &self,
+        dest: &Dest,
+        cmp: &Comparator,
+        arg1: &VectorOperand,
+        arg2: &VectorOperand,
+        lane: LaneType,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let bits = lane.size();
+        self.op_vector_binary(
+            dest,
+            arg1,
+            arg2,
+            lane,
+            |a, b| {
+                if lane_cmp(cmp, a, b, bits) {
+                    u64::MAX
+                } else {
+                    0
+                }
+            },
+            frame,
+        )
+    }
+
+    /// Applies `op` to each lane and the zero extended shift amount
+    fn vector_shift(
+        &self,
+        dest: &Dest,
+        arg1: &VectorOperand,
+        amount: &Operand,
+        lane: LaneType,
+        op: impl Fn(u64, u128) -> u64,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let vector = self.rv_to_vector(frame, arg1)?;
+        let amount = self.rv_to_immed(frame, amount)?.to_u128();
+
+        let mut value = vector;
+        for i in 0..vector.lanes(lane) {
+            value.set_lane(lane, i, op(vector.lane(lane, i), amount));
+        }
+        self.write_vector(dest, value, frame)
+    }
+
+    /// Shifting by at least the lane width produces zero
+    pub(super) fn op_vector_shift_left(
+        &self,
+        dest: &Dest,
+        arg1: &VectorOperand,
+        amount: &Operand,
+        lane: LaneType,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let bits = lane.size() as u128;
+        self.vector_shift(
+            dest,
+            arg1,
+            amount,
+            lane,
+            |v, amount| if amount >= bits { 0 } else { v << amount },
+            frame,
+        )
+    }
+
+    /// Behaves like the scalar right shifts on each lane
+    pub(super) fn op_vector_shift_right(
+        &self,
+        dest: &Dest,
+        arg1: &VectorOperand,
+        amount: &Operand,
+        lane: LaneType,
+        signed: bool,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let bits = lane.size();
+        self.vector_shift(
+            dest,
+            arg1,
+            amount,
+            lane,
+            |v, amount| {
+                if signed {
+                    (lane_signed(v, bits) >> amount.min(bits as u128 - 1)) as u64
+                } else if amount >= bits as u128 {
+                    0
+                } else {
+                    v >> amount
+                }
+            },
+            frame,
+        )
+    }
+
+    pub(super) fn op_vector_shuffle(
+        &self,
+        dest: &Dest,
+        arg1: &VectorOperand,
+        arg2: &VectorOperand,
+        indices: &[u8],
+        lane: LaneType,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let arg1 = self.rv_to_vector(frame, arg1)?;
+        let arg2 = self.rv_to_vector(frame, arg2)?;
+        let lanes = arg1.lanes(lane);
+        if arg1.get_type() != arg2.get_type() || indices.len() != lanes {
+            return Err(Trap::TypeMismatch);
+        }
+
+        let mut value = arg1;
+        for (i, idx) in indices.iter().map(|idx| *idx as usize).enumerate() {
+            let v = if idx < lanes {
+                arg1.lane(lane, idx)
+            } else if idx < 2 * lanes {
+                arg2.lane(lane, idx - lanes)
+            } else {
+                0
+            };
+            value.set_lane(lane, i, v);
+        }
+        self.write_vector(dest, value, frame)
+    }
+
+    pub(super) fn op_vector_insert_lane(
+        &self,
+        dest: &Dest,
+        vector: &VectorOperand,
+        value: &Operand,
+        idx: u8,
+        lane: LaneType,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let mut vector = self.rv_to_vector(frame, vector)?;
+        let value = self.rv_to_immed(frame, value)?.to_u64();
+        if idx as usize >= vector.lanes(lane) {
+            return Err(Trap::InvalidLane(idx));
+        }
+
+        vector.set_lane(lane, idx as usize, value);
+        self.write_vector(dest, vector, frame)
+    }
+
+    pub(super) fn op_vector_extract_lane(
+        &self,
+        dest: &Dest,
+        vector: &VectorOperand,
+        idx: u8,
+        lane: LaneType,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let vector = self.rv_to_vector(frame, vector)?;
+        if idx as usize >= vector.lanes(lane) {
+            return Err(Trap::InvalidLane(idx));
+        }
+
+        let value = lane.to_int_type().from_u64(vector.lane(lane, idx as usize));
+        self.write_lvalue(dest, value, false, frame);
+        Ok(())
+    }
+
+    pub(super) fn op_vector_splat(
+        &self,
+        dest: &Dest,
+        value: &Operand,
+        lane: LaneType,
+        ty: VectorType,
+        frame: &mut Frame<State>,
+    ) -> Result<(), Trap> {
+        let value = self.rv_to_immed(frame, value)?.to_u64();
+
+        let mut vector = ty.zero();
+        for i in 0..vector.lanes(lane) {
+            vector.set_lane(lane, i, value);
+        }
+        self.write_vector(dest, vector, frame)
+    }
+}
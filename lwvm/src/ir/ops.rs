@@ -1,11 +1,45 @@
-use crate::ir::types::{BlockLabel, Comparator, IntImmed, IntType, LValue, RValue};
+use crate::ir::types::{
+    AtomicOp, BlockLabel, Comparator, Condition, Endianness, FlagOp, FloatComparator, FloatImmed,
+    FloatType, IntImmed, IntType, LValue, LaneType, MemoryOrdering, MultSignedness, RValue,
+    StructType, StructTypeImmed, TypeImmed, VectorImmed, VectorType,
+};
 
 #[derive(Debug, Clone)]
 pub(crate) enum Operation {
-    Add(LValue, RValue<IntImmed>, RValue<IntImmed>, bool),
-    Sub(LValue, RValue<IntImmed>, RValue<IntImmed>, bool),
+    /// Add, Sub, And, Or and Xor record their flags from the operands they
+    /// read if their last field is set
+    Add(LValue, RValue<IntImmed>, RValue<IntImmed>, bool, bool),
+    Sub(LValue, RValue<IntImmed>, RValue<IntImmed>, bool, bool),
 
     Mult(LValue, RValue<IntImmed>, RValue<IntImmed>, bool),
+
+    AddCarry(
+        LValue,
+        LValue,
+        RValue<IntImmed>,
+        RValue<IntImmed>,
+        RValue<IntImmed>,
+        bool,
+    ),
+    SubBorrow(
+        LValue,
+        LValue,
+        RValue<IntImmed>,
+        RValue<IntImmed>,
+        RValue<IntImmed>,
+        bool,
+    ),
+    AddOverflow(LValue, LValue, RValue<IntImmed>, RValue<IntImmed>, bool),
+    SubOverflow(LValue, LValue, RValue<IntImmed>, RValue<IntImmed>, bool),
+    MultOverflow(LValue, LValue, RValue<IntImmed>, RValue<IntImmed>, bool),
+    MultWide(
+        LValue,
+        LValue,
+        RValue<IntImmed>,
+        RValue<IntImmed>,
+        MultSignedness,
+    ),
+    MultHigh(LValue, RValue<IntImmed>, RValue<IntImmed>, MultSignedness),
     Div(LValue, RValue<IntImmed>, RValue<IntImmed>, bool),
     Rem(LValue, RValue<IntImmed>, RValue<IntImmed>, bool),
 
@@ -14,22 +48,238 @@ pub(crate) enum Operation {
     SignExtend(LValue, RValue<IntImmed>, IntType),
     ZeroExtend(LValue, RValue<IntImmed>, IntType),
 
-    And(LValue, RValue<IntImmed>, RValue<IntImmed>),
-    Or(LValue, RValue<IntImmed>, RValue<IntImmed>),
-    Xor(LValue, RValue<IntImmed>, RValue<IntImmed>),
+    RotateLeft(LValue, RValue<IntImmed>, RValue<IntImmed>),
+    RotateRight(LValue, RValue<IntImmed>, RValue<IntImmed>),
+    RotateLeftCarry(
+        LValue,
+        LValue,
+        RValue<IntImmed>,
+        RValue<IntImmed>,
+        RValue<IntImmed>,
+    ),
+    RotateRightCarry(
+        LValue,
+        LValue,
+        RValue<IntImmed>,
+        RValue<IntImmed>,
+        RValue<IntImmed>,
+    ),
+    CountLeadingZeros(LValue, RValue<IntImmed>),
+    CountTrailingZeros(LValue, RValue<IntImmed>),
+    PopCount(LValue, RValue<IntImmed>),
+    ByteSwap(LValue, RValue<IntImmed>),
+    BitReverse(LValue, RValue<IntImmed>),
+
+    And(LValue, RValue<IntImmed>, RValue<IntImmed>, bool),
+    Or(LValue, RValue<IntImmed>, RValue<IntImmed>, bool),
+    Xor(LValue, RValue<IntImmed>, RValue<IntImmed>, bool),
     Not(LValue, RValue<IntImmed>),
 
-    HostReadMem(LValue, RValue<IntImmed>),
-    HostWriteMem(RValue<IntImmed>, RValue<IntImmed>),
-    //FnCall(RValue<IntImmed>, Vec<RValue<IntImmed>>),
-    GuestReadMem(LValue, RValue<IntImmed>, u8),
-    GuestWriteMem(RValue<IntImmed>, RValue<IntImmed>, u8),
+    FAdd(LValue, RValue<FloatImmed>, RValue<FloatImmed>),
+    FSub(LValue, RValue<FloatImmed>, RValue<FloatImmed>),
+    FMult(LValue, RValue<FloatImmed>, RValue<FloatImmed>),
+    FDiv(LValue, RValue<FloatImmed>, RValue<FloatImmed>),
+    FSqrt(LValue, RValue<FloatImmed>),
+    FCmp(
+        LValue,
+        FloatComparator,
+        RValue<FloatImmed>,
+        RValue<FloatImmed>,
+    ),
+
+    IntToFloat(LValue, RValue<IntImmed>, FloatType, bool),
+    FloatToInt(LValue, RValue<FloatImmed>, IntType, bool),
+    FloatToBits(LValue, RValue<FloatImmed>),
+    BitsToFloat(LValue, RValue<IntImmed>),
+
+    VAdd(LValue, RValue<VectorImmed>, RValue<VectorImmed>, LaneType),
+    VSub(LValue, RValue<VectorImmed>, RValue<VectorImmed>, LaneType),
+    VMult(LValue, RValue<VectorImmed>, RValue<VectorImmed>, LaneType),
+    VMin(
+        LValue,
+        RValue<VectorImmed>,
+        RValue<VectorImmed>,
+        LaneType,
+        bool,
+    ),
+    VMax(
+        LValue,
+        RValue<VectorImmed>,
+        RValue<VectorImmed>,
+        LaneType,
+        bool,
+    ),
+    VCmp(
+        LValue,
+        Comparator,
+        RValue<VectorImmed>,
+        RValue<VectorImmed>,
+        LaneType,
+    ),
+    VShiftLeft(LValue, RValue<VectorImmed>, RValue<IntImmed>, LaneType),
+    VShiftRight(
+        LValue,
+        RValue<VectorImmed>,
+        RValue<IntImmed>,
+        LaneType,
+        bool,
+    ),
+    VShuffle(
+        LValue,
+        RValue<VectorImmed>,
+        RValue<VectorImmed>,
+        Vec<u8>,
+        LaneType,
+    ),
+    VInsertLane(LValue, RValue<VectorImmed>, RValue<IntImmed>, u8, LaneType),
+    VExtractLane(LValue, RValue<VectorImmed>, u8, LaneType),
+    VSplat(LValue, RValue<IntImmed>, LaneType, VectorType),
+
+    /// Accesses of the given width, with whether the address is assumed
+    /// to be aligned and whether the access is volatile
+    HostReadMem(LValue, RValue<IntImmed>, IntType, bool, bool),
+    HostWriteMem(RValue<IntImmed>, RValue<IntImmed>, IntType, bool, bool),
+    FnCall(Option<LValue>, String, Vec<RValue<IntImmed>>),
+    /// Accesses without an endianness use the unit's default
+    GuestReadMem(LValue, RValue<IntImmed>, u8, Option<Endianness>),
+    GuestWriteMem(RValue<IntImmed>, RValue<IntImmed>, u8, Option<Endianness>),
+    GuestReadStruct(LValue, RValue<IntImmed>, StructType),
+    GuestWriteStruct(RValue<IntImmed>, RValue<StructTypeImmed>),
+    GuestAtomicRmw(
+        LValue,
+        AtomicOp,
+        RValue<IntImmed>,
+        RValue<IntImmed>,
+        u8,
+        MemoryOrdering,
+    ),
+    GuestCompareExchange(
+        LValue,
+        RValue<IntImmed>,
+        RValue<IntImmed>,
+        RValue<IntImmed>,
+        u8,
+        MemoryOrdering,
+    ),
+    GuestLoadLinked(LValue, RValue<IntImmed>, u8, MemoryOrdering),
+    GuestStoreConditional(
+        LValue,
+        RValue<IntImmed>,
+        RValue<IntImmed>,
+        u8,
+        MemoryOrdering,
+    ),
+    Fence(MemoryOrdering),
+
+    ExtractField(LValue, RValue<StructTypeImmed>, u8),
+    InsertField(LValue, RValue<StructTypeImmed>, RValue<TypeImmed>, u8),
 
     ICmp(LValue, Comparator, RValue<IntImmed>, RValue<IntImmed>),
     Select(RValue<IntImmed>, LValue, RValue<IntImmed>, RValue<IntImmed>),
 
+    SetFlags(FlagOp, RValue<IntImmed>, RValue<IntImmed>, bool),
+    ReadCondition(LValue, Condition),
+    SelectCondition(Condition, LValue, RValue<IntImmed>, RValue<IntImmed>),
+
     Branch(RValue<IntImmed>, BlockLabel, BlockLabel),
+    BranchCondition(Condition, BlockLabel, BlockLabel),
+    CompareBranch(
+        Comparator,
+        RValue<IntImmed>,
+        RValue<IntImmed>,
+        BlockLabel,
+        BlockLabel,
+    ),
+    /// Branches to the block the jump table maps the target to, or leaves
+    /// the unit with the target guest address if it has no entry
+    IndirectBranch(RValue<IntImmed>, Vec<(u64, BlockLabel)>),
     Exit(u8),
 
-    Instruction(),
+    /// Marks the start of a guest instruction at the given guest PC,
+    /// with the given length in bytes
+    Instruction(u64, u8),
+}
+
+impl Operation {
+    /// Values written by the operation
+    pub(crate) fn dests(&self) -> Vec<LValue> {
+        match self {
+            Self::AddCarry(d1, d2, ..)
+            | Self::SubBorrow(d1, d2, ..)
+            | Self::AddOverflow(d1, d2, ..)
+            | Self::SubOverflow(d1, d2, ..)
+            | Self::MultOverflow(d1, d2, ..)
+            | Self::MultWide(d1, d2, ..)
+            | Self::RotateLeftCarry(d1, d2, ..)
+            | Self::RotateRightCarry(d1, d2, ..) => vec![*d1, *d2],
+            Self::Add(d, ..)
+            | Self::Sub(d, ..)
+            | Self::Mult(d, ..)
+            | Self::MultHigh(d, ..)
+            | Self::Div(d, ..)
+            | Self::Rem(d, ..)
+            | Self::LShift(d, ..)
+            | Self::RShift(d, ..)
+            | Self::SignExtend(d, ..)
+            | Self::ZeroExtend(d, ..)
+            | Self::RotateLeft(d, ..)
+            | Self::RotateRight(d, ..)
+            | Self::CountLeadingZeros(d, ..)
+            | Self::CountTrailingZeros(d, ..)
+            | Self::PopCount(d, ..)
+            | Self::ByteSwap(d, ..)
+            | Self::BitReverse(d, ..)
+            | Self::And(d, ..)
+            | Self::Or(d, ..)
+            | Self::Xor(d, ..)
+            | Self::Not(d, ..)
+            | Self::FAdd(d, ..)
+            | Self::FSub(d, ..)
+            | Self::FMult(d, ..)
+            | Self::FDiv(d, ..)
+            | Self::FSqrt(d, ..)
+            | Self::FCmp(d, ..)
+            | Self::IntToFloat(d, ..)
+            | Self::FloatToInt(d, ..)
+            | Self::FloatToBits(d, ..)
+            | Self::BitsToFloat(d, ..)
+            | Self::VAdd(d, ..)
+            | Self::VSub(d, ..)
+            | Self::VMult(d, ..)
+            | Self::VMin(d, ..)
+            | Self::VMax(d, ..)
+            | Self::VCmp(d, ..)
+            | Self::VShiftLeft(d, ..)
+            | Self::VShiftRight(d, ..)
+            | Self::VShuffle(d, ..)
+            | Self::VInsertLane(d, ..)
+            | Self::VExtractLane(d, ..)
+            | Self::VSplat(d, ..)
+            | Self::HostReadMem(d, ..)
+            | Self::GuestReadMem(d, ..)
+            | Self::GuestReadStruct(d, ..)
+            | Self::GuestAtomicRmw(d, ..)
+            | Self::GuestCompareExchange(d, ..)
+            | Self::GuestLoadLinked(d, ..)
+            | Self::GuestStoreConditional(d, ..)
+            | Self::ExtractField(d, ..)
+            | Self::InsertField(d, ..)
+            | Self::ICmp(d, ..)
+            | Self::Select(_, d, ..)
+            | Self::ReadCondition(d, _)
+            | Self::SelectCondition(_, d, ..) => vec![*d],
+            Self::FnCall(d, ..) => d.iter().copied().collect(),
+            Self::HostWriteMem(..)
+            | Self::GuestWriteMem(..)
+            | Self::GuestWriteStruct(..)
+            | Self::Fence(_)
+            | Self::SetFlags(..)
+            | Self::Branch(..)
+            | Self::BranchCondition(..)
+            | Self::CompareBranch(..)
+            | Self::IndirectBranch(..)
+            | Self::Exit(_)
+            | Self::Instruction(..) => vec![],
+        }
+    }
 }
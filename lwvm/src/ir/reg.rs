@@ -1,21 +1,27 @@
+use crate::backend::Trap;
+use crate::ir::types::{StructTypeImmed, TypeImmed, VectorImmed};
 use crate::IntImmed;
 
+#[derive(Debug, Clone, Copy)]
 pub enum RegisterType {
     I8,
     I16,
     I32,
     I64,
-    // TODO: Support 128 bit integer registers
-    // TODO: Support floating point registers
-    // TODO: Support vector registers
+    I128,
+    F32,
+    F64,
+    V128,
+    V256,
 }
 
 /// Allows a type to be used as a RegisterType within
 /// a state struct definition.
-/// Safety:
+///
+/// # Safety
 /// - The type must have the same size as the register it maps to
 /// - The memory layout of the type should match that of the register type
-/// it is mapped to (e.g. mapping f32 to RegisterType::I32 is invalid)
+///   it is mapped to (e.g. mapping f32 to RegisterType::I32 is invalid)
 /// - to_reg_type() must return the same register type on every call
 unsafe trait AsRegister {
     fn to_reg_type() -> RegisterType;
@@ -45,6 +51,36 @@ unsafe impl AsRegister for u64 {
     }
 }
 
+unsafe impl AsRegister for u128 {
+    fn to_reg_type() -> RegisterType {
+        RegisterType::I128
+    }
+}
+
+unsafe impl AsRegister for [u8; 16] {
+    fn to_reg_type() -> RegisterType {
+        RegisterType::V128
+    }
+}
+
+unsafe impl AsRegister for [u8; 32] {
+    fn to_reg_type() -> RegisterType {
+        RegisterType::V256
+    }
+}
+
+unsafe impl AsRegister for f32 {
+    fn to_reg_type() -> RegisterType {
+        RegisterType::F32
+    }
+}
+
+unsafe impl AsRegister for f64 {
+    fn to_reg_type() -> RegisterType {
+        RegisterType::F64
+    }
+}
+
 pub trait RegisterMap {
     fn register_offsets() -> Vec<Register>;
 }
@@ -60,39 +96,202 @@ impl<T: AsRegister, const N: usize> RegisterMap for [T; N] {
     }
 }
 
+impl RegisterType {
+    /// Width of the register in bits
+    pub(crate) fn size(&self) -> u16 {
+        match self {
+            Self::I8 => 8,
+            Self::I16 => 16,
+            Self::I32 | Self::F32 => 32,
+            Self::I64 | Self::F64 => 64,
+            Self::I128 | Self::V128 => 128,
+            Self::V256 => 256,
+        }
+    }
+
+    pub(crate) fn is_vector(&self) -> bool {
+        matches!(self, Self::V128 | Self::V256)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct Register {
     pub(crate) offset: usize,
     pub(crate) ty: RegisterType,
 }
 
+/// Float registers hold the bit pattern of their value, and are read and
+/// written like integer registers of the same width. Vector registers can
+/// only be accessed as vectors, which lowering checks for every operand.
 impl Register {
     pub(crate) unsafe fn read<State: RegisterMap>(&self, state: &State) -> IntImmed {
         let state = (state as *const State as *const u8).add(self.offset);
         match self.ty {
             RegisterType::I8 => IntImmed::I8(*state),
             RegisterType::I16 => IntImmed::I16(*(state as *const u16)),
-            RegisterType::I32 => IntImmed::I32(*(state as *const u32)),
-            RegisterType::I64 => IntImmed::I64(*(state as *const u64)),
+            RegisterType::I32 | RegisterType::F32 => IntImmed::I32(*(state as *const u32)),
+            RegisterType::I64 | RegisterType::F64 => IntImmed::I64(*(state as *const u64)),
+            RegisterType::I128 => IntImmed::I128(*(state as *const u128)),
+            RegisterType::V128 | RegisterType::V256 => unreachable!("Vector register read"),
         }
     }
 
-    pub(crate) fn trunc_to_type(&self, immed: IntImmed) -> IntImmed {
-        let value = immed.to_u64();
+    /// Reads a value of this register's type from an arbitrary host address
+    pub(crate) unsafe fn load(&self, ptr: *const u8) -> IntImmed {
         match self.ty {
-            RegisterType::I8 => IntImmed::I8(value as u8),
-            RegisterType::I16 => IntImmed::I16(value as u16),
-            RegisterType::I32 => IntImmed::I32(value as u32),
-            RegisterType::I64 => IntImmed::I64(value as u64),
+            RegisterType::I8 => IntImmed::I8(*ptr),
+            RegisterType::I16 => IntImmed::I16((ptr as *const u16).read_unaligned()),
+            RegisterType::I32 | RegisterType::F32 => {
+                IntImmed::I32((ptr as *const u32).read_unaligned())
+            }
+            RegisterType::I64 | RegisterType::F64 => {
+                IntImmed::I64((ptr as *const u64).read_unaligned())
+            }
+            RegisterType::I128 => IntImmed::I128((ptr as *const u128).read_unaligned()),
+            RegisterType::V128 | RegisterType::V256 => unreachable!("Vector register load"),
         }
     }
 
-    pub(crate) unsafe fn write<State: RegisterMap>(&self, value: u64, state: &mut State) {
+    /// Writes the low bits of `value` to an arbitrary host address at this
+    /// register's width
+    pub(crate) unsafe fn store(&self, value: u128, ptr: *mut u8) {
+        match self.ty {
+            RegisterType::I8 => *ptr = value as u8,
+            RegisterType::I16 => (ptr as *mut u16).write_unaligned(value as u16),
+            RegisterType::I32 | RegisterType::F32 => {
+                (ptr as *mut u32).write_unaligned(value as u32)
+            }
+            RegisterType::I64 | RegisterType::F64 => {
+                (ptr as *mut u64).write_unaligned(value as u64)
+            }
+            RegisterType::I128 => (ptr as *mut u128).write_unaligned(value),
+            RegisterType::V128 | RegisterType::V256 => unreachable!("Vector register store"),
+        }
+    }
+
+    pub(crate) unsafe fn write<State: RegisterMap>(&self, value: u128, state: &mut State) {
         let reg = (state as *mut State as *mut u8).add(self.offset);
         match self.ty {
             RegisterType::I8 => *reg = value as u8,
             RegisterType::I16 => *(reg as *mut u16) = value as u16,
-            RegisterType::I32 => *(reg as *mut u32) = value as u32,
-            RegisterType::I64 => *(reg as *mut u64) = value,
+            RegisterType::I32 | RegisterType::F32 => *(reg as *mut u32) = value as u32,
+            RegisterType::I64 | RegisterType::F64 => *(reg as *mut u64) = value as u64,
+            RegisterType::I128 => *(reg as *mut u128) = value,
+            RegisterType::V128 | RegisterType::V256 => unreachable!("Vector register write"),
+        }
+    }
+
+    pub(crate) unsafe fn read_vector<State: RegisterMap>(&self, state: &State) -> VectorImmed {
+        let state = (state as *const State as *const u8).add(self.offset);
+        let mut value = match self.ty {
+            RegisterType::V256 => VectorImmed::V256([0; 32]),
+            _ => VectorImmed::V128([0; 16]),
+        };
+        let bytes = value.bytes_mut();
+        core::ptr::copy_nonoverlapping(state, bytes.as_mut_ptr(), bytes.len());
+        value
+    }
+
+    /// The value must be the same width as the register
+    pub(crate) unsafe fn write_vector<State: RegisterMap>(
+        &self,
+        value: &VectorImmed,
+        state: &mut State,
+    ) {
+        let reg = (state as *mut State as *mut u8).add(self.offset);
+        let bytes = value.bytes();
+        core::ptr::copy_nonoverlapping(bytes.as_ptr(), reg, bytes.len());
+    }
+}
+
+/// Temporary values used by a unit. Scratch values are not part of the
+/// register state, live from the start of a run until the unit exits
+/// (carried across resumptions), and take the type of the last value
+/// written to them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ScratchFile {
+    values: Vec<Option<ScratchValue>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ScratchValue {
+    Int(IntImmed),
+    Vector(VectorImmed),
+    Struct(StructTypeImmed),
+}
+
+impl ScratchFile {
+    /// Creates an empty file with room for scratch values below `slots`.
+    /// Writing a higher index grows the file.
+    pub(crate) fn new(slots: usize) -> Self {
+        Self {
+            values: vec![None; slots],
         }
     }
+
+    fn slot(&self, idx: u8) -> Option<&ScratchValue> {
+        self.values.get(idx as usize)?.as_ref()
+    }
+
+    fn set(&mut self, idx: u8, value: ScratchValue) {
+        let idx = idx as usize;
+        if idx >= self.values.len() {
+            self.values.resize(idx + 1, None);
+        }
+        self.values[idx] = Some(value);
+    }
+
+    /// Returns the integer value, if one has been written
+    pub(crate) fn get(&self, idx: u8) -> Option<IntImmed> {
+        match self.slot(idx) {
+            Some(ScratchValue::Int(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn read(&self, idx: u8) -> Result<IntImmed, Trap> {
+        match self.slot(idx) {
+            Some(ScratchValue::Int(value)) => Ok(*value),
+            Some(_) => Err(Trap::TypeMismatch),
+            None => Err(Trap::UninitializedScratch(idx)),
+        }
+    }
+
+    pub(crate) fn read_vector(&self, idx: u8) -> Result<VectorImmed, Trap> {
+        match self.slot(idx) {
+            Some(ScratchValue::Vector(value)) => Ok(*value),
+            Some(_) => Err(Trap::TypeMismatch),
+            None => Err(Trap::UninitializedScratch(idx)),
+        }
+    }
+
+    pub(crate) fn read_struct(&self, idx: u8) -> Result<&StructTypeImmed, Trap> {
+        match self.slot(idx) {
+            Some(ScratchValue::Struct(value)) => Ok(value),
+            Some(_) => Err(Trap::TypeMismatch),
+            None => Err(Trap::UninitializedScratch(idx)),
+        }
+    }
+
+    /// Reads an integer or struct value
+    pub(crate) fn read_value(&self, idx: u8) -> Result<TypeImmed, Trap> {
+        match self.slot(idx) {
+            Some(ScratchValue::Int(value)) => Ok(TypeImmed::Integer(*value)),
+            Some(ScratchValue::Struct(value)) => Ok(TypeImmed::Struct(value.clone())),
+            Some(ScratchValue::Vector(_)) => Err(Trap::TypeMismatch),
+            None => Err(Trap::UninitializedScratch(idx)),
+        }
+    }
+
+    pub(crate) fn write(&mut self, idx: u8, value: IntImmed) {
+        self.set(idx, ScratchValue::Int(value));
+    }
+
+    pub(crate) fn write_vector(&mut self, idx: u8, value: VectorImmed) {
+        self.set(idx, ScratchValue::Vector(value));
+    }
+
+    pub(crate) fn write_struct(&mut self, idx: u8, value: StructTypeImmed) {
+        self.set(idx, ScratchValue::Struct(value));
+    }
 }
@@ -7,6 +7,7 @@ pub enum IntType {
     I16,
     I32,
     I64,
+    I128,
 }
 
 macro_rules! impl_from_type {
@@ -18,6 +19,7 @@ macro_rules! impl_from_type {
                 Self::I16 => IntImmed::I16(v as u16),
                 Self::I32 => IntImmed::I32(v as u32),
                 Self::I64 => IntImmed::I64(v as u64),
+                Self::I128 => IntImmed::I128(v as u128),
             }
         }
     };
@@ -32,30 +34,47 @@ macro_rules! impl_from_type_signed {
                 Self::I16 => IntImmed::I16(v as i16 as u16),
                 Self::I32 => IntImmed::I32(v as i32 as u32),
                 Self::I64 => IntImmed::I64(v as i64 as u64),
+                Self::I128 => IntImmed::I128(v as i128 as u128),
             }
         }
     };
 }
 
+#[allow(clippy::wrong_self_convention)]
 impl IntType {
     impl_from_type!(u8, from_u8);
     impl_from_type!(u16, from_u16);
     impl_from_type!(u32, from_u32);
     impl_from_type!(u64, from_u64);
+    impl_from_type!(u128, from_u128);
 
     impl_from_type_signed!(i8, from_u8_signed);
     impl_from_type_signed!(i16, from_u16_signed);
     impl_from_type_signed!(i32, from_u32_signed);
     impl_from_type_signed!(i64, from_u64_signed);
+    impl_from_type_signed!(i128, from_u128_signed);
+
+    /// Width of the type in bits. Bools are 1 bit wide.
+    pub fn size(&self) -> u8 {
+        match self {
+            Self::Bool => 1,
+            Self::I8 => 8,
+            Self::I16 => 16,
+            Self::I32 => 32,
+            Self::I64 => 64,
+            Self::I128 => 128,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IntImmed {
     Bool(bool),
     I8(u8),
     I16(u16),
     I32(u32),
     I64(u64),
+    I128(u128),
 }
 
 impl From<u32> for IntImmed {
@@ -71,6 +90,7 @@ pub enum ZippedIntImmed {
     I16(u16, u16),
     I32(u32, u32),
     I64(u64, u64),
+    I128(u128, u128),
 }
 
 macro_rules! impl_maybe_type {
@@ -90,6 +110,7 @@ impl IntImmed {
     impl_maybe_type!(u16, I16, maybe_u16);
     impl_maybe_type!(u32, I32, maybe_u32);
     impl_maybe_type!(u64, I64, maybe_u64);
+    impl_maybe_type!(u128, I128, maybe_u128);
 
     pub fn size(&self) -> u8 {
         match self {
@@ -98,26 +119,39 @@ impl IntImmed {
             Self::I16(_) => 16,
             Self::I32(_) => 32,
             Self::I64(_) => 64,
+            Self::I128(_) => 128,
         }
     }
 
+    /// Zero extends the value to 64 bits, truncating 128 bit values
     pub fn to_u64(&self) -> u64 {
+        self.to_u128() as u64
+    }
+
+    /// Sign extends the value to 64 bits, truncating 128 bit values
+    pub fn to_i64(&self) -> i64 {
+        self.to_i128() as i64
+    }
+
+    pub fn to_u128(&self) -> u128 {
         match *self {
-            Self::Bool(b) => b as u64,
-            Self::I8(i) => i as u64,
-            Self::I16(i) => i as u64,
-            Self::I32(i) => i as u64,
-            Self::I64(i) => i,
+            Self::Bool(b) => b as u128,
+            Self::I8(i) => i as u128,
+            Self::I16(i) => i as u128,
+            Self::I32(i) => i as u128,
+            Self::I64(i) => i as u128,
+            Self::I128(i) => i,
         }
     }
 
-    pub fn to_i64(&self) -> i64 {
+    pub fn to_i128(&self) -> i128 {
         match *self {
-            Self::Bool(b) => b as i64,
-            Self::I8(i) => i as i8 as i64,
-            Self::I16(i) => i as i16 as i64,
-            Self::I32(i) => i as i32 as i64,
-            Self::I64(i) => i as i64,
+            Self::Bool(b) => b as i128,
+            Self::I8(i) => i as i8 as i128,
+            Self::I16(i) => i as i16 as i128,
+            Self::I32(i) => i as i32 as i128,
+            Self::I64(i) => i as i64 as i128,
+            Self::I128(i) => i as i128,
         }
     }
 
@@ -129,6 +163,7 @@ impl IntImmed {
                 Self::I16(i) => ty.from_u16_signed(*i as i16),
                 Self::I32(i) => ty.from_u32_signed(*i as i32),
                 Self::I64(i) => ty.from_u64_signed(*i as i64),
+                Self::I128(i) => ty.from_u128_signed(*i as i128),
             }
         } else {
             match self {
@@ -137,13 +172,15 @@ impl IntImmed {
                 Self::I16(i) => ty.from_u16(*i),
                 Self::I32(i) => ty.from_u32(*i),
                 Self::I64(i) => ty.from_u64(*i),
+                Self::I128(i) => ty.from_u128(*i),
             }
         }
     }
 
     /// Casts the smaller of first, second up to the larger type
-    /// Returns a pair of IntImmed values such that both are
-    /// the same internal type.
+    /// Returns a pair of IntImmed values in the same order as the
+    /// arguments, such that both are the same internal type. Operand
+    /// order matters to non-commutative operations such as sub and div.
     pub fn upcast(first: &Self, second: &Self, signed: bool) -> (Self, Self) {
         let first_size = first.size();
         let second_size = second.size();
@@ -152,24 +189,23 @@ impl IntImmed {
             return (*first, *second);
         }
 
-        let (larger, smaller) = if first_size > second_size {
-            (first, second)
+        if first_size > second_size {
+            (*first, second.cast(first.get_type(), signed))
         } else {
-            (second, first)
-        };
-
-        (*larger, smaller.cast(larger.get_type(), signed))
+            (first.cast(second.get_type(), signed), *second)
+        }
     }
 
     pub fn upcast_zip(first: &Self, second: &Self, signed: bool) -> ZippedIntImmed {
-        let (larger, smaller) = Self::upcast(first, second, signed);
-
-        match larger {
-            Self::Bool(b) => ZippedIntImmed::Bool(b, smaller.maybe_bool().unwrap()),
-            Self::I8(v) => ZippedIntImmed::I8(v, smaller.maybe_u8().unwrap()),
-            Self::I16(v) => ZippedIntImmed::I16(v, smaller.maybe_u16().unwrap()),
-            Self::I32(v) => ZippedIntImmed::I32(v, smaller.maybe_u32().unwrap()),
-            Self::I64(v) => ZippedIntImmed::I64(v, smaller.maybe_u64().unwrap()),
+        let (first, second) = Self::upcast(first, second, signed);
+
+        match first {
+            Self::Bool(b) => ZippedIntImmed::Bool(b, second.maybe_bool().unwrap()),
+            Self::I8(v) => ZippedIntImmed::I8(v, second.maybe_u8().unwrap()),
+            Self::I16(v) => ZippedIntImmed::I16(v, second.maybe_u16().unwrap()),
+            Self::I32(v) => ZippedIntImmed::I32(v, second.maybe_u32().unwrap()),
+            Self::I64(v) => ZippedIntImmed::I64(v, second.maybe_u64().unwrap()),
+            Self::I128(v) => ZippedIntImmed::I128(v, second.maybe_u128().unwrap()),
         }
     }
 
@@ -180,17 +216,171 @@ impl IntImmed {
             Self::I16(_) => IntType::I16,
             Self::I32(_) => IntType::I32,
             Self::I64(_) => IntType::I64,
+            Self::I128(_) => IntType::I128,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FloatType {
+    F32,
+    F64,
+}
+
+impl FloatType {
+    pub fn from_f64(&self, v: f64) -> FloatImmed {
+        match self {
+            Self::F32 => FloatImmed::F32(v as f32),
+            Self::F64 => FloatImmed::F64(v),
+        }
+    }
+}
+
+/// A floating point value. Outside of immediates, floats are stored as
+/// their bit pattern in a 32 or 64 bit integer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FloatImmed {
+    F32(f32),
+    F64(f64),
+}
+
+impl FloatImmed {
+    pub fn get_type(&self) -> FloatType {
+        match self {
+            Self::F32(_) => FloatType::F32,
+            Self::F64(_) => FloatType::F64,
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match *self {
+            Self::F32(f) => f as f64,
+            Self::F64(f) => f,
+        }
+    }
+
+    pub fn cast(&self, ty: FloatType) -> Self {
+        ty.from_f64(self.to_f64())
+    }
+
+    pub fn to_bits(&self) -> IntImmed {
+        match *self {
+            Self::F32(f) => IntImmed::I32(f.to_bits()),
+            Self::F64(f) => IntImmed::I64(f.to_bits()),
+        }
+    }
+
+    /// Reinterprets a 32 or 64 bit integer as a float of the same width
+    pub fn from_bits(bits: IntImmed) -> Option<Self> {
+        match bits {
+            IntImmed::I32(v) => Some(Self::F32(f32::from_bits(v))),
+            IntImmed::I64(v) => Some(Self::F64(f64::from_bits(v))),
+            _ => None,
         }
     }
 }
 
+/// Type of the lanes a vector operation splits its operands into
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LaneType {
+    I8,
+    I16,
+    I32,
+    I64,
+}
+
+impl LaneType {
+    pub fn size(&self) -> u8 {
+        match self {
+            Self::I8 => 8,
+            Self::I16 => 16,
+            Self::I32 => 32,
+            Self::I64 => 64,
+        }
+    }
+
+    pub fn to_int_type(&self) -> IntType {
+        match self {
+            Self::I8 => IntType::I8,
+            Self::I16 => IntType::I16,
+            Self::I32 => IntType::I32,
+            Self::I64 => IntType::I64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum VectorType {
+    V128,
+    V256,
+}
+
+impl VectorType {
+    pub fn zero(&self) -> VectorImmed {
+        match self {
+            Self::V128 => VectorImmed::V128([0; 16]),
+            Self::V256 => VectorImmed::V256([0; 32]),
+        }
+    }
+}
+
+/// A vector value, stored as little-endian bytes. Vectors are untyped;
+/// each operation on them gives the type of the lanes it works on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VectorImmed {
+    V128([u8; 16]),
+    V256([u8; 32]),
+}
+
+impl VectorImmed {
+    pub fn get_type(&self) -> VectorType {
+        match self {
+            Self::V128(_) => VectorType::V128,
+            Self::V256(_) => VectorType::V256,
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            Self::V128(b) => b,
+            Self::V256(b) => b,
+        }
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        match self {
+            Self::V128(b) => b,
+            Self::V256(b) => b,
+        }
+    }
+
+    pub fn lanes(&self, ty: LaneType) -> usize {
+        self.bytes().len() * 8 / ty.size() as usize
+    }
+
+    /// Zero extended value of lane `idx`, which must be less than `lanes(ty)`
+    pub fn lane(&self, ty: LaneType, idx: usize) -> u64 {
+        let width = ty.size() as usize / 8;
+        let mut buf = [0u8; 8];
+        buf[..width].copy_from_slice(&self.bytes()[idx * width..(idx + 1) * width]);
+        u64::from_le_bytes(buf)
+    }
+
+    /// Sets lane `idx` to the low bits of `value`
+    pub fn set_lane(&mut self, ty: LaneType, idx: usize, value: u64) {
+        let width = ty.size() as usize / 8;
+        self.bytes_mut()[idx * width..(idx + 1) * width]
+            .copy_from_slice(&value.to_le_bytes()[..width]);
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum RValue<T> {
     LValue(LValue),
     Immediate(T),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LValue {
     Register(u8),
     Scratch(u8),
@@ -208,71 +398,364 @@ impl From<IntImmed> for RValue<IntImmed> {
     }
 }
 
+impl From<FloatImmed> for RValue<FloatImmed> {
+    fn from(value: FloatImmed) -> Self {
+        Self::Immediate(value)
+    }
+}
+
+impl From<VectorImmed> for RValue<VectorImmed> {
+    fn from(value: VectorImmed) -> Self {
+        Self::Immediate(value)
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy)]
 pub enum Comparator {
     EQ,
     NEQ,
     SLT,
     SGT,
+    SLE,
+    SGE,
     ULT,
     UGT,
+    ULE,
+    UGE,
+}
+
+impl Comparator {
+    /// Whether the comparison treats its operands as signed, and so
+    /// sign extends the narrower one
+    pub(crate) fn is_signed(&self) -> bool {
+        matches!(self, Self::SLT | Self::SGT | Self::SLE | Self::SGE)
+    }
+}
+
+/// How the operands of a widening or high half multiply are interpreted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultSignedness {
+    Unsigned,
+    Signed,
+    /// The first operand is signed and the second unsigned
+    SignedUnsigned,
 }
 
-#[derive(Debug)]
-pub enum Condition {}
-
-// #[derive(Debug, Clone, Eq)]
-// pub struct StructType {
-//     fields: Vec<Type>,
-// }
-
-// impl PartialEq for StructType {
-//     fn eq(&self, other: &Self) -> bool {
-//         self.fields.iter().eq(other.fields.iter())
-//     }
-// }
-
-// #[derive(Debug)]
-// pub struct StructTypeImmed {
-//     ty: StructType,
-//     field_vals: Vec<TypeImmed>,
-// }
-
-// impl StructTypeImmed {
-//     fn validate_fields(&self) -> bool {
-//         if self.ty.fields.len() != self.field_vals.len() {
-//             return false;
-//         }
-
-//         self.field_vals
-//             .iter()
-//             .map(|f| f.get_type())
-//             .zip(self.ty.fields.iter())
-//             .fold(true, |a, b| a && b.0 == *b.1)
-//     }
-
-//     fn get_type(&self) -> &StructType {
-//         &self.ty
-//     }
-// }
-
-// #[derive(Debug, Clone, PartialEq, Eq)]
-// pub enum Type {
-//     Integer(IntType),
-//     Struct(StructType),
-// }
-
-// #[derive(Debug)]
-// pub enum TypeImmed {
-//     Integer(IntImmed),
-//     Struct(StructTypeImmed),
-// }
-
-// impl TypeImmed {
-//     fn get_type(&self) -> Type {
-//         match self {
-//             Self::Integer(i) => Type::Integer(i.get_type()),
-//             Self::Struct(s) => Type::Struct(s.get_type().clone()),
-//         }
-//     }
-// }
+impl MultSignedness {
+    /// Whether the first and second operands are signed
+    pub(crate) fn operands(self) -> (bool, bool) {
+        match self {
+            Self::Unsigned => (false, false),
+            Self::Signed => (true, true),
+            Self::SignedUnsigned => (true, false),
+        }
+    }
+}
+
+/// Ordered comparisons are false if either value is NaN, and NEQ is true.
+/// UNO is true only if either value is NaN.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy)]
+pub enum FloatComparator {
+    EQ,
+    NEQ,
+    LT,
+    LE,
+    GT,
+    GE,
+    UNO,
+}
+
+/// An operation whose flags are recorded by set_flags or by the operation
+/// itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagOp {
+    Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+}
+
+/// A read-modify-write performed atomically on guest memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtomicOp {
+    Add,
+    And,
+    Or,
+    Xor,
+    Exchange,
+}
+
+/// Byte order of values in guest memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+impl Endianness {
+    /// Byte order of the host
+    pub(crate) fn host() -> Self {
+        if cfg!(target_endian = "big") {
+            Self::Big
+        } else {
+            Self::Little
+        }
+    }
+
+    /// Reads an unsigned integer from `bytes`, which are at most 16 bytes long
+    pub(crate) fn read_uint(self, bytes: &[u8]) -> u128 {
+        let mut buf = [0u8; 16];
+        match self {
+            Self::Little => {
+                buf[..bytes.len()].copy_from_slice(bytes);
+                u128::from_le_bytes(buf)
+            }
+            Self::Big => {
+                buf[16 - bytes.len()..].copy_from_slice(bytes);
+                u128::from_be_bytes(buf)
+            }
+        }
+    }
+
+    /// Writes the low `bytes.len()` bytes of `value` to `bytes`
+    pub(crate) fn write_uint(self, value: u128, bytes: &mut [u8]) {
+        let len = bytes.len();
+        match self {
+            Self::Little => bytes.copy_from_slice(&value.to_le_bytes()[..len]),
+            Self::Big => bytes.copy_from_slice(&value.to_be_bytes()[16 - len..]),
+        }
+    }
+}
+
+/// Memory ordering of an atomic guest access or fence, with the meaning
+/// of the C++ memory model. Orderings that do not apply to an access are
+/// weakened to the nearest one that does, so a release load-linked is
+/// relaxed, and a compare-exchange that fails only orders as an acquire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryOrdering {
+    Relaxed,
+    Acquire,
+    Release,
+    AcqRel,
+    SeqCst,
+}
+
+/// A test of the flags recorded by the last flag-setting operation, computed
+/// from its operands when the condition is read. Carry is set on unsigned
+/// overflow of an add and on borrow out of a sub. Logic operations clear
+/// carry, overflow and aux carry. Parity is that of the low byte of the
+/// result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Zero,
+    NotZero,
+    Sign,
+    NotSign,
+    Carry,
+    NotCarry,
+    Overflow,
+    NotOverflow,
+    ParityEven,
+    ParityOdd,
+    AuxCarry,
+    NotAuxCarry,
+    /// Neither carry nor zero is set
+    UnsignedGreater,
+    UnsignedLessEqual,
+    /// Sign differs from overflow
+    SignedLess,
+    SignedGreaterEqual,
+    /// Zero is clear and sign equals overflow
+    SignedGreater,
+    SignedLessEqual,
+}
+
+/// An ordered list of fields. In guest memory, fields are laid out in
+/// order without padding, with Bools taking a byte and nested structs
+/// stored inline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructType {
+    fields: Vec<Type>,
+}
+
+impl StructType {
+    pub fn new(fields: Vec<Type>) -> Self {
+        Self { fields }
+    }
+
+    pub fn fields(&self) -> &[Type] {
+        &self.fields
+    }
+
+    /// Size of the struct in guest memory in bytes
+    pub fn size(&self) -> usize {
+        self.fields.iter().map(|f| f.size()).sum()
+    }
+
+    /// A struct of this type with every field zeroed
+    pub fn zero(&self) -> StructTypeImmed {
+        StructTypeImmed {
+            ty: self.clone(),
+            field_vals: self.fields.iter().map(|f| f.zero()).collect(),
+        }
+    }
+
+    /// Decodes a struct from its guest memory layout with integer fields
+    /// in the given byte order. `bytes` must be exactly `size()` bytes long.
+    pub(crate) fn decode(&self, bytes: &[u8], endianness: Endianness) -> StructTypeImmed {
+        let mut offset = 0;
+        let field_vals = self
+            .fields
+            .iter()
+            .map(|f| {
+                let size = f.size();
+                let bytes = &bytes[offset..offset + size];
+                offset += size;
+                match f {
+                    Type::Integer(ty) => {
+                        TypeImmed::Integer(ty.from_u128(endianness.read_uint(bytes)))
+                    }
+                    Type::Struct(ty) => TypeImmed::Struct(ty.decode(bytes, endianness)),
+                }
+            })
+            .collect();
+
+        StructTypeImmed {
+            ty: self.clone(),
+            field_vals,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructTypeImmed {
+    ty: StructType,
+    field_vals: Vec<TypeImmed>,
+}
+
+impl StructTypeImmed {
+    /// Fails if the values do not match the fields of the type
+    pub fn new(ty: StructType, field_vals: Vec<TypeImmed>) -> Result<Self, String> {
+        let value = Self { ty, field_vals };
+        if !value.validate_fields() {
+            return Err(String::from("Struct field values do not match its type"));
+        }
+        Ok(value)
+    }
+
+    fn validate_fields(&self) -> bool {
+        if self.ty.fields.len() != self.field_vals.len() {
+            return false;
+        }
+
+        self.field_vals
+            .iter()
+            .map(|f| f.get_type())
+            .zip(self.ty.fields.iter())
+            .all(|(value, field)| value == *field)
+    }
+
+    pub fn get_type(&self) -> &StructType {
+        &self.ty
+    }
+
+    pub fn field(&self, idx: u8) -> Option<&TypeImmed> {
+        self.field_vals.get(idx as usize)
+    }
+
+    /// Replaces a field with a value of the same type, returning
+    /// false if there is no such field or the types differ
+    pub fn set_field(&mut self, idx: u8, value: TypeImmed) -> bool {
+        match self.field_vals.get_mut(idx as usize) {
+            Some(field) if field.get_type() == value.get_type() => {
+                *field = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Encodes the struct in its guest memory layout with integer fields
+    /// in the given byte order
+    pub(crate) fn encode(&self, endianness: Endianness) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.ty.size());
+        for field in self.field_vals.iter() {
+            match field {
+                TypeImmed::Integer(i) => {
+                    let start = bytes.len();
+                    bytes.resize(start + Type::Integer(i.get_type()).size(), 0);
+                    endianness.write_uint(i.to_u128(), &mut bytes[start..]);
+                }
+                TypeImmed::Struct(s) => bytes.extend(s.encode(endianness)),
+            }
+        }
+        bytes
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Integer(IntType),
+    Struct(StructType),
+}
+
+impl Type {
+    /// Size of a value of the type in guest memory in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Integer(IntType::Bool) => 1,
+            Self::Integer(ty) => ty.size() as usize / 8,
+            Self::Struct(s) => s.size(),
+        }
+    }
+
+    pub fn zero(&self) -> TypeImmed {
+        match self {
+            Self::Integer(ty) => TypeImmed::Integer(ty.from_u8(0)),
+            Self::Struct(s) => TypeImmed::Struct(s.zero()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeImmed {
+    Integer(IntImmed),
+    Struct(StructTypeImmed),
+}
+
+impl TypeImmed {
+    pub fn get_type(&self) -> Type {
+        match self {
+            Self::Integer(i) => Type::Integer(i.get_type()),
+            Self::Struct(s) => Type::Struct(s.get_type().clone()),
+        }
+    }
+}
+
+impl From<IntImmed> for TypeImmed {
+    fn from(value: IntImmed) -> Self {
+        Self::Integer(value)
+    }
+}
+
+impl From<StructTypeImmed> for TypeImmed {
+    fn from(value: StructTypeImmed) -> Self {
+        Self::Struct(value)
+    }
+}
+
+impl From<StructTypeImmed> for RValue<StructTypeImmed> {
+    fn from(value: StructTypeImmed) -> Self {
+        Self::Immediate(value)
+    }
+}
+
+impl<T: Into<TypeImmed>> From<T> for RValue<TypeImmed> {
+    fn from(value: T) -> Self {
+        Self::Immediate(value.into())
+    }
+}
@@ -1,18 +1,60 @@
 pub mod backend;
 pub mod block;
 pub mod ctx;
+pub mod host;
 pub mod interpret;
+pub mod mem;
 pub mod unit;
 
 mod ir;
 
-pub use ir::types::{IntImmed, LValue};
+pub use ir::types::{
+    AtomicOp, BlockLabel, Comparator, Condition, Endianness, FlagOp, FloatComparator, FloatImmed,
+    FloatType, IntImmed, IntType, LValue, LaneType, MemoryOrdering, MultSignedness, RValue,
+    StructType, StructTypeImmed, Type, TypeImmed, VectorImmed, VectorType,
+};
 
 #[cfg(test)]
 mod tests {
+    use crate::backend::{ExitReason, ExitStatus, Fuel};
     use crate::ctx::ExecutionContext;
 
+    /// A unit of the given blocks, entered at the first one
+    fn translation_unit<'a>(
+        blocks: impl IntoIterator<Item = (&'a str, super::block::BasicBlock)>,
+    ) -> super::unit::TranslationUnit {
+        let mut unit = super::unit::TranslationUnit::default();
+        let mut entry = None;
+        for (label, block) in blocks {
+            entry.get_or_insert(label);
+            unit.add_basic_block(String::from(label), block).unwrap();
+        }
+        unit.set_entry(String::from(entry.unwrap())).unwrap();
+        unit
+    }
+
+    /// Compiles `unit` with the interpreter and runs it to completion
+    fn run<State: super::ir::reg::RegisterMap>(
+        unit: super::unit::TranslationUnit,
+        state: &mut State,
+    ) -> ExitReason {
+        let ctx: ExecutionContext<super::interpret::InterpreterBackend> =
+            ExecutionContext::default();
+        run_in(&ctx, unit, state)
+    }
+
+    /// Like [`run`], for a context with its own backend or host functions
+    fn run_in<Backend: super::backend::Compiler, State: super::ir::reg::RegisterMap>(
+        ctx: &ExecutionContext<Backend>,
+        unit: super::unit::TranslationUnit,
+        state: &mut State,
+    ) -> ExitReason {
+        let mut tb = ctx.compile(Box::new(unit)).unwrap();
+        unsafe { tb.execute(state) }
+    }
+
     #[test]
+    #[allow(clippy::unnecessary_cast)]
     fn it_works() {
         use super::ir::types::{IntImmed, LValue};
 
@@ -42,4 +84,2071 @@ mod tests {
 
         assert_eq!(state[0], -7 as i16 as u64);
     }
+
+    #[test]
+    fn upcast_keeps_operand_order() {
+        use super::ir::types::{IntImmed, LValue};
+
+        assert_eq!(
+            IntImmed::upcast(&IntImmed::I8(0xff), &IntImmed::I32(5), true),
+            (IntImmed::I32(u32::MAX), IntImmed::I32(5))
+        );
+        assert_eq!(
+            IntImmed::upcast(&IntImmed::I32(5), &IntImmed::I8(0xff), false),
+            (IntImmed::I32(5), IntImmed::I32(0xff))
+        );
+
+        // The narrower operand is the minuend, so the result is negative
+        let mut block = super::block::BasicBlock::builder();
+        block.sub(LValue::Register(0), IntImmed::I8(1), IntImmed::I32(5), true);
+        let unit = translation_unit([("main", block.finish_exit(0))]);
+
+        let mut state = [0u64; 1];
+        run(unit, &mut state);
+
+        assert_eq!(state[0], -4i64 as u64);
+    }
+
+    #[test]
+    fn integer_ops() {
+        use super::ir::types::{Comparator, IntImmed, IntType, LValue};
+
+        let mut block = super::block::BasicBlock::builder();
+        block.mult(
+            LValue::Register(0),
+            IntImmed::I32(7),
+            IntImmed::I32(6),
+            false,
+        );
+        block.div(
+            LValue::Register(1),
+            IntImmed::I32(-7i32 as u32),
+            IntImmed::I32(2),
+            true,
+        );
+        block.rem(
+            LValue::Register(2),
+            IntImmed::I32(-7i32 as u32),
+            IntImmed::I32(2),
+            true,
+        );
+        block.shift_left(LValue::Register(3), IntImmed::I8(0x81), IntImmed::I8(1));
+        block.shift_right(
+            LValue::Register(4),
+            IntImmed::I8(0x80),
+            IntImmed::I8(9),
+            true,
+        );
+        block.sign_extend(LValue::Register(5), IntImmed::I8(0xfe), IntType::I16);
+        block.int_cmp(
+            LValue::Register(6),
+            Comparator::SLT,
+            IntImmed::I8(0xff),
+            IntImmed::I32(1),
+        );
+        block.xor(
+            LValue::Register(7),
+            LValue::Register(0),
+            IntImmed::I32(0xff),
+        );
+        block.select(
+            LValue::Register(6),
+            LValue::Register(8),
+            IntImmed::I64(1),
+            IntImmed::I64(2),
+        );
+        let unit = translation_unit([("main", block.finish_exit(0))]);
+
+        let mut state = [0u64; 10];
+        run(unit, &mut state);
+
+        assert_eq!(state[0], 42);
+        assert_eq!(state[1], -3i64 as u64);
+        assert_eq!(state[2], -1i64 as u64);
+        assert_eq!(state[3], 0x02);
+        assert_eq!(state[4], -1i64 as u64);
+        assert_eq!(state[5], -2i64 as u64);
+        assert_eq!(state[6], 1);
+        assert_eq!(state[7], 42 ^ 0xff);
+        assert_eq!(state[8], 1);
+    }
+
+    #[test]
+    fn overflow_arithmetic() {
+        use super::ir::types::{IntImmed, LValue};
+
+        let mut block = super::block::BasicBlock::builder();
+        // A 128 bit add from 64 bit halves, (2^64 + (2^64 - 1)) + 1
+        block.add_overflow(
+            LValue::Register(0),
+            LValue::Scratch(0),
+            IntImmed::I64(u64::MAX),
+            IntImmed::I64(1),
+            false,
+        );
+        block.add_carry(
+            LValue::Register(1),
+            LValue::Register(2),
+            IntImmed::I64(1),
+            IntImmed::I64(0),
+            LValue::Scratch(0),
+            false,
+        );
+        // 0 - 0 with a borrow in
+        block.sub_borrow(
+            LValue::Register(3),
+            LValue::Register(4),
+            IntImmed::I8(0),
+            IntImmed::I8(0),
+            IntImmed::Bool(true),
+            false,
+        );
+        block.sub_overflow(
+            LValue::Scratch(1),
+            LValue::Register(5),
+            IntImmed::I32(i32::MIN as u32),
+            IntImmed::I32(1),
+            true,
+        );
+        block.add_carry(
+            LValue::Scratch(1),
+            LValue::Register(6),
+            IntImmed::I8(0x7f),
+            IntImmed::I8(0),
+            IntImmed::I8(1),
+            true,
+        );
+        block.mult_overflow(
+            LValue::Register(7),
+            LValue::Register(8),
+            IntImmed::I16(200),
+            IntImmed::I16(300),
+            false,
+        );
+        block.mult_overflow(
+            LValue::Scratch(1),
+            LValue::Register(9),
+            IntImmed::I16(200),
+            IntImmed::I16(300),
+            true,
+        );
+        block.mult_overflow(
+            LValue::Scratch(1),
+            LValue::Register(10),
+            IntImmed::I8(0x80),
+            IntImmed::I8(0xff),
+            true,
+        );
+        block.add_overflow(
+            LValue::Scratch(1),
+            LValue::Register(11),
+            IntImmed::I128(u128::MAX),
+            IntImmed::I128(1),
+            false,
+        );
+        block.mult_overflow(
+            LValue::Scratch(1),
+            LValue::Register(12),
+            IntImmed::I128(1 << 64),
+            IntImmed::I128(1 << 63),
+            true,
+        );
+        block.sub_borrow(
+            LValue::Scratch(1),
+            LValue::Register(13),
+            IntImmed::I128(5),
+            IntImmed::I128(4),
+            IntImmed::I128(1),
+            false,
+        );
+        let unit = translation_unit([("main", block.finish_exit(0))]);
+
+        let mut state = [0u64; 14];
+        run(unit, &mut state);
+
+        assert_eq!(state[..3], [0, 2, 0]);
+        assert_eq!(state[3..5], [0xff, 1]);
+        assert_eq!(state[5..7], [1, 1]);
+        assert_eq!(state[7..11], [60000, 0, 1, 1]);
+        assert_eq!(state[11..], [1, 1, 0]);
+    }
+
+    #[test]
+    fn widening_mult() {
+        use super::ir::types::{IntImmed, LValue, MultSignedness};
+
+        let mut block = super::block::BasicBlock::builder();
+        block.mult_wide(
+            LValue::Register(0),
+            LValue::Register(1),
+            IntImmed::I64(u64::MAX),
+            IntImmed::I64(u64::MAX),
+            MultSignedness::Unsigned,
+        );
+        block.mult_wide(
+            LValue::Register(2),
+            LValue::Register(3),
+            IntImmed::I64(u64::MAX),
+            IntImmed::I64(u64::MAX),
+            MultSignedness::Signed,
+        );
+        // -1 * (2^64 - 1)
+        block.mult_wide(
+            LValue::Register(4),
+            LValue::Register(5),
+            IntImmed::I64(u64::MAX),
+            IntImmed::I64(u64::MAX),
+            MultSignedness::SignedUnsigned,
+        );
+        block.mult_high(
+            LValue::Register(6),
+            IntImmed::I8(0x80),
+            IntImmed::I8(0x80),
+            MultSignedness::Signed,
+        );
+        // The narrower operand is extended to the wider one
+        block.mult_high(
+            LValue::Register(7),
+            IntImmed::I32(0xffff_ffff),
+            IntImmed::I8(0xff),
+            MultSignedness::Unsigned,
+        );
+        block.mult_high(
+            LValue::Scratch(0),
+            IntImmed::I128(u128::MAX),
+            IntImmed::I128(u128::MAX),
+            MultSignedness::Unsigned,
+        );
+        block.shift_right(
+            LValue::Register(8),
+            LValue::Scratch(0),
+            IntImmed::I128(0),
+            false,
+        );
+        block.shift_right(
+            LValue::Register(9),
+            LValue::Scratch(0),
+            IntImmed::I128(64),
+            false,
+        );
+        // -2^127 * 2 = -2^128
+        block.mult_wide(
+            LValue::Scratch(1),
+            LValue::Scratch(2),
+            IntImmed::I128(1 << 127),
+            IntImmed::I128(2),
+            MultSignedness::Signed,
+        );
+        block.shift_right(
+            LValue::Register(10),
+            LValue::Scratch(1),
+            IntImmed::I128(0),
+            false,
+        );
+        block.shift_right(
+            LValue::Register(11),
+            LValue::Scratch(2),
+            IntImmed::I128(64),
+            false,
+        );
+        // The high half wins when both halves share a destination
+        block.mult_wide(
+            LValue::Register(12),
+            LValue::Register(12),
+            IntImmed::I16(0x1234),
+            IntImmed::I16(0x100),
+            MultSignedness::Unsigned,
+        );
+        let unit = translation_unit([("main", block.finish_exit(0))]);
+
+        let mut state = [0u64; 13];
+        run(unit, &mut state);
+
+        assert_eq!(state[..2], [1, 0xffff_ffff_ffff_fffe]);
+        assert_eq!(state[2..4], [1, 0]);
+        assert_eq!(state[4..6], [1, u64::MAX]);
+        assert_eq!(state[6..8], [0x40, 0xfe]);
+        assert_eq!(state[8..10], [u64::MAX - 1, u64::MAX]);
+        assert_eq!(state[10..12], [0, u64::MAX]);
+        assert_eq!(state[12], 0x12);
+    }
+
+    #[test]
+    fn bit_ops() {
+        use super::ir::types::{IntImmed, LValue};
+
+        let mut block = super::block::BasicBlock::builder();
+        block.rotate_left(LValue::Register(0), IntImmed::I8(0x81), IntImmed::I8(9));
+        block.rotate_right(LValue::Register(1), IntImmed::I16(0x0001), IntImmed::I8(4));
+        // The top bit moves into the carry, and the clear carry into bit 0
+        block.rotate_left_carry(
+            LValue::Register(2),
+            LValue::Register(3),
+            IntImmed::I8(0x81),
+            IntImmed::I8(1),
+            IntImmed::Bool(false),
+        );
+        block.rotate_right_carry(
+            LValue::Register(4),
+            LValue::Register(5),
+            IntImmed::I8(0x01),
+            IntImmed::I8(10),
+            IntImmed::Bool(true),
+        );
+        block.count_leading_zeros(LValue::Register(6), IntImmed::I32(0x0010_0000));
+        block.count_trailing_zeros(LValue::Register(7), IntImmed::I16(0));
+        block.pop_count(LValue::Register(8), IntImmed::I64(0xf0f0));
+        block.byte_swap(LValue::Register(9), IntImmed::I32(0x1234_5678));
+        block.bit_reverse(LValue::Register(10), IntImmed::I8(0x01));
+        block.rotate_left(
+            LValue::Scratch(0),
+            IntImmed::I128(1 << 127),
+            IntImmed::I8(1),
+        );
+        block.count_trailing_zeros(LValue::Register(11), LValue::Scratch(0));
+        block.rotate_left_carry(
+            LValue::Register(12),
+            LValue::Register(13),
+            IntImmed::I8(0x96),
+            IntImmed::I8(3),
+            IntImmed::Bool(true),
+        );
+        // A full width rotate of a 128 bit value moves its low bit into the carry
+        block.rotate_left_carry(
+            LValue::Scratch(1),
+            LValue::Register(14),
+            IntImmed::I128(1 << 127 | 1),
+            IntImmed::I8(128),
+            IntImmed::Bool(false),
+        );
+        block.shift_right(
+            LValue::Register(15),
+            LValue::Scratch(1),
+            IntImmed::I8(64),
+            false,
+        );
+        let unit = translation_unit([("main", block.finish_exit(0))]);
+
+        let mut state = [0u64; 16];
+        run(unit, &mut state);
+
+        assert_eq!(state[..2], [0x03, 0x1000]);
+        assert_eq!(state[2..4], [0x02, 1]);
+        // Rotating 9 bits right by 10 is the same as rotating by 1
+        assert_eq!(state[4..6], [0x80, 1]);
+        assert_eq!(state[6..8], [11, 16]);
+        assert_eq!(state[8..11], [8, 0x7856_3412, 0x80]);
+        assert_eq!(state[11], 0);
+        assert_eq!(state[12..14], [0xb6, 0]);
+        assert_eq!(state[14..], [1, 1 << 62]);
+    }
+
+    #[test]
+    fn guest_memory() {
+        use super::interpret::InterpreterBackend;
+        use super::ir::types::{IntImmed, LValue};
+        use super::mem::GuestMemory;
+
+        let mut memory = vec![0u8; 16];
+        let guest = unsafe { GuestMemory::new(memory.as_mut_ptr(), memory.len()) };
+
+        let mut block = super::block::BasicBlock::builder();
+        block.guest_mem_write(IntImmed::I64(4), IntImmed::I32(0x12345678), 32);
+        block.guest_mem_read(LValue::Register(0), IntImmed::I64(5), 16);
+        let unit = translation_unit([("main", block.finish_exit(0))]);
+
+        let ctx = ExecutionContext::new(InterpreterBackend::new(guest));
+        let mut state = [0u64; 1];
+        run_in(&ctx, unit, &mut state);
+
+        assert_eq!(memory[4..8], [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(state[0], 0x3456);
+    }
+
+    #[test]
+    fn host_memory() {
+        use super::backend::Trap;
+        use super::ir::types::{IntImmed, IntType, LValue};
+
+        let mut memory = [0u64; 2];
+        let addr = memory.as_mut_ptr() as u64;
+
+        let mut block = super::block::BasicBlock::builder();
+        unsafe {
+            // Only the low 32 bits of the value are written
+            block.host_mem_write(
+                IntImmed::I64(addr),
+                IntImmed::I64(u64::MAX),
+                IntType::I32,
+                true,
+                false,
+            );
+            block.host_mem_read(
+                LValue::Register(0),
+                IntImmed::I64(addr),
+                IntType::I64,
+                true,
+                true,
+            );
+            block.host_mem_write(
+                IntImmed::I64(addr + 9),
+                IntImmed::I16(0xabcd),
+                IntType::I16,
+                false,
+                true,
+            );
+            block.host_mem_read(
+                LValue::Register(1),
+                IntImmed::I64(addr + 9),
+                IntType::I16,
+                false,
+                false,
+            );
+            block.host_mem_read(
+                LValue::Register(2),
+                IntImmed::I64(addr + 9),
+                IntType::I32,
+                true,
+                false,
+            );
+        }
+        let unit = translation_unit([("main", block.finish_exit(0))]);
+
+        let mut state = [0u64; 3];
+        let reason = run(unit, &mut state);
+
+        assert_eq!(
+            reason.status,
+            ExitStatus::Trap(Trap::MisalignedHostAccess(addr + 9))
+        );
+        assert_eq!(state, [0xffff_ffff, 0xabcd, 0]);
+        assert_eq!(memory[0], 0xffff_ffff);
+        assert_eq!(memory[1].to_ne_bytes()[1..3], 0xabcdu16.to_ne_bytes());
+    }
+
+    #[test]
+    fn endianness() {
+        use super::interpret::InterpreterBackend;
+        use super::ir::types::{
+            AtomicOp, Endianness, IntImmed, IntType, LValue, MemoryOrdering, StructType, Type,
+        };
+        use super::mem::GuestMemory;
+
+        // Backed by u64s so that atomic accesses are aligned on the host
+        let mut memory = [0u64; 2];
+        let guest = unsafe { GuestMemory::new(memory.as_mut_ptr() as *mut u8, 16) };
+
+        let header = StructType::new(vec![
+            Type::Integer(IntType::I16),
+            Type::Integer(IntType::I8),
+        ]);
+
+        let mut block = super::block::BasicBlock::builder();
+        block.guest_mem_write(IntImmed::I64(0), IntImmed::I32(0x12345678), 32);
+        block.guest_mem_read_endian(
+            LValue::Register(0),
+            IntImmed::I64(0),
+            16,
+            Endianness::Little,
+        );
+        block.guest_read_struct(LValue::Scratch(0), IntImmed::I64(0), header);
+        block.extract_field(LValue::Register(1), LValue::Scratch(0), 0);
+        block.guest_atomic_rmw(
+            LValue::Register(2),
+            AtomicOp::Add,
+            IntImmed::I64(0),
+            IntImmed::I32(0x100),
+            32,
+            MemoryOrdering::SeqCst,
+        );
+        block.guest_mem_write_endian(
+            IntImmed::I64(8),
+            IntImmed::I16(0x1234),
+            16,
+            Endianness::Little,
+        );
+        let mut unit = translation_unit([("main", block.finish_exit(0))]);
+        unit.set_endianness(Endianness::Big);
+
+        let ctx = ExecutionContext::new(InterpreterBackend::new(guest));
+        let mut state = [0u64; 3];
+        let reason = run_in(&ctx, unit, &mut state);
+
+        assert_eq!(reason.status, ExitStatus::Exit(0));
+        assert_eq!(state, [0x3412, 0x1234, 0x12345678]);
+        assert_eq!(memory[0].to_ne_bytes()[..4], [0x12, 0x34, 0x57, 0x78]);
+        assert_eq!(memory[1].to_ne_bytes()[..2], [0x34, 0x12]);
+    }
+
+    #[test]
+    fn struct_values() {
+        use super::backend::Trap;
+        use super::interpret::InterpreterBackend;
+        use super::ir::types::{IntImmed, IntType, LValue, StructType, Type};
+        use super::mem::GuestMemory;
+
+        // A segment descriptor with its access rights in a nested struct
+        let access = StructType::new(vec![
+            Type::Integer(IntType::Bool),
+            Type::Integer(IntType::I8),
+        ]);
+        let segment = StructType::new(vec![
+            Type::Integer(IntType::I32),
+            Type::Integer(IntType::I16),
+            Type::Struct(access.clone()),
+        ]);
+        assert_eq!(segment.size(), 8);
+
+        let mut memory = vec![0u8; 16];
+        memory[..8].copy_from_slice(&[0x00, 0x10, 0x00, 0x00, 0xff, 0x0f, 0x01, 0x93]);
+        let guest = unsafe { GuestMemory::new(memory.as_mut_ptr(), memory.len()) };
+
+        let mut block = super::block::BasicBlock::builder();
+        block.guest_read_struct(LValue::Scratch(0), IntImmed::I64(0), segment.clone());
+        block.extract_field(LValue::Register(0), LValue::Scratch(0), 0);
+        block.extract_field(LValue::Scratch(1), LValue::Scratch(0), 2);
+        block.extract_field(LValue::Register(1), LValue::Scratch(1), 1);
+        // The limit is truncated to the width of its field
+        block.insert_field(
+            LValue::Scratch(0),
+            LValue::Scratch(0),
+            IntImmed::I64(0x1_abcd),
+            1,
+        );
+        block.insert_field(
+            LValue::Scratch(1),
+            LValue::Scratch(1),
+            IntImmed::Bool(false),
+            0,
+        );
+        block.insert_field(
+            LValue::Scratch(0),
+            LValue::Scratch(0),
+            LValue::Scratch(1),
+            2,
+        );
+        block.guest_write_struct(IntImmed::I64(8), LValue::Scratch(0));
+        let unit = translation_unit([("main", block.finish_exit(0))]);
+
+        let ctx = ExecutionContext::new(InterpreterBackend::new(guest));
+        let mut state = [0u64; 2];
+        let reason = run_in(&ctx, unit, &mut state);
+
+        assert_eq!(reason.status, ExitStatus::Exit(0));
+        assert_eq!(state, [0x1000, 0x93]);
+        assert_eq!(
+            memory[8..],
+            [0x00, 0x10, 0x00, 0x00, 0xcd, 0xab, 0x00, 0x93]
+        );
+
+        // Blocks that fail to type check are rejected when they are added
+        let try_run = |block: super::block::BasicBlockBuilder| {
+            let mut unit = super::unit::TranslationUnit::default();
+            unit.add_basic_block(String::from("main"), block.finish_exit(0))?;
+            unit.set_entry(String::from("main")).unwrap();
+            Ok::<_, String>(run_in(&ctx, unit, &mut [0u64; 2]).status)
+        };
+
+        // Structs cannot be held in registers
+        let mut block = super::block::BasicBlock::builder();
+        block.extract_field(LValue::Register(0), segment.zero(), 2);
+        assert!(try_run(block).is_err());
+
+        let mut block = super::block::BasicBlock::builder();
+        block.guest_read_struct(LValue::Register(0), IntImmed::I64(0), segment.clone());
+        assert!(try_run(block).is_err());
+
+        let mut block = super::block::BasicBlock::builder();
+        block.guest_read_struct(LValue::Scratch(0), IntImmed::I64(0), segment.clone());
+        block.extract_field(LValue::Scratch(1), LValue::Scratch(0), 3);
+        assert_eq!(try_run(block), Err(String::from("Struct has no field 3")));
+
+        // A struct field only accepts a struct of its own type
+        let mut block = super::block::BasicBlock::builder();
+        block.insert_field(LValue::Scratch(0), segment.zero(), segment.zero(), 2);
+        assert!(try_run(block).is_err());
+
+        let mut block = super::block::BasicBlock::builder();
+        block.guest_read_struct(LValue::Scratch(0), IntImmed::I64(0), segment.clone());
+        block.insert_field(LValue::Scratch(1), LValue::Scratch(0), IntImmed::I8(1), 2);
+        assert!(try_run(block).is_err());
+
+        // Once a scratch value is overwritten, its old type no longer applies
+        let mut block = super::block::BasicBlock::builder();
+        block.guest_read_struct(LValue::Scratch(1), IntImmed::I64(0), access.clone());
+        block.add(LValue::Scratch(1), IntImmed::I8(1), IntImmed::I8(1), false);
+        block.insert_field(LValue::Scratch(0), segment.zero(), LValue::Scratch(1), 1);
+        assert_eq!(try_run(block), Ok(ExitStatus::Exit(0)));
+
+        // Types that are only known at run time are still checked then
+        let mut entry = super::block::BasicBlock::builder();
+        entry.guest_read_struct(LValue::Scratch(0), IntImmed::I64(0), segment.clone());
+        let entry = entry.finish_branch(
+            IntImmed::Bool(true),
+            String::from("insert"),
+            String::from("insert"),
+        );
+        let mut insert = super::block::BasicBlock::builder();
+        insert.insert_field(LValue::Scratch(1), segment.zero(), LValue::Scratch(0), 2);
+        let unit = translation_unit([("entry", entry), ("insert", insert.finish_exit(0))]);
+        let reason = run_in(&ctx, unit, &mut [0u64; 2]);
+        assert_eq!(reason.status, ExitStatus::Trap(Trap::TypeMismatch));
+
+        let mut block = super::block::BasicBlock::builder();
+        block.guest_read_struct(LValue::Scratch(0), IntImmed::I64(12), segment);
+        assert_eq!(
+            try_run(block),
+            Ok(ExitStatus::Trap(Trap::GuestMemoryFault(12)))
+        );
+    }
+
+    #[test]
+    fn i128_ops() {
+        use super::ir::types::{Comparator, IntImmed, IntType, LValue};
+
+        let mut block = super::block::BasicBlock::builder();
+        block.add(
+            LValue::Register(0),
+            LValue::Register(0),
+            IntImmed::I128(1),
+            false,
+        );
+        block.mult(
+            LValue::Register(1),
+            LValue::Register(0),
+            IntImmed::I64(3),
+            false,
+        );
+        block.sign_extend(LValue::Register(2), IntImmed::I64(u64::MAX), IntType::I128);
+        block.shift_right(
+            LValue::Register(2),
+            LValue::Register(2),
+            IntImmed::I8(100),
+            false,
+        );
+        block.int_cmp(
+            LValue::Scratch(0),
+            Comparator::SLT,
+            IntImmed::I128(1 << 127),
+            IntImmed::I8(0),
+        );
+        block.zero_extend(LValue::Register(3), LValue::Scratch(0), IntType::I128);
+        block.div(
+            LValue::Register(3),
+            IntImmed::I128(u128::MAX),
+            LValue::Register(3),
+            true,
+        );
+        let unit = translation_unit([("main", block.finish_exit(0))]);
+
+        let mut state = [u64::MAX as u128, 0, 0, 0];
+        run(unit, &mut state);
+
+        assert_eq!(state[0], 1 << 64);
+        assert_eq!(state[1], 3 << 64);
+        assert_eq!(state[2], u128::MAX >> 100);
+        assert_eq!(state[3], u128::MAX);
+    }
+
+    #[test]
+    fn float_ops() {
+        use super::ir::types::{FloatComparator, FloatImmed, FloatType, IntImmed, IntType, LValue};
+
+        let mut block = super::block::BasicBlock::builder();
+        block.float_mult(
+            LValue::Register(0),
+            LValue::Register(0),
+            FloatImmed::F64(4.0),
+        );
+        block.float_sqrt(LValue::Register(0), LValue::Register(0));
+        block.float_div(
+            LValue::Scratch(0),
+            FloatImmed::F32(1.0),
+            FloatImmed::F32(3.0),
+        );
+        block.float_add(
+            LValue::Register(1),
+            LValue::Scratch(0),
+            FloatImmed::F32(0.0),
+        );
+        block.float_cmp(
+            LValue::Scratch(1),
+            FloatComparator::LT,
+            FloatImmed::F64(f64::NAN),
+            LValue::Register(0),
+        );
+        block.int_to_float(
+            LValue::Register(2),
+            IntImmed::I8(0xfe),
+            FloatType::F64,
+            true,
+        );
+        block.float_to_int(
+            LValue::Scratch(2),
+            FloatImmed::F64(-7.9),
+            IntType::I32,
+            true,
+        );
+        block.float_to_bits(LValue::Scratch(3), FloatImmed::F32(1.5));
+        block.bits_to_float(LValue::Register(3), LValue::Scratch(3));
+        block.float_add(LValue::Register(3), LValue::Register(3), LValue::Scratch(1));
+        let unit = translation_unit([("main", block.finish_exit(0))]);
+
+        let mut state = [2.25f64, 0.0, 0.0, 0.0];
+        let reason = run(unit, &mut state);
+
+        assert_eq!(state[0], 3.0);
+        assert_eq!(state[1], (1.0f32 / 3.0) as f64);
+        assert_eq!(state[2], -2.0);
+
+        // The comparison result is a Bool, which is not a float
+        assert_eq!(
+            reason.status,
+            ExitStatus::Trap(super::backend::Trap::InvalidFloatWidth(1))
+        );
+        assert_eq!(state[3], 1.5);
+    }
+
+    #[test]
+    fn vector_ops() {
+        use super::backend::Trap;
+        use super::ir::types::{IntImmed, LValue, LaneType, VectorImmed, VectorType};
+
+        let v128 = |lanes: [u32; 4]| {
+            let mut bytes = [0u8; 16];
+            for (i, lane) in lanes.iter().enumerate() {
+                bytes[i * 4..i * 4 + 4].copy_from_slice(&lane.to_le_bytes());
+            }
+            bytes
+        };
+
+        let mut block = super::block::BasicBlock::builder();
+        block.vector_splat(
+            LValue::Register(0),
+            IntImmed::I32(7),
+            LaneType::I32,
+            VectorType::V128,
+        );
+        block.vector_add(
+            LValue::Register(1),
+            LValue::Register(0),
+            VectorImmed::V128(v128([1, 2, 3, 4])),
+            LaneType::I32,
+        );
+        block.vector_shuffle(
+            LValue::Register(2),
+            LValue::Register(1),
+            LValue::Register(0),
+            &[3, 4, 0, 9],
+            LaneType::I32,
+        );
+        block.vector_extract_lane(LValue::Scratch(0), LValue::Register(2), 0, LaneType::I32);
+        block.vector_insert_lane(
+            LValue::Register(2),
+            LValue::Register(2),
+            LValue::Scratch(0),
+            3,
+            LaneType::I32,
+        );
+        block.vector_min(
+            LValue::Register(0),
+            LValue::Register(0),
+            VectorImmed::V128(v128([u32::MAX, 8, 3, 0x8000_0000])),
+            LaneType::I32,
+            true,
+        );
+        block.vector_shift_right(
+            LValue::Register(0),
+            LValue::Register(0),
+            IntImmed::I8(1),
+            LaneType::I32,
+            true,
+        );
+        block.vector_extract_lane(LValue::Scratch(1), LValue::Register(0), 4, LaneType::I32);
+        let unit = translation_unit([("main", block.finish_exit(0))]);
+
+        let mut state = [[0u8; 16]; 3];
+        let reason = run(unit, &mut state);
+
+        assert_eq!(state[1], v128([8, 9, 10, 11]));
+        assert_eq!(state[2], v128([11, 7, 8, 11]));
+        assert_eq!(state[0], v128([u32::MAX, 3, 1, 0xc000_0000]));
+        assert_eq!(reason.status, ExitStatus::Trap(Trap::InvalidLane(4)));
+    }
+
+    #[test]
+    fn scratch_values() {
+        use super::ir::types::{Comparator, IntImmed, LValue};
+
+        let mut entry = super::block::BasicBlock::builder();
+        entry.add(LValue::Scratch(0), IntImmed::I8(5), IntImmed::I8(3), false);
+        entry.int_cmp(
+            LValue::Scratch(1),
+            Comparator::EQ,
+            LValue::Scratch(0),
+            IntImmed::I8(8),
+        );
+        let entry = entry.finish_branch(
+            LValue::Scratch(1),
+            String::from("taken"),
+            String::from("not_taken"),
+        );
+
+        // Scratch values keep the width of the value written to them
+        let mut taken = super::block::BasicBlock::builder();
+        taken.shift_left(LValue::Scratch(2), LValue::Scratch(0), IntImmed::I8(5));
+        taken.sub(
+            LValue::Register(0),
+            LValue::Scratch(0),
+            IntImmed::I8(1),
+            false,
+        );
+        taken.or(LValue::Register(1), LValue::Scratch(2), IntImmed::I8(1));
+        let taken = taken.finish_exit(0);
+
+        let not_taken = super::block::BasicBlock::builder().finish_exit(1);
+
+        let unit = translation_unit([("entry", entry), ("taken", taken), ("not_taken", not_taken)]);
+        let mut state = [0u64; 2];
+        run(unit, &mut state);
+
+        assert_eq!(state, [7, 1]);
+    }
+
+    #[test]
+    fn loop_and_missing_entry() {
+        use super::ir::types::{Comparator, IntImmed, LValue};
+
+        let mut body = super::block::BasicBlock::builder();
+        body.add(
+            LValue::Register(0),
+            LValue::Register(0),
+            IntImmed::I64(1),
+            false,
+        );
+        body.int_cmp(
+            LValue::Scratch(0),
+            Comparator::ULT,
+            LValue::Register(0),
+            IntImmed::I64(1000),
+        );
+        let body = body.finish_branch(
+            LValue::Scratch(0),
+            String::from("body"),
+            String::from("done"),
+        );
+
+        let mut unit = super::unit::TranslationUnit::default();
+        unit.add_basic_block(String::from("body"), body).unwrap();
+
+        let ctx: ExecutionContext<super::interpret::InterpreterBackend> =
+            ExecutionContext::default();
+
+        // The entrypoint is missing
+        assert!(ctx.compile::<[u64; 1]>(Box::new(unit.clone())).is_err());
+        unit.set_entry(String::from("body")).unwrap();
+
+        let done = super::block::BasicBlock::builder().finish_exit(0);
+        unit.add_basic_block(String::from("done"), done).unwrap();
+
+        let mut state = [0u64; 1];
+        let reason = run_in(&ctx, unit, &mut state);
+
+        assert_eq!(state[0], 1000);
+        assert_eq!(
+            reason,
+            ExitReason {
+                status: ExitStatus::Exit(0),
+                block: String::from("done"),
+                ops_retired: 3 * 1000 + 1,
+                blocks_retired: 1001,
+                guest_pc: None,
+                instructions_retired: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn traps() {
+        use super::backend::Trap;
+        use super::interpret::InterpreterBackend;
+        use super::ir::types::{IntImmed, LValue};
+        use super::mem::GuestMemory;
+
+        let mut memory = vec![0u8; 8];
+        let guest = unsafe { GuestMemory::new(memory.as_mut_ptr(), memory.len()) };
+        let ctx = ExecutionContext::new(InterpreterBackend::new(guest));
+
+        let run_main = |block: super::block::BasicBlock| {
+            let mut state = [7u64; 1];
+            let reason = run_in(&ctx, translation_unit([("main", block)]), &mut state);
+            (reason, state[0])
+        };
+
+        let mut block = super::block::BasicBlock::builder();
+        block.add(
+            LValue::Register(0),
+            IntImmed::I64(1),
+            IntImmed::I64(2),
+            false,
+        );
+        block.div(
+            LValue::Register(0),
+            IntImmed::I64(1),
+            IntImmed::I64(0),
+            false,
+        );
+        let (reason, reg) = run_main(block.finish_exit(0));
+        assert_eq!(reason.status, ExitStatus::Trap(Trap::DivideByZero));
+        assert_eq!((reason.block.as_str(), reason.ops_retired), ("main", 1));
+        assert_eq!(reg, 3);
+
+        let mut block = super::block::BasicBlock::builder();
+        block.rem(
+            LValue::Register(0),
+            IntImmed::I32(i32::MIN as u32),
+            IntImmed::I32(u32::MAX),
+            true,
+        );
+        let (reason, reg) = run_main(block.finish_exit(0));
+        assert_eq!(reason.status, ExitStatus::Trap(Trap::IntegerOverflow));
+        assert_eq!(reg, 7);
+
+        let mut block = super::block::BasicBlock::builder();
+        block.guest_mem_write(IntImmed::I64(6), IntImmed::I32(0), 32);
+        let (reason, _) = run_main(block.finish_exit(0));
+        assert_eq!(reason.status, ExitStatus::Trap(Trap::GuestMemoryFault(6)));
+
+        let mut block = super::block::BasicBlock::builder();
+        block.not(LValue::Register(1), IntImmed::I8(0));
+        let (reason, _) = run_main(block.finish_exit(0));
+        assert_eq!(reason.status, ExitStatus::Trap(Trap::InvalidRegister(1)));
+
+        let block = super::block::BasicBlock::builder().finish_branch(
+            IntImmed::Bool(true),
+            String::from("missing"),
+            String::from("main"),
+        );
+        let (reason, _) = run_main(block);
+        assert_eq!(
+            reason.status,
+            ExitStatus::Trap(Trap::UnknownLabel(String::from("missing")))
+        );
+    }
+
+    #[test]
+    fn atomics() {
+        use super::backend::Trap;
+        use super::interpret::InterpreterBackend;
+        use super::ir::types::{AtomicOp, IntImmed, LValue, MemoryOrdering};
+        use super::mem::GuestMemory;
+
+        // Backed by u64s so that atomic accesses are aligned on the host
+        let mut memory = [10u64, 7];
+        let guest = unsafe { GuestMemory::new(memory.as_mut_ptr() as *mut u8, 16) };
+
+        let mut block = super::block::BasicBlock::builder();
+        block.guest_atomic_rmw(
+            LValue::Register(0),
+            AtomicOp::Add,
+            IntImmed::I64(0),
+            IntImmed::I32(5),
+            32,
+            MemoryOrdering::SeqCst,
+        );
+        block.guest_compare_exchange(
+            LValue::Register(1),
+            IntImmed::I64(8),
+            IntImmed::I64(7),
+            IntImmed::I64(9),
+            64,
+            MemoryOrdering::AcqRel,
+        );
+        // Fails, as the value is now 9
+        block.guest_compare_exchange(
+            LValue::Register(2),
+            IntImmed::I64(8),
+            IntImmed::I64(7),
+            IntImmed::I64(11),
+            64,
+            MemoryOrdering::AcqRel,
+        );
+        block.fence(MemoryOrdering::SeqCst);
+        block.guest_load_linked(
+            LValue::Scratch(0),
+            IntImmed::I64(0),
+            32,
+            MemoryOrdering::Acquire,
+        );
+        block.guest_store_conditional(
+            LValue::Register(3),
+            IntImmed::I64(0),
+            IntImmed::I32(100),
+            32,
+            MemoryOrdering::Release,
+        );
+        // The reservation was cleared by the first store
+        block.guest_store_conditional(
+            LValue::Register(4),
+            IntImmed::I64(0),
+            IntImmed::I32(200),
+            32,
+            MemoryOrdering::Release,
+        );
+        block.guest_atomic_rmw(
+            LValue::Register(5),
+            AtomicOp::Exchange,
+            IntImmed::I64(4),
+            IntImmed::I16(0x1234),
+            16,
+            MemoryOrdering::Relaxed,
+        );
+        block.guest_atomic_rmw(
+            LValue::Register(5),
+            AtomicOp::Xor,
+            IntImmed::I64(1),
+            IntImmed::I16(1),
+            16,
+            MemoryOrdering::Relaxed,
+        );
+        let unit = translation_unit([("main", block.finish_exit(0))]);
+
+        let ctx = ExecutionContext::new(InterpreterBackend::new(guest));
+        let mut state = [0u64; 6];
+        let reason = run_in(&ctx, unit, &mut state);
+
+        assert_eq!(reason.status, ExitStatus::Trap(Trap::MisalignedAtomic(1)));
+        assert_eq!(state, [10, 7, 9, 1, 0, 0]);
+        assert_eq!(memory, [0x1234_0000_0000 | 100, 9]);
+    }
+
+    #[test]
+    fn host_functions() {
+        use super::backend::Trap;
+        use super::host::Signature;
+        use super::interpret::InterpreterBackend;
+        use super::ir::types::{IntImmed, IntType, LValue};
+
+        let mut ctx = ExecutionContext::new(InterpreterBackend::default());
+        ctx.register_fn(
+            "sum",
+            Signature::new(&[IntType::I8, IntType::I64], Some(IntType::I64)),
+            |_, args| Ok(Some(IntImmed::I64(args[0].to_u64() + args[1].to_u64()))),
+        );
+        ctx.register_fn("swap", Signature::new(&[], None), |state, _| {
+            let r0 = state.register(0).unwrap();
+            let r1 = state.register(1).unwrap();
+            assert!(state.set_register(0, r1));
+            assert!(state.set_register(1, r0));
+            assert!(!state.set_register(4, r0));
+            Ok(None)
+        });
+        ctx.register_fn("bad", Signature::new(&[], Some(IntType::I8)), |_, _| {
+            Ok(Some(IntImmed::I16(1)))
+        });
+
+        let mut block = super::block::BasicBlock::builder();
+        // The first argument is truncated to 8 bits
+        block.fn_call(
+            LValue::Register(0),
+            "sum",
+            [IntImmed::I32(0x1ff).into(), LValue::Register(1).into()],
+        );
+        block.fn_call_void("swap", []);
+        let unit = translation_unit([("main", block.finish_exit(0))]);
+
+        let mut state = [0u64, 10, 0, 0];
+        let reason = run_in(&ctx, unit, &mut state);
+        assert_eq!(reason.status, ExitStatus::Exit(0));
+        assert_eq!(state, [10, 265, 0, 0]);
+
+        let call = |name: &str, dest: Option<LValue>| {
+            let mut block = super::block::BasicBlock::builder();
+            match dest {
+                Some(dest) => block.fn_call(dest, name, []),
+                None => block.fn_call_void(name, []),
+            }
+            let unit = translation_unit([("main", block.finish_exit(0))]);
+            run_in(&ctx, unit, &mut [0u64; 4]).status
+        };
+        assert_eq!(
+            call("missing", None),
+            ExitStatus::Trap(Trap::UnknownFunction(String::from("missing")))
+        );
+        // Wrong number of arguments
+        assert_eq!(
+            call("sum", Some(LValue::Scratch(0))),
+            ExitStatus::Trap(Trap::TypeMismatch)
+        );
+        // Returned value does not match the signature
+        assert_eq!(call("bad", None), ExitStatus::Trap(Trap::TypeMismatch));
+    }
+
+    #[test]
+    fn closure_backend() {
+        use super::interpret::ClosureBackend;
+        use super::ir::types::{Comparator, IntImmed, LValue};
+
+        let mut entry = super::block::BasicBlock::builder();
+        entry.add(
+            LValue::Register(1),
+            IntImmed::I8(0xfe),
+            IntImmed::I16(3),
+            true,
+        );
+        entry.sub(
+            LValue::Register(2),
+            IntImmed::I8(0),
+            IntImmed::I32(5),
+            false,
+        );
+        entry.div(
+            LValue::Register(3),
+            IntImmed::I64(100),
+            IntImmed::I64(7),
+            false,
+        );
+        let entry = entry.finish_branch(
+            IntImmed::Bool(true),
+            String::from("body"),
+            String::from("done"),
+        );
+
+        let mut body = super::block::BasicBlock::builder();
+        body.mult(
+            LValue::Register(0),
+            LValue::Register(0),
+            IntImmed::I8(0xff),
+            true,
+        );
+        body.xor(
+            LValue::Register(3),
+            LValue::Register(3),
+            LValue::Register(0),
+        );
+        body.add(
+            LValue::Register(4),
+            LValue::Register(4),
+            IntImmed::I32(1),
+            false,
+        );
+        body.int_cmp(
+            LValue::Scratch(0),
+            Comparator::SLT,
+            LValue::Register(4),
+            IntImmed::I8(9),
+        );
+        let body = body.finish_branch(
+            LValue::Scratch(0),
+            String::from("body"),
+            String::from("done"),
+        );
+
+        let unit = translation_unit([
+            ("entry", entry),
+            ("body", body),
+            ("done", super::block::BasicBlock::builder().finish_exit(4)),
+        ]);
+
+        let mut expected = [3u64, 0, 0, 0, 0];
+        let mut state = expected;
+        let expected_reason = run(unit.clone(), &mut expected);
+        let closure = ExecutionContext::new(ClosureBackend::default());
+        let reason = run_in(&closure, unit, &mut state);
+
+        assert_eq!(expected[..3], [-3i64 as u64, 1, 0xffff_fffb]);
+        assert_eq!(state, expected);
+        assert_eq!(reason, expected_reason);
+        assert_eq!(reason.status, ExitStatus::Exit(4));
+    }
+
+    #[test]
+    fn closure_backend_matches_interpreter() {
+        use super::backend::Trap;
+        use super::interpret::{ClosureBackend, InterpreterBackend};
+        use super::ir::types::{Comparator, Endianness, IntImmed, IntType, LValue};
+        use super::mem::GuestMemory;
+
+        let mut block = super::block::BasicBlock::builder();
+        block.instruction(0x1000, 4);
+        // Signed division and remainder round towards zero
+        block.div(
+            LValue::Register(0),
+            IntImmed::I16(0xfff9),
+            IntImmed::I8(2),
+            true,
+        );
+        block.rem(
+            LValue::Register(1),
+            IntImmed::I16(0xfff9),
+            IntImmed::I8(2),
+            true,
+        );
+        block.shift_left(LValue::Register(2), IntImmed::I16(1), IntImmed::I8(20));
+        block.shift_right(
+            LValue::Register(3),
+            IntImmed::I8(0x80),
+            IntImmed::I8(100),
+            true,
+        );
+        block.sign_extend(LValue::Register(4), IntImmed::I8(0x80), IntType::I32);
+        block.zero_extend(LValue::Register(5), IntImmed::I8(0x80), IntType::I32);
+        block.not(LValue::Register(6), IntImmed::I16(0x00ff));
+        block.instruction(0x1004, 2);
+        // Bools and 128 bit values go through the interpreter
+        block.int_cmp(
+            LValue::Scratch(0),
+            Comparator::ULT,
+            IntImmed::I8(1),
+            IntImmed::I8(2),
+        );
+        block.add(
+            LValue::Register(7),
+            LValue::Scratch(0),
+            IntImmed::Bool(true),
+            false,
+        );
+        block.not(LValue::Scratch(1), LValue::Scratch(0));
+        block.sub(
+            LValue::Register(8),
+            LValue::Scratch(1),
+            IntImmed::I8(1),
+            true,
+        );
+        block.add(
+            LValue::Scratch(2),
+            IntImmed::I128(u128::MAX),
+            IntImmed::I8(2),
+            false,
+        );
+        block.shift_right(
+            LValue::Register(9),
+            LValue::Scratch(2),
+            IntImmed::I8(0),
+            false,
+        );
+        block.select(
+            LValue::Scratch(0),
+            LValue::Register(10),
+            LValue::Register(4),
+            LValue::Register(5),
+        );
+        block.guest_mem_write_endian(IntImmed::I64(2), LValue::Register(4), 32, Endianness::Big);
+        block.guest_mem_read(LValue::Register(11), IntImmed::I64(0), 64);
+        let block = block.finish_compare_branch(
+            Comparator::SLT,
+            LValue::Register(4),
+            LValue::Register(5),
+            String::from("trap"),
+            String::from("main"),
+        );
+
+        let mut trap = super::block::BasicBlock::builder();
+        trap.instruction(0x1006, 4);
+        trap.div(
+            LValue::Register(12),
+            IntImmed::I8(0x80),
+            IntImmed::I8(0xff),
+            true,
+        );
+        let trap = trap.finish_exit(0);
+
+        let unit = translation_unit([("main", block), ("trap", trap)]);
+
+        let mut memory = [0u8; 16];
+        let mut run_on = |closure: bool| {
+            memory = [0; 16];
+            let guest = unsafe { GuestMemory::new(memory.as_mut_ptr(), memory.len()) };
+            let mut state = [0u64; 13];
+            let reason = if closure {
+                let ctx = ExecutionContext::new(ClosureBackend::new(guest));
+                run_in(&ctx, unit.clone(), &mut state)
+            } else {
+                let ctx = ExecutionContext::new(InterpreterBackend::new(guest));
+                run_in(&ctx, unit.clone(), &mut state)
+            };
+            (state, reason, memory)
+        };
+
+        let expected = run_on(false);
+        assert_eq!(
+            expected.0[..12],
+            [
+                -3i64 as u64,
+                -1i64 as u64,
+                0,
+                u64::MAX,
+                -128i64 as u64,
+                0x80,
+                0xff00,
+                2,
+                u64::MAX,
+                1,
+                -128i64 as u64,
+                0x80ff_ffff_0000,
+            ]
+        );
+        assert_eq!(expected.1.status, ExitStatus::Trap(Trap::IntegerOverflow));
+        assert_eq!(run_on(true), expected);
+    }
+
+    #[test]
+    fn compare_branch() {
+        use super::interpret::ClosureBackend;
+        use super::ir::types::{Comparator, IntImmed, LValue};
+
+        let mut entry = super::block::BasicBlock::builder();
+        // -1 as an I8 is sign extended for signed comparisons only
+        entry.int_cmp(
+            LValue::Register(1),
+            Comparator::SLE,
+            IntImmed::I8(0xff),
+            IntImmed::I32(0),
+        );
+        entry.int_cmp(
+            LValue::Register(2),
+            Comparator::ULE,
+            IntImmed::I8(0xff),
+            IntImmed::I32(0x100),
+        );
+        entry.int_cmp(
+            LValue::Register(3),
+            Comparator::SGE,
+            IntImmed::I16(5),
+            IntImmed::I16(5),
+        );
+        entry.int_cmp(
+            LValue::Register(4),
+            Comparator::UGE,
+            IntImmed::I16(4),
+            IntImmed::I16(5),
+        );
+        let entry = entry.finish_compare_branch(
+            Comparator::SGE,
+            IntImmed::I8(0xff),
+            IntImmed::I8(0),
+            String::from("done"),
+            String::from("body"),
+        );
+
+        let mut body = super::block::BasicBlock::builder();
+        body.add(
+            LValue::Register(0),
+            LValue::Register(0),
+            IntImmed::I64(1),
+            false,
+        );
+        let body = body.finish_compare_branch(
+            Comparator::ULE,
+            LValue::Register(0),
+            IntImmed::I8(9),
+            String::from("body"),
+            String::from("done"),
+        );
+
+        let unit = translation_unit([
+            ("entry", entry),
+            ("body", body),
+            ("done", super::block::BasicBlock::builder().finish_exit(0)),
+        ]);
+
+        let mut expected = [0u64; 5];
+        let mut state = expected;
+        let expected_reason = run(unit.clone(), &mut expected);
+        let closure = ExecutionContext::new(ClosureBackend::default());
+        let reason = run_in(&closure, unit, &mut state);
+
+        assert_eq!(expected, [10, 1, 1, 1, 0]);
+        assert_eq!(state, expected);
+        assert_eq!(reason, expected_reason);
+        assert_eq!(reason.blocks_retired, 12);
+    }
+
+    #[test]
+    fn condition_flags() {
+        use super::backend::Trap;
+        use super::ir::types::{Condition, IntImmed, LValue};
+
+        let mut entry = super::block::BasicBlock::builder();
+        entry.sub_with_flags(LValue::Register(0), IntImmed::I8(5), IntImmed::I8(7), false);
+        let entry = entry.finish_branch_condition(
+            Condition::Carry,
+            String::from("check"),
+            String::from("bad"),
+        );
+
+        // Flags set in the previous block are still live here, even
+        // across a resumption
+        let mut check = super::block::BasicBlock::builder();
+        check.read_condition(LValue::Register(1), Condition::SignedLess);
+        check.read_condition(LValue::Register(2), Condition::Zero);
+        check.add_with_flags(
+            LValue::Scratch(0),
+            IntImmed::I8(0x7f),
+            IntImmed::I8(1),
+            false,
+        );
+        check.read_condition(LValue::Register(3), Condition::Overflow);
+        check.read_condition(LValue::Register(4), Condition::AuxCarry);
+        check.and_with_flags(LValue::Register(5), IntImmed::I8(0x0f), IntImmed::I8(0x03));
+        check.select_condition(
+            Condition::ParityEven,
+            LValue::Register(6),
+            IntImmed::I64(1),
+            IntImmed::I64(2),
+        );
+        check.read_condition(LValue::Register(7), Condition::Carry);
+        let check = check.finish_exit(0);
+
+        let unit = translation_unit([
+            ("entry", entry),
+            ("check", check),
+            ("bad", super::block::BasicBlock::builder().finish_exit(1)),
+        ]);
+
+        let ctx: ExecutionContext<super::interpret::InterpreterBackend> =
+            ExecutionContext::default();
+        let mut tb = ctx.compile(Box::new(unit)).unwrap();
+        let mut state = [0u64; 8];
+        let reason = unsafe { tb.execute_with_fuel(&mut state, Fuel::Blocks(1)) };
+        let ExitStatus::OutOfFuel(resume) = reason.status else {
+            panic!("{:?}", reason)
+        };
+        let reason = unsafe { tb.resume(&mut state, resume, Fuel::Unlimited) };
+
+        assert_eq!(reason.status, ExitStatus::Exit(0));
+        assert_eq!(state, [0xfe, 1, 0, 1, 1, 3, 1, 0]);
+
+        let mut block = super::block::BasicBlock::builder();
+        block.read_condition(LValue::Register(0), Condition::Zero);
+        let unit = translation_unit([("main", block.finish_exit(0))]);
+        let reason = run_in(&ctx, unit, &mut state);
+        assert_eq!(reason.status, ExitStatus::Trap(Trap::UndefinedFlags));
+    }
+
+    #[test]
+    fn condition_flags_aliased_dest() {
+        use super::interpret::ClosureBackend;
+        use super::ir::types::{Condition, IntImmed, LValue};
+
+        // The flags come from the operands, not from the result written
+        // over one of them
+        let mut block = super::block::BasicBlock::builder();
+        block.add_with_flags(
+            LValue::Register(0),
+            LValue::Register(0),
+            IntImmed::I64(1),
+            false,
+        );
+        block.read_condition(LValue::Register(2), Condition::Carry);
+        block.read_condition(LValue::Register(3), Condition::Zero);
+        block.xor_with_flags(
+            LValue::Register(1),
+            LValue::Register(1),
+            IntImmed::I64(0x0f),
+        );
+        block.read_condition(LValue::Register(4), Condition::Zero);
+        let unit = translation_unit([("main", block.finish_exit(0))]);
+
+        let mut state = [u64::MAX, 0x0f, 0, 0, 0];
+        let reason = run(unit.clone(), &mut state);
+        assert_eq!(reason.status, ExitStatus::Exit(0));
+        assert_eq!(state, [0, 0, 1, 1, 1]);
+
+        let closure = ExecutionContext::new(ClosureBackend::default());
+        let mut state = [u64::MAX, 0x0f, 0, 0, 0];
+        let reason = run_in(&closure, unit, &mut state);
+        assert_eq!(reason.status, ExitStatus::Exit(0));
+        assert_eq!(state, [0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn fuel() {
+        use super::ir::types::{Comparator, IntImmed, LValue};
+
+        let mut entry = super::block::BasicBlock::builder();
+        entry.add(
+            LValue::Scratch(5),
+            IntImmed::I64(7),
+            IntImmed::I64(0),
+            false,
+        );
+        let entry = entry.finish_branch(
+            IntImmed::Bool(true),
+            String::from("body"),
+            String::from("body"),
+        );
+
+        let mut body = super::block::BasicBlock::builder();
+        body.add(
+            LValue::Register(0),
+            LValue::Register(0),
+            LValue::Scratch(5),
+            false,
+        );
+        body.int_cmp(
+            LValue::Scratch(0),
+            Comparator::ULT,
+            LValue::Register(0),
+            IntImmed::I64(700),
+        );
+        let body = body.finish_branch(
+            LValue::Scratch(0),
+            String::from("body"),
+            String::from("done"),
+        );
+
+        let unit = translation_unit([
+            ("entry", entry),
+            ("body", body),
+            ("done", super::block::BasicBlock::builder().finish_exit(0)),
+        ]);
+
+        let ctx: ExecutionContext<super::interpret::InterpreterBackend> =
+            ExecutionContext::default();
+        let mut tb = ctx.compile(Box::new(unit)).unwrap();
+        let mut state = [0u64; 1];
+
+        let reason = unsafe { tb.execute_with_fuel(&mut state, Fuel::Blocks(1)) };
+        assert_eq!(state[0], 0);
+        assert_eq!(reason.block, "entry");
+        let ExitStatus::OutOfFuel(resume) = reason.status else {
+            panic!("Expected to run out of fuel");
+        };
+
+        // Scratch 5 is carried over from the entry block
+        let reason = unsafe { tb.resume(&mut state, resume, Fuel::Ops(10)) };
+        assert_eq!(state[0], 7 * 4);
+        assert_eq!((reason.ops_retired, reason.blocks_retired), (12, 4));
+        let ExitStatus::OutOfFuel(resume) = reason.status else {
+            panic!("Expected to run out of fuel");
+        };
+
+        let reason = unsafe { tb.resume(&mut state, resume, Fuel::Unlimited) };
+        assert_eq!(state[0], 700);
+        assert_eq!(reason.status, ExitStatus::Exit(0));
+    }
+
+    #[test]
+    fn foreign_resume_point() {
+        use super::backend::{Executable, ResumePoint, Trap};
+        use super::interpret::InterpreterBackend;
+        use super::ir::types::{IntImmed, LValue};
+        use super::mem::GuestMemory;
+
+        let mut entry = super::block::BasicBlock::builder();
+        entry.add(
+            LValue::Register(0),
+            LValue::Register(0),
+            IntImmed::I64(1),
+            false,
+        );
+        let entry = entry.finish_branch(
+            IntImmed::Bool(true),
+            String::from("second"),
+            String::from("second"),
+        );
+        let mut second = super::block::BasicBlock::builder();
+        second.add(
+            LValue::Register(0),
+            LValue::Register(0),
+            IntImmed::I64(2),
+            false,
+        );
+
+        let unit = translation_unit([("entry", entry), ("second", second.finish_exit(0))]);
+
+        // A single block unit, which the two block unit's point would
+        // index past the end of, and the same unit over other guest memory
+        let other =
+            translation_unit([("main", super::block::BasicBlock::builder().finish_exit(1))]);
+
+        let mut memory = [0u8; 8];
+        let backend = InterpreterBackend::default();
+        let exec = backend.compile::<[u64; 1]>(&unit).unwrap();
+        let other = backend.compile::<[u64; 1]>(&other).unwrap();
+        let remapped = unsafe { GuestMemory::new(memory.as_mut_ptr(), memory.len()) };
+        let remapped = InterpreterBackend::new(remapped)
+            .compile::<[u64; 1]>(&unit)
+            .unwrap();
+        let mut state = [0u64; 1];
+
+        unsafe {
+            let reason = exec.execute_with_fuel(&mut state, Fuel::Blocks(1));
+            let ExitStatus::OutOfFuel(resume) = reason.status else {
+                panic!("Expected to run out of fuel");
+            };
+
+            for foreign in [&other, &remapped] {
+                let reason = foreign.resume(&mut state, resume.clone(), Fuel::Unlimited);
+                assert_eq!(reason.status, ExitStatus::Trap(Trap::ForeignResumePoint));
+                assert_eq!(reason.ops_retired, 0);
+            }
+            let reason = exec.resume(&mut state, ResumePoint::new(0u8), Fuel::Unlimited);
+            assert_eq!(reason.status, ExitStatus::Trap(Trap::ForeignResumePoint));
+            assert_eq!(state[0], 1);
+
+            // Recompiling an unchanged unit gives an interchangeable executable
+            let twin = backend.compile::<[u64; 1]>(&unit).unwrap();
+            let reason = twin.resume(&mut state, resume, Fuel::Unlimited);
+            assert_eq!(reason.status, ExitStatus::Exit(0));
+        }
+        assert_eq!(state[0], 3);
+    }
+
+    #[test]
+    fn guest_instructions() {
+        use super::backend::{Executable, Trap};
+        use super::interpret::{ClosureBackend, InterpreterBackend};
+        use super::ir::types::{IntImmed, LValue};
+
+        let mut entry = super::block::BasicBlock::builder();
+        entry.instruction(0x1000, 4);
+        entry.add(
+            LValue::Register(0),
+            LValue::Register(0),
+            IntImmed::I64(1),
+            false,
+        );
+        entry.instruction(0x1004, 4);
+        entry.add(
+            LValue::Register(0),
+            LValue::Register(0),
+            IntImmed::I64(2),
+            false,
+        );
+        // The instruction at 0x1008 continues into the next block
+        entry.instruction(0x1008, 2);
+        let entry = entry.finish_branch(
+            IntImmed::Bool(true),
+            String::from("next"),
+            String::from("next"),
+        );
+
+        let mut next = super::block::BasicBlock::builder();
+        next.add(
+            LValue::Register(0),
+            LValue::Register(0),
+            IntImmed::I64(4),
+            false,
+        );
+        next.instruction(0x100a, 4);
+        next.div(
+            LValue::Scratch(0),
+            IntImmed::I64(100),
+            LValue::Register(1),
+            false,
+        );
+        let unit = translation_unit([("entry", entry), ("next", next.finish_exit(0))]);
+
+        let interp = InterpreterBackend::default()
+            .compile::<[u64; 2]>(&unit)
+            .unwrap();
+        let closure = ClosureBackend::default()
+            .compile::<[u64; 2]>(&unit)
+            .unwrap();
+        let backends: [&dyn Executable<[u64; 2]>; 2] = [&interp, &closure];
+
+        for exec in backends {
+            // The faulting instruction is reported, and is not retired
+            let mut state = [0u64; 2];
+            let reason = unsafe { exec.execute(&mut state) };
+            assert_eq!(reason.status, ExitStatus::Trap(Trap::DivideByZero));
+            assert_eq!(reason.guest_pc, Some(0x100a));
+            assert_eq!(reason.instructions_retired, 3);
+
+            // Runs stop before the marker of the next instruction
+            let mut state = [0u64, 5];
+            let reason = unsafe { exec.execute_with_fuel(&mut state, Fuel::Instructions(2)) };
+            assert_eq!(state[0], 3);
+            assert_eq!(
+                (reason.guest_pc, reason.instructions_retired),
+                (Some(0x1008), 2)
+            );
+            let ExitStatus::OutOfFuel(resume) = reason.status else {
+                panic!("Expected to run out of fuel");
+            };
+
+            let reason = unsafe { exec.resume(&mut state, resume, Fuel::Instructions(1)) };
+            assert_eq!(state[0], 7);
+            assert_eq!(
+                (reason.guest_pc, reason.instructions_retired),
+                (Some(0x100a), 1)
+            );
+            assert_eq!(reason.block, "next");
+            let ExitStatus::OutOfFuel(resume) = reason.status else {
+                panic!("Expected to run out of fuel");
+            };
+
+            let reason = unsafe { exec.resume(&mut state, resume, Fuel::Instructions(1)) };
+            assert_eq!(reason.status, ExitStatus::Exit(0));
+            assert_eq!(
+                (reason.guest_pc, reason.instructions_retired),
+                (Some(0x100a), 1)
+            );
+            assert_eq!(reason.ops_retired, 3);
+        }
+    }
+
+    #[test]
+    fn indirect_branch() {
+        use super::backend::{Executable, Trap};
+        use super::interpret::{ClosureBackend, InterpreterBackend};
+        use super::ir::types::{IntImmed, LValue};
+
+        let entry = super::block::BasicBlock::builder().finish_indirect_branch(
+            LValue::Register(0),
+            vec![
+                (0x20, String::from("b")),
+                (0x10, String::from("a")),
+                (0x10, String::from("b")),
+                (0x40, String::from("missing")),
+            ],
+        );
+
+        let targets = [("a", 1), ("b", 2)].map(|(label, value)| {
+            let mut block = super::block::BasicBlock::builder();
+            block.add(
+                LValue::Register(1),
+                IntImmed::I64(value),
+                IntImmed::I64(0),
+                false,
+            );
+            (label, block.finish_exit(0))
+        });
+        let unit = translation_unit([("entry", entry)].into_iter().chain(targets));
+
+        let interp = InterpreterBackend::default()
+            .compile::<[u64; 2]>(&unit)
+            .unwrap();
+        let closure = ClosureBackend::default()
+            .compile::<[u64; 2]>(&unit)
+            .unwrap();
+        let backends: [&dyn Executable<[u64; 2]>; 2] = [&interp, &closure];
+
+        for exec in backends {
+            // The first entry for an address is used
+            let mut state = [0x10u64, 0];
+            let reason = unsafe { exec.execute(&mut state) };
+            assert_eq!((reason.status, state[1]), (ExitStatus::Exit(0), 1));
+
+            let mut state = [0x20u64, 0];
+            let reason = unsafe { exec.execute(&mut state) };
+            assert_eq!((reason.status, state[1]), (ExitStatus::Exit(0), 2));
+
+            let mut state = [0x30u64, 0];
+            let reason = unsafe { exec.execute(&mut state) };
+            assert_eq!(reason.status, ExitStatus::Jump(0x30));
+            assert_eq!((reason.block.as_str(), reason.blocks_retired), ("entry", 1));
+
+            let mut state = [0x40u64, 0];
+            let reason = unsafe { exec.execute(&mut state) };
+            assert_eq!(
+                reason.status,
+                ExitStatus::Trap(Trap::UnknownLabel(String::from("missing")))
+            );
+        }
+    }
+
+    #[test]
+    fn tracer() {
+        use super::backend::Trap;
+        use super::interpret::{InterpreterBackend, TraceOp, Tracer};
+        use super::ir::types::{IntImmed, IntType, LValue};
+        use std::{cell::RefCell, rc::Rc};
+
+        type Event = (
+            String,
+            &'static str,
+            Vec<IntImmed>,
+            Option<(LValue, IntImmed)>,
+        );
+
+        #[derive(Default)]
+        struct Recorder {
+            events: Vec<Event>,
+            traps: Vec<(&'static str, Trap)>,
+        }
+
+        impl Tracer for Recorder {
+            fn before_op(&mut self, op: &TraceOp, operands: &[IntImmed]) {
+                self.events
+                    .push((op.block.clone(), op.name, operands.to_vec(), None));
+            }
+
+            fn after_op(&mut self, _op: &TraceOp, write: Option<(LValue, IntImmed)>) {
+                self.events.last_mut().unwrap().3 = write;
+            }
+
+            fn on_trap(&mut self, op: &TraceOp, trap: &Trap) {
+                self.traps.push((op.name, trap.clone()));
+            }
+        }
+
+        let mut block = super::block::BasicBlock::builder();
+        block.add(LValue::Scratch(0), IntImmed::I8(2), IntImmed::I8(3), false);
+        block.zero_extend(LValue::Register(0), LValue::Scratch(0), IntType::I16);
+
+        let recorder = Rc::new(RefCell::new(Recorder::default()));
+        let ctx =
+            ExecutionContext::new(InterpreterBackend::default().with_tracer(recorder.clone()));
+        let mut state = [0u32; 1];
+        let unit = translation_unit([("main", block.finish_exit(0))]);
+        run_in(&ctx, unit, &mut state);
+
+        let main = String::from("main");
+        assert_eq!(
+            recorder.borrow().events,
+            vec![
+                (
+                    main.clone(),
+                    "add",
+                    vec![IntImmed::I8(2), IntImmed::I8(3)],
+                    Some((LValue::Scratch(0), IntImmed::I8(5)))
+                ),
+                (
+                    main.clone(),
+                    "zero_extend",
+                    vec![IntImmed::I8(5)],
+                    Some((LValue::Register(0), IntImmed::I32(5)))
+                ),
+                (main, "exit", vec![], None),
+            ]
+        );
+        assert!(recorder.borrow().traps.is_empty());
+
+        // The trapping operation is reported, both when executing it
+        // traps and when one of its operands cannot be read
+        let mut block = super::block::BasicBlock::builder();
+        block.div(LValue::Register(0), IntImmed::I8(1), IntImmed::I8(0), false);
+        let unit = translation_unit([("div", block.finish_exit(0))]);
+        run_in(&ctx, unit, &mut state);
+
+        let mut block = super::block::BasicBlock::builder();
+        block.add(
+            LValue::Register(0),
+            IntImmed::I8(1),
+            LValue::Scratch(3),
+            false,
+        );
+        let unit = translation_unit([("scratch", block.finish_exit(0))]);
+        run_in(&ctx, unit, &mut state);
+
+        let recorder = recorder.borrow();
+        assert_eq!(
+            recorder.traps,
+            vec![
+                ("div", Trap::DivideByZero),
+                ("add", Trap::UninitializedScratch(3))
+            ]
+        );
+        let events = &recorder.events[recorder.events.len() - 2..];
+        assert_eq!(events[0].2, vec![IntImmed::I8(1), IntImmed::I8(0)]);
+        assert_eq!(events[1].2, vec![IntImmed::I8(1)]);
+
+        // Overflow operations are reported under their own names, without
+        // the carry in of the carry operations
+        let overflow = Rc::new(RefCell::new(Recorder::default()));
+        let ctx =
+            ExecutionContext::new(InterpreterBackend::default().with_tracer(overflow.clone()));
+        let mut block = super::block::BasicBlock::builder();
+        block.add_overflow(
+            LValue::Scratch(0),
+            LValue::Scratch(1),
+            IntImmed::I8(1),
+            IntImmed::I8(2),
+            false,
+        );
+        block.sub_overflow(
+            LValue::Scratch(0),
+            LValue::Scratch(1),
+            IntImmed::I8(1),
+            IntImmed::I8(2),
+            false,
+        );
+        let unit = translation_unit([("overflow", block.finish_exit(0))]);
+        run_in(&ctx, unit, &mut state);
+
+        let events = &overflow.borrow().events;
+        let names: Vec<_> = events.iter().map(|event| event.1).collect();
+        assert_eq!(names, ["add_overflow", "sub_overflow", "exit"]);
+        assert_eq!(events[0].2, vec![IntImmed::I8(1), IntImmed::I8(2)]);
+    }
+
+    #[test]
+    fn profiler() {
+        use super::interpret::{InterpreterBackend, Profiler};
+        use super::ir::types::{Comparator, IntImmed, LValue};
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let entry = super::block::BasicBlock::builder().finish_branch(
+            IntImmed::Bool(true),
+            String::from("body"),
+            String::from("done"),
+        );
+
+        let mut body = super::block::BasicBlock::builder();
+        body.add(
+            LValue::Register(0),
+            LValue::Register(0),
+            IntImmed::I64(1),
+            false,
+        );
+        body.int_cmp(
+            LValue::Scratch(0),
+            Comparator::ULT,
+            LValue::Register(0),
+            IntImmed::I64(10),
+        );
+        let body = body.finish_branch(
+            LValue::Scratch(0),
+            String::from("body"),
+            String::from("done"),
+        );
+
+        let unit = translation_unit([
+            ("entry", entry),
+            ("body", body),
+            ("done", super::block::BasicBlock::builder().finish_exit(0)),
+        ]);
+
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        let ctx =
+            ExecutionContext::new(InterpreterBackend::default().with_profiler(profiler.clone()));
+        run_in(&ctx, unit, &mut [0u64; 1]);
+
+        let profiler = profiler.borrow();
+        let body = profiler.block("body").unwrap();
+        assert_eq!((body.entries, body.ops_retired), (10, 30));
+        assert_eq!(body.edges.get("body"), Some(&9));
+        assert_eq!(body.edges.get("done"), Some(&1));
+        assert_eq!(profiler.block("done").unwrap().entries, 1);
+
+        let report = profiler.report();
+        assert_eq!(report.len(), 3);
+        assert_eq!(report[0].0, "body");
+    }
+
+    #[test]
+    fn profiler_indirect_branch() {
+        use super::interpret::{InterpreterBackend, Profiler};
+        use super::ir::types::{IntImmed, LValue};
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        // Each pass through the dispatch block takes a different table entry
+        let dispatch = super::block::BasicBlock::builder().finish_indirect_branch(
+            LValue::Register(0),
+            vec![
+                (0, String::from("a")),
+                (1, String::from("b")),
+                (2, String::from("c")),
+            ],
+        );
+
+        let targets = ["a", "b", "c"].map(|label| {
+            let mut block = super::block::BasicBlock::builder();
+            block.add(
+                LValue::Register(0),
+                LValue::Register(0),
+                IntImmed::I64(1),
+                false,
+            );
+            let block = block.finish_branch(
+                IntImmed::Bool(true),
+                String::from("dispatch"),
+                String::from("dispatch"),
+            );
+            (label, block)
+        });
+        let unit = translation_unit([("dispatch", dispatch)].into_iter().chain(targets));
+
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        let ctx =
+            ExecutionContext::new(InterpreterBackend::default().with_profiler(profiler.clone()));
+        let reason = run_in(&ctx, unit, &mut [0u64; 1]);
+        assert_eq!(reason.status, ExitStatus::Jump(3));
+
+        let profiler = profiler.borrow();
+        let dispatch = profiler.block("dispatch").unwrap();
+        assert_eq!(dispatch.entries, 4);
+        assert_eq!(dispatch.edges.len(), 3);
+        for label in ["a", "b", "c"] {
+            assert_eq!(dispatch.edges.get(label), Some(&1));
+        }
+    }
+
+    #[test]
+    fn debugger() {
+        use super::interpret::{DebugStop, InterpreterBackend};
+        use super::ir::types::{Comparator, IntImmed, LValue};
+
+        let mut body = super::block::BasicBlock::builder();
+        body.add(
+            LValue::Register(0),
+            LValue::Register(0),
+            IntImmed::I64(1),
+            false,
+        );
+        body.int_cmp(
+            LValue::Scratch(0),
+            Comparator::ULT,
+            LValue::Register(0),
+            IntImmed::I64(1000),
+        );
+        let body = body.finish_branch(
+            LValue::Scratch(0),
+            String::from("body"),
+            String::from("done"),
+        );
+
+        let unit = translation_unit([
+            ("body", body),
+            ("done", super::block::BasicBlock::builder().finish_exit(3)),
+        ]);
+
+        let exec = InterpreterBackend::default()
+            .compile::<[u64; 1]>(&unit)
+            .unwrap();
+        let mut state = [0u64; 1];
+        let mut dbg = exec.debug(&mut state);
+
+        assert!(dbg.add_breakpoint("body", 3).is_err());
+        dbg.add_breakpoint("body", 1).unwrap();
+
+        unsafe {
+            assert_eq!(dbg.run(), DebugStop::Breakpoint);
+            assert_eq!(dbg.register(0), Some(IntImmed::I64(1)));
+            assert!(dbg.set_register(0, IntImmed::I64(998)));
+
+            assert_eq!(dbg.step_op(), DebugStop::Step);
+            assert_eq!(dbg.position(), Some((&String::from("body"), 2)));
+            assert_eq!(dbg.scratch(0), Some(IntImmed::Bool(true)));
+
+            assert_eq!(dbg.step_block(), DebugStop::Step);
+            assert_eq!(dbg.position(), Some((&String::from("body"), 0)));
+
+            assert_eq!(dbg.run(), DebugStop::Breakpoint);
+            assert_eq!(dbg.state()[0], 999);
+            assert!(dbg.remove_breakpoint("body", 1));
+
+            // Leave the loop early by overriding the comparison result
+            assert_eq!(dbg.step_op(), DebugStop::Step);
+            dbg.set_scratch(0, IntImmed::Bool(false));
+
+            // Scratch values the unit never uses can still be written
+            assert_eq!(dbg.scratch(200), None);
+            dbg.set_scratch(200, IntImmed::I8(7));
+            assert_eq!(dbg.scratch(200), Some(IntImmed::I8(7)));
+
+            let DebugStop::Exited(reason) = dbg.run() else {
+                panic!("Expected the unit to exit");
+            };
+            assert_eq!(reason.status, ExitStatus::Exit(3));
+            assert_eq!(dbg.position(), None);
+        }
+
+        assert_eq!(state[0], 999);
+    }
 }
@@ -0,0 +1,388 @@
+use crate::backend::Trap;
+use crate::ir::types::{
+    AtomicOp, Endianness, IntType, MemoryOrdering, StructType, StructTypeImmed, Type,
+};
+use crate::IntImmed;
+use std::sync::atomic::{self, AtomicU16, AtomicU32, AtomicU64, AtomicU8, Ordering};
+
+/// Runs `$body` with `$atomic` bound to the atomic integer of type `$ty` at
+/// `$ptr`, `$int` to its underlying integer type, and `$order` to the
+/// function converting between that integer's native and guest byte order.
+macro_rules! with_atomic {
+    ($ptr:expr, $ty:expr, $endianness:expr, |$atomic:ident, $int:ident, $order:ident| $body:expr) => {
+        match $ty {
+            IntType::I8 => {
+                type $int = u8;
+                let $atomic = AtomicU8::from_ptr($ptr);
+                let $order = |value: $int| value;
+                u64::from($body)
+            }
+            IntType::I16 => {
+                with_atomic!(@sized AtomicU16, u16, $ptr, $endianness, $atomic, $int, $order, $body)
+            }
+            IntType::I32 => {
+                with_atomic!(@sized AtomicU32, u32, $ptr, $endianness, $atomic, $int, $order, $body)
+            }
+            _ => with_atomic!(@sized AtomicU64, u64, $ptr, $endianness, $atomic, $int, $order, $body),
+        }
+    };
+    (@sized $atomic_ty:ident, $int_ty:ident, $ptr:expr, $endianness:expr,
+     $atomic:ident, $int:ident, $order:ident, $body:expr) => {{
+        type $int = $int_ty;
+        let $atomic = $atomic_ty::from_ptr($ptr as *mut $int_ty);
+        let $order: fn($int) -> $int = match $endianness {
+            Endianness::Little => $int_ty::from_le,
+            Endianness::Big => $int_ty::from_be,
+        };
+        u64::from($body)
+    }};
+}
+
+/// The size, ordering and byte order of an atomic guest memory access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct AtomicAccess {
+    pub(crate) size: u8,
+    pub(crate) ordering: MemoryOrdering,
+    pub(crate) endianness: Endianness,
+}
+
+/// The width, alignment assumption and volatility of a host memory access
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct HostAccess {
+    ty: IntType,
+    aligned: bool,
+    volatile: bool,
+}
+
+impl HostAccess {
+    pub(crate) fn new(ty: IntType, aligned: bool, volatile: bool) -> Self {
+        Self {
+            ty,
+            aligned,
+            volatile,
+        }
+    }
+
+    /// Returns the access width in bytes, checking the alignment of `addr`
+    fn check(&self, addr: u64) -> Result<usize, Trap> {
+        let bytes = Type::Integer(self.ty).size();
+        if self.aligned && addr % bytes as u64 != 0 {
+            return Err(Trap::MisalignedHostAccess(addr));
+        }
+        Ok(bytes)
+    }
+
+    /// Reads a value in native byte order from host address `addr`
+    pub(crate) unsafe fn load(&self, addr: u64) -> Result<IntImmed, Trap> {
+        let ptr = addr as usize as *const u8;
+        let value = match self.check(addr)? {
+            1 => u128::from(self.load_as::<u8>(ptr)),
+            2 => u128::from(self.load_as::<u16>(ptr)),
+            4 => u128::from(self.load_as::<u32>(ptr)),
+            8 => u128::from(self.load_as::<u64>(ptr)),
+            _ => self.load_as::<u128>(ptr),
+        };
+        Ok(self.ty.from_u128(value))
+    }
+
+    unsafe fn load_as<T: Copy>(&self, ptr: *const u8) -> T {
+        let ptr = ptr as *const T;
+        match (self.aligned, self.volatile) {
+            (true, true) => ptr.read_volatile(),
+            (true, false) => ptr.read(),
+            (false, false) => ptr.read_unaligned(),
+            (false, true) => {
+                let mut value = core::mem::MaybeUninit::<T>::uninit();
+                let bytes = value.as_mut_ptr() as *mut u8;
+                for i in 0..std::mem::size_of::<T>() {
+                    *bytes.add(i) = (ptr as *const u8).add(i).read_volatile();
+                }
+                value.assume_init()
+            }
+        }
+    }
+
+    /// Writes the low bits of `value` in native byte order to host address `addr`
+    pub(crate) unsafe fn store(&self, addr: u64, value: IntImmed) -> Result<(), Trap> {
+        let ptr = addr as usize as *mut u8;
+        let value = value.to_u128();
+        match self.check(addr)? {
+            1 => self.store_as(ptr, value as u8),
+            2 => self.store_as(ptr, value as u16),
+            4 => self.store_as(ptr, value as u32),
+            8 => self.store_as(ptr, value as u64),
+            _ => self.store_as(ptr, value),
+        }
+        Ok(())
+    }
+
+    unsafe fn store_as<T: Copy>(&self, ptr: *mut u8, value: T) {
+        let ptr = ptr as *mut T;
+        match (self.aligned, self.volatile) {
+            (true, true) => ptr.write_volatile(value),
+            (true, false) => ptr.write(value),
+            (false, false) => ptr.write_unaligned(value),
+            (false, true) => {
+                let bytes = &value as *const T as *const u8;
+                for i in 0..std::mem::size_of::<T>() {
+                    (ptr as *mut u8).add(i).write_volatile(*bytes.add(i));
+                }
+            }
+        }
+    }
+}
+
+/// A location reserved by a load-linked access, along with the value
+/// it read. A store-conditional to the location succeeds only if it
+/// still holds that value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Reservation {
+    pub(crate) addr: u64,
+    pub(crate) size: u8,
+    pub(crate) value: u64,
+}
+
+impl MemoryOrdering {
+    fn load(self) -> Ordering {
+        match self {
+            Self::Relaxed | Self::Release => Ordering::Relaxed,
+            Self::Acquire | Self::AcqRel => Ordering::Acquire,
+            Self::SeqCst => Ordering::SeqCst,
+        }
+    }
+
+    fn rmw(self) -> Ordering {
+        match self {
+            Self::Relaxed => Ordering::Relaxed,
+            Self::Acquire => Ordering::Acquire,
+            Self::Release => Ordering::Release,
+            Self::AcqRel => Ordering::AcqRel,
+            Self::SeqCst => Ordering::SeqCst,
+        }
+    }
+}
+
+/// A flat region of host memory backing the guest address space.
+/// Guest address `addr` maps to host address `base + addr`. The byte
+/// order of values is chosen per access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuestMemory {
+    base: *mut u8,
+    size: usize,
+}
+
+impl Default for GuestMemory {
+    /// An empty guest address space, on which every access is out of bounds
+    fn default() -> Self {
+        Self {
+            base: core::ptr::null_mut(),
+            size: 0,
+        }
+    }
+}
+
+impl GuestMemory {
+    /// # Safety
+    /// `base` must be valid for reads and writes of `size` bytes for as long
+    /// as any executable using this guest memory can be run.
+    pub unsafe fn new(base: *mut u8, size: usize) -> Self {
+        Self { base, size }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Maps an access of `bytes` bytes at guest address `addr` to a host pointer,
+    /// if the whole access lies within the guest address space
+    pub(crate) fn translate(&self, addr: u64, bytes: usize) -> Option<*mut u8> {
+        let addr = usize::try_from(addr).ok()?;
+        if addr.checked_add(bytes)? > self.size {
+            return None;
+        }
+
+        Some(unsafe { self.base.add(addr) })
+    }
+
+    /// Converts an access size in bits to the integer type it loads
+    pub(crate) fn access_type(size: u8) -> Option<IntType> {
+        match size {
+            8 => Some(IntType::I8),
+            16 => Some(IntType::I16),
+            32 => Some(IntType::I32),
+            64 => Some(IntType::I64),
+            128 => Some(IntType::I128),
+            _ => None,
+        }
+    }
+
+    /// Reads a `size` bit value from guest address `addr`
+    pub(crate) unsafe fn read(
+        &self,
+        addr: u64,
+        size: u8,
+        endianness: Endianness,
+    ) -> Result<IntImmed, Trap> {
+        let ty = Self::access_type(size).ok_or(Trap::InvalidAccessSize(size))?;
+        let bytes = size as usize / 8;
+        let ptr = self
+            .translate(addr, bytes)
+            .ok_or(Trap::GuestMemoryFault(addr))?;
+
+        let value = endianness.read_uint(core::slice::from_raw_parts(ptr, bytes));
+        Ok(ty.from_u128(value))
+    }
+
+    pub(crate) unsafe fn read_struct(
+        &self,
+        addr: u64,
+        ty: &StructType,
+        endianness: Endianness,
+    ) -> Result<StructTypeImmed, Trap> {
+        let bytes = ty.size();
+        let ptr = self
+            .translate(addr, bytes)
+            .ok_or(Trap::GuestMemoryFault(addr))?;
+
+        Ok(ty.decode(core::slice::from_raw_parts(ptr, bytes), endianness))
+    }
+
+    pub(crate) unsafe fn write_struct(
+        &self,
+        addr: u64,
+        value: &StructTypeImmed,
+        endianness: Endianness,
+    ) -> Result<(), Trap> {
+        let buf = value.encode(endianness);
+        let ptr = self
+            .translate(addr, buf.len())
+            .ok_or(Trap::GuestMemoryFault(addr))?;
+
+        core::ptr::copy_nonoverlapping(buf.as_ptr(), ptr, buf.len());
+        Ok(())
+    }
+
+    /// Maps an atomic access of `size` bits at guest address `addr` to a
+    /// host pointer. Atomic accesses must be at most 64 bits and naturally
+    /// aligned.
+    fn translate_atomic(&self, addr: u64, size: u8) -> Result<(*mut u8, IntType), Trap> {
+        let ty = Self::access_type(size)
+            .filter(|_| size <= 64)
+            .ok_or(Trap::InvalidAccessSize(size))?;
+        let bytes = size as usize / 8;
+        let ptr = self
+            .translate(addr, bytes)
+            .ok_or(Trap::GuestMemoryFault(addr))?;
+        if ptr as usize % bytes != 0 {
+            return Err(Trap::MisalignedAtomic(addr));
+        }
+
+        Ok((ptr, ty))
+    }
+
+    /// Atomically reads a value from guest address `addr`
+    pub(crate) unsafe fn load_atomic(
+        &self,
+        addr: u64,
+        access: AtomicAccess,
+    ) -> Result<IntImmed, Trap> {
+        let (ptr, ty) = self.translate_atomic(addr, access.size)?;
+        let value = with_atomic!(ptr, ty, access.endianness, |atomic, Int, order| order(
+            atomic.load(access.ordering.load())
+        ));
+        Ok(ty.from_u128(value as u128))
+    }
+
+    /// Applies `op` with `value` to the value at guest address `addr`
+    /// in a single atomic step, returning the previous value
+    pub(crate) unsafe fn rmw_atomic(
+        &self,
+        addr: u64,
+        op: AtomicOp,
+        value: u64,
+        access: AtomicAccess,
+    ) -> Result<IntImmed, Trap> {
+        let (ptr, ty) = self.translate_atomic(addr, access.size)?;
+        let ordering = access.ordering;
+        // Values in host byte order can be updated by the native operations
+        let native = ty == IntType::I8 || access.endianness == Endianness::host();
+        let old = with_atomic!(ptr, ty, access.endianness, |atomic, Int, order| {
+            let value = value as Int;
+            if native {
+                match op {
+                    AtomicOp::Add => atomic.fetch_add(value, ordering.rmw()),
+                    AtomicOp::And => atomic.fetch_and(value, ordering.rmw()),
+                    AtomicOp::Or => atomic.fetch_or(value, ordering.rmw()),
+                    AtomicOp::Xor => atomic.fetch_xor(value, ordering.rmw()),
+                    AtomicOp::Exchange => atomic.swap(value, ordering.rmw()),
+                }
+            } else {
+                let update = |old: Int| {
+                    let old = order(old);
+                    let new = match op {
+                        AtomicOp::Add => old.wrapping_add(value),
+                        AtomicOp::And => old & value,
+                        AtomicOp::Or => old | value,
+                        AtomicOp::Xor => old ^ value,
+                        AtomicOp::Exchange => value,
+                    };
+                    Some(order(new))
+                };
+                // The update always succeeds
+                let (Ok(old) | Err(old)) =
+                    atomic.fetch_update(ordering.rmw(), ordering.load(), update);
+                order(old)
+            }
+        });
+        Ok(ty.from_u128(old as u128))
+    }
+
+    /// Atomically replaces the value at guest address `addr` with `new`
+    /// if it is `expected`, returning the value it held
+    pub(crate) unsafe fn compare_exchange(
+        &self,
+        addr: u64,
+        expected: u64,
+        new: u64,
+        access: AtomicAccess,
+    ) -> Result<IntImmed, Trap> {
+        let (ptr, ty) = self.translate_atomic(addr, access.size)?;
+        let ordering = access.ordering;
+        let old = with_atomic!(ptr, ty, access.endianness, |atomic, Int, order| {
+            let (Ok(old) | Err(old)) = atomic.compare_exchange(
+                order(expected as Int),
+                order(new as Int),
+                ordering.rmw(),
+                ordering.load(),
+            );
+            order(old)
+        });
+        Ok(ty.from_u128(old as u128))
+    }
+
+    /// Writes the low `size` bits of `value` to guest address `addr`
+    pub(crate) unsafe fn write(
+        &self,
+        addr: u64,
+        value: IntImmed,
+        size: u8,
+        endianness: Endianness,
+    ) -> Result<(), Trap> {
+        Self::access_type(size).ok_or(Trap::InvalidAccessSize(size))?;
+        let bytes = size as usize / 8;
+        let ptr = self
+            .translate(addr, bytes)
+            .ok_or(Trap::GuestMemoryFault(addr))?;
+
+        endianness.write_uint(value.to_u128(), core::slice::from_raw_parts_mut(ptr, bytes));
+        Ok(())
+    }
+
+    /// Orders guest memory accesses on either side of the fence.
+    /// A relaxed fence has no effect.
+    pub(crate) fn fence(ordering: MemoryOrdering) {
+        if ordering != MemoryOrdering::Relaxed {
+            atomic::fence(ordering.rmw());
+        }
+    }
+}
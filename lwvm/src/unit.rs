@@ -1,12 +1,35 @@
 use crate::block::{BasicBlock, InstructionStream};
-use crate::ir::types::BlockLabel;
+use crate::ir::types::{BlockLabel, Endianness};
 use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-#[derive(Default, Clone)]
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(0);
+
+fn next_revision() -> u64 {
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Clone)]
 pub struct TranslationUnit {
     pub(crate) labels: BTreeMap<BlockLabel, usize>,
     pub(crate) blocks: Vec<BasicBlock>,
     pub(crate) entrypoint: Option<usize>,
+    pub(crate) endianness: Endianness,
+    /// Identifies the contents of the unit. It changes on every
+    /// modification, and is shared only by clones of the same contents.
+    pub(crate) revision: u64,
+}
+
+impl Default for TranslationUnit {
+    fn default() -> Self {
+        Self {
+            labels: BTreeMap::new(),
+            blocks: Vec::new(),
+            entrypoint: None,
+            endianness: Endianness::default(),
+            revision: next_revision(),
+        }
+    }
 }
 
 impl TranslationUnit {
@@ -16,9 +39,11 @@ impl TranslationUnit {
                 "Block is not terminated (All basic blocks must end with a branch or exit)",
             ));
         }
+        block.check_struct_types()?;
 
         self.labels.insert(label, self.blocks.len());
         self.blocks.push(block);
+        self.revision = next_revision();
 
         Ok(())
     }
@@ -29,7 +54,16 @@ impl TranslationUnit {
             .get(&label)
             .ok_or(format!("No such block {} to make entrypiont", label))?;
         self.entrypoint = Some(*entry_idx);
+        self.revision = next_revision();
 
         Ok(())
     }
+
+    /// Sets the byte order of guest memory accesses in the unit that do not
+    /// specify one, which is little-endian by default. Struct and atomic
+    /// accesses always use it.
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
+        self.revision = next_revision();
+    }
 }